@@ -0,0 +1,266 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+use std::{
+    fmt::Write as _,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use ar_drivers::{
+    any_glasses,
+    stationary::{MotionState, StationaryDetector},
+    Error, GlassesEvent,
+};
+use clap::Parser;
+
+/// Characterize the IMU noise of the glasses with Allan deviation.
+/// The glasses have to lie perfectly still (e.g. on a desk) during the whole recording.
+#[derive(clap::Parser, Debug)]
+struct CliArgs {
+    /// Length of the recording in seconds. The bias instability and especially the rate
+    /// random walk need long recordings (at least an hour) to show up.
+    #[clap(long, short, default_value_t = 1800)]
+    duration: u64,
+
+    /// Write the Allan deviation curves here
+    #[clap(long, short, default_value = "allan_deviation.csv")]
+    output: PathBuf,
+}
+
+const AXES: [&str; 6] = ["gyro_x", "gyro_y", "gyro_z", "acc_x", "acc_y", "acc_z"];
+
+fn main() {
+    let args = CliArgs::parse();
+    let mut glasses = any_glasses().unwrap();
+    let serial = glasses.serial().unwrap();
+    println!("Got glasses: {}, serial={}", glasses.name(), serial);
+    println!(
+        "Recording for {} seconds, do not touch the glasses.",
+        args.duration
+    );
+
+    let started = Instant::now();
+    let mut last_print = Instant::now();
+    let mut detector = StationaryDetector::default();
+    let mut moved = false;
+    let mut samples: Vec<[f32; 6]> = Vec::new();
+    let mut timestamps = (None, 0);
+    while started.elapsed() < Duration::from_secs(args.duration) {
+        let event = match glasses.read_event() {
+            Ok(event) => event,
+            // Occasional timeouts and corrupted packets should not end a long recording
+            Err(Error::PacketTimeout | Error::ChecksumMismatch) => continue,
+            Err(e) => {
+                println!(
+                    "Recording stopped after {}s: {}",
+                    started.elapsed().as_secs(),
+                    e
+                );
+                break;
+            }
+        };
+        if detector.update(&event) == Some(MotionState::Moving) {
+            moved = true;
+        }
+        if let GlassesEvent::AccGyro {
+            accelerometer,
+            gyroscope,
+            timestamp,
+        } = event
+        {
+            samples.push([
+                gyroscope.x,
+                gyroscope.y,
+                gyroscope.z,
+                accelerometer.x,
+                accelerometer.y,
+                accelerometer.z,
+            ]);
+            timestamps = (timestamps.0.or(Some(timestamp)), timestamp);
+        }
+        if last_print.elapsed() > Duration::from_secs(10) {
+            println!(
+                "{:5}s / {}s, {} samples",
+                started.elapsed().as_secs(),
+                args.duration,
+                samples.len()
+            );
+            last_print = Instant::now();
+        }
+    }
+    if moved {
+        println!("WARNING: the glasses moved during the recording, the results are not reliable");
+    }
+    let (Some(first), last) = timestamps else {
+        println!("No IMU samples were received");
+        return;
+    };
+    if samples.len() < 100 || last <= first {
+        println!("Not enough samples");
+        return;
+    }
+    // Sample period, in seconds
+    let tau0 = (last - first) as f64 / 1_000_000.0 / (samples.len() - 1) as f64;
+    println!(
+        "Got {} samples at {:.1}Hz, computing Allan deviation...",
+        samples.len(),
+        1.0 / tau0
+    );
+
+    let cluster_sizes = cluster_sizes(samples.len());
+    let curves: Vec<Vec<f64>> = (0..AXES.len())
+        .map(|axis| {
+            let values: Vec<f64> = samples.iter().map(|s| s[axis] as f64).collect();
+            allan_deviation(&values, &cluster_sizes)
+        })
+        .collect();
+    let taus: Vec<f64> = cluster_sizes.iter().map(|&m| m as f64 * tau0).collect();
+
+    let mut csv = format!("# {}, serial={}\ntau", glasses.name(), serial);
+    for axis in AXES {
+        write!(csv, ",{axis}").unwrap();
+    }
+    csv.push('\n');
+    for (i, tau) in taus.iter().enumerate() {
+        write!(csv, "{tau}").unwrap();
+        for curve in &curves {
+            write!(csv, ",{}", curve[i]).unwrap();
+        }
+        csv.push('\n');
+    }
+    std::fs::write(&args.output, csv).unwrap();
+    println!(
+        "Allan deviation curves written to {}",
+        args.output.display()
+    );
+
+    println!();
+    println!("Noise parameters of {}:", glasses.name());
+    println!(
+        "{:<8} {:>14} {:>16} {:>14}",
+        "axis", "random walk", "bias instability", "rate rnd walk"
+    );
+    let mut parameters = Vec::new();
+    for (axis, curve) in AXES.iter().zip(&curves) {
+        let noise = NoiseParameters::from_curve(&taus, curve);
+        let format = |value: Option<f64>| value.map_or("-".into(), |v| format!("{v:.3e}"));
+        println!(
+            "{:<8} {:>14} {:>16} {:>14}",
+            axis,
+            format(noise.random_walk),
+            format(noise.bias_instability),
+            format(noise.rate_random_walk)
+        );
+        parameters.push(noise);
+    }
+    println!(
+        "Units: gyro: rad/sqrt(s), rad/s, rad/s/sqrt(s); acc: m/s/sqrt(s), m/s^2, m/s^2/sqrt(s)"
+    );
+
+    let average = |values: &mut dyn Iterator<Item = Option<f64>>| {
+        let values: Vec<f64> = values.flatten().collect();
+        (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+    };
+    let gyro = &parameters[..3];
+    if let Some(arw) = average(&mut gyro.iter().map(|p| p.random_walk)) {
+        println!();
+        println!(
+            "Gyro angle random walk: {:.3} deg/sqrt(h)",
+            arw.to_degrees() * 60.0
+        );
+        println!("Suggested EskfConfig:");
+        println!("    gyro_noise: {:.2e},", arw / tau0.sqrt());
+        if let Some(rrw) = average(&mut gyro.iter().map(|p| p.rate_random_walk)) {
+            println!("    gyro_bias_walk: {:.2e},", rrw);
+        }
+    }
+    if let Some(bias_instability) = average(&mut gyro.iter().map(|p| p.bias_instability)) {
+        println!(
+            "Gyro bias instability: {:.3} deg/h",
+            bias_instability.to_degrees() * 3600.0
+        );
+    }
+}
+
+/// Logarithmically spaced cluster sizes (about 10 per decade), with at least
+/// 9 clusters of the largest size
+fn cluster_sizes(sample_count: usize) -> Vec<usize> {
+    let mut result: Vec<usize> = Vec::new();
+    let mut size = 1.0f64;
+    while (size as usize) <= sample_count / 9 {
+        if result.last() != Some(&(size as usize)) {
+            result.push(size as usize);
+        }
+        size *= 10f64.powf(0.1);
+    }
+    result
+}
+
+/// Overlapping Allan deviation, for each cluster size
+fn allan_deviation(values: &[f64], cluster_sizes: &[usize]) -> Vec<f64> {
+    // Cumulative sums, so that the average of any cluster is a single subtraction
+    let mut sums = Vec::with_capacity(values.len() + 1);
+    sums.push(0.0);
+    for value in values {
+        sums.push(sums.last().unwrap() + value);
+    }
+    cluster_sizes
+        .iter()
+        .map(|&m| {
+            let count = values.len() + 1 - 2 * m;
+            let sum: f64 = (0..count)
+                .map(|k| {
+                    let difference = (sums[k + 2 * m] - 2.0 * sums[k + m] + sums[k]) / m as f64;
+                    difference * difference
+                })
+                .sum();
+            (sum / (2.0 * count as f64)).sqrt()
+        })
+        .collect()
+}
+
+/// The standard noise terms, read from the Allan deviation curve
+struct NoiseParameters {
+    /// Angle (or velocity) random walk: the -1/2 slope line at tau = 1s
+    random_walk: Option<f64>,
+    /// Bias instability: the minimum of the curve, divided by sqrt(2 ln(2) / pi)
+    bias_instability: Option<f64>,
+    /// Rate random walk: the +1/2 slope line at tau = 3s
+    rate_random_walk: Option<f64>,
+}
+
+impl NoiseParameters {
+    /// Largest difference from the ideal slope that is still accepted
+    const SLOPE_TOLERANCE: f64 = 0.1;
+
+    fn from_curve(taus: &[f64], deviations: &[f64]) -> Self {
+        let slopes: Vec<(usize, f64)> = (0..taus.len().saturating_sub(1))
+            .map(|i| {
+                let slope = (deviations[i + 1] / deviations[i]).ln() / (taus[i + 1] / taus[i]).ln();
+                (i, slope)
+            })
+            .filter(|(_, slope)| slope.is_finite())
+            .collect();
+        // Point of the curve where the slope is the closest to `slope`, if close enough
+        let closest = |slope: f64| {
+            slopes
+                .iter()
+                .min_by(|a, b| (a.1 - slope).abs().total_cmp(&(b.1 - slope).abs()))
+                .filter(|(_, s)| (s - slope).abs() < Self::SLOPE_TOLERANCE)
+                .map(|&(i, _)| i)
+        };
+        let bias_instability = deviations
+            .iter()
+            .copied()
+            .filter(|d| d.is_finite())
+            .min_by(f64::total_cmp)
+            .map(|minimum| minimum / (2.0 * 2f64.ln() / std::f64::consts::PI).sqrt());
+        Self {
+            random_walk: closest(-0.5).map(|i| deviations[i] * taus[i].sqrt()),
+            bias_instability,
+            rate_random_walk: closest(0.5).map(|i| deviations[i] * (3.0 / taus[i]).sqrt()),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use ar_drivers::{
+    mag_calibration::MagCalibrator, user_calibration::UserCalibration, GlassesSelector,
+};
+use clap::Parser;
+
+/// Calibrate the magnetometer of the glasses (hard and soft iron)
+#[derive(clap::Parser, Debug)]
+struct CliArgs {
+    /// Save the calibration here instead of the user calibration of the glasses
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// Stop collecting samples after this many seconds, even if the coverage is low
+    #[clap(long, short, default_value_t = 120)]
+    timeout: u64,
+
+    /// Only print the results, do not save them
+    #[clap(long, short)]
+    dry_run: bool,
+}
+
+/// Stop collecting at this coverage
+const TARGET_COVERAGE: f32 = 0.95;
+
+fn main() {
+    let args = CliArgs::parse();
+    // The raw readings are needed, without the previous calibration
+    let mut glasses = GlassesSelector::new()
+        .user_calibration(false)
+        .open()
+        .unwrap();
+    let serial = glasses.serial().unwrap();
+    println!("Got glasses, serial={}", serial);
+    if !glasses.capabilities().magnetometer {
+        println!("{} does not have a magnetometer", glasses.name());
+        return;
+    }
+
+    println!("Slowly rotate the glasses in every direction: turn them around, upside down,");
+    println!("and roll them on every side. Keep away from metal objects and electronics.");
+    let start = Instant::now();
+    let mut last_print = Instant::now();
+    let mut calibrator = MagCalibrator::new();
+    while calibrator.coverage() < TARGET_COVERAGE
+        && start.elapsed() < Duration::from_secs(args.timeout)
+    {
+        calibrator.update(&glasses.read_event().unwrap());
+        if last_print.elapsed() > Duration::from_secs(1) {
+            println!(
+                "Samples: {:5}, coverage: {:3.0}%",
+                calibrator.sample_count(),
+                calibrator.coverage() * 100.0
+            );
+            last_print = Instant::now();
+        }
+    }
+
+    let calibration = match calibrator.fit() {
+        Ok(calibration) => calibration,
+        Err(e) => {
+            println!("Calibration failed: {}", e);
+            return;
+        }
+    };
+    println!("Hard iron offset:{}", calibration.hard_iron);
+    println!("Soft iron matrix:{}", calibration.soft_iron);
+    println!("Field strength: {:.2}", calibration.field_strength);
+    println!(
+        "Residual: {:.2}%, coverage: {:.0}%",
+        calibration.residual * 100.0,
+        calibration.coverage * 100.0
+    );
+    if calibration.residual > 0.02 || calibration.coverage < TARGET_COVERAGE {
+        println!("The fit is poor, consider running the calibration again.");
+    }
+
+    if args.dry_run {
+        return;
+    }
+    if let Some(path) = args.output {
+        calibration.save(&path).unwrap();
+        println!("Saved to {}", path.display());
+        return;
+    }
+    let mut user_calibration = UserCalibration::load_for(&serial)
+        .unwrap()
+        .unwrap_or_default();
+    user_calibration.magnetometer = Some(calibration);
+    match user_calibration.save_for(&serial) {
+        Ok(()) => println!("Saved to the user calibration of {}", serial),
+        Err(e) => println!("Could not save the calibration ({}), use --output", e),
+    }
+}
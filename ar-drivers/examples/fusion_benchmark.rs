@@ -0,0 +1,76 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+use std::time::Duration;
+
+use ar_drivers::fusion_benchmark::{all_algorithms, run_all, BenchmarkConfig, Scenario};
+use clap::Parser;
+use nalgebra::Vector3;
+
+/// Compare the sensor fusion algorithms on simulated head motions
+#[derive(clap::Parser, Debug)]
+struct CliArgs {
+    /// Simulated time of each run, in seconds
+    #[clap(long, short, default_value_t = 60)]
+    duration: u64,
+
+    /// Accelerometer and gyroscope sample rate, in Hz
+    #[clap(long, default_value_t = 1000.0)]
+    sample_rate: f32,
+
+    /// Standard deviation of the gyroscope noise, in rad/sec
+    #[clap(long, default_value_t = 0.003)]
+    gyro_noise: f32,
+
+    /// Gyroscope bias (x,y,z in the RUB frame), in rad/sec
+    #[clap(long, num_args = 3, value_delimiter = ',', default_values_t = [0.002, -0.001, 0.0015])]
+    gyro_bias: Vec<f32>,
+
+    /// Standard deviation of the accelerometer noise, in m/s^2
+    #[clap(long, default_value_t = 0.02)]
+    accelerometer_noise: f32,
+
+    /// Accelerometer bias (x,y,z in the RUB frame), in m/s^2
+    #[clap(long, num_args = 3, value_delimiter = ',', default_values_t = [0.0, 0.0, 0.0])]
+    accelerometer_bias: Vec<f32>,
+
+    /// Seed of the noise generator
+    #[clap(long, default_value_t = 0x5eed)]
+    seed: u64,
+}
+
+fn main() {
+    let args = CliArgs::parse();
+    let mut config = BenchmarkConfig {
+        duration: Duration::from_secs(args.duration),
+        ..Default::default()
+    };
+    config.simulation.sample_rate = args.sample_rate;
+    config.simulation.gyro_noise = args.gyro_noise;
+    config.simulation.gyro_bias = Vector3::from_column_slice(&args.gyro_bias);
+    config.simulation.accelerometer_noise = args.accelerometer_noise;
+    config.simulation.accelerometer_bias = Vector3::from_column_slice(&args.accelerometer_bias);
+    config.simulation.seed = args.seed;
+
+    println!(
+        "{:<12} {:<14} {:>9} {:>9} {:>9} {:>11} {:>12}",
+        "scenario", "algorithm", "RMS °", "tilt °", "max °", "drift °/min", "convergence"
+    );
+    for result in run_all(&all_algorithms(), &Scenario::all(), &config) {
+        let convergence = match result.convergence_time {
+            Some(time) => format!("{:.2}s", time.as_secs_f32()),
+            None => "never".into(),
+        };
+        println!(
+            "{:<12} {:<14} {:>9.3} {:>9.3} {:>9.3} {:>11.3} {:>12}",
+            result.scenario,
+            result.algorithm.to_string(),
+            result.rms_error.to_degrees(),
+            result.rms_tilt_error.to_degrees(),
+            result.max_error.to_degrees(),
+            result.drift_per_minute.to_degrees(),
+            convergence
+        );
+    }
+}
@@ -0,0 +1,23 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+use ar_drivers::list_glasses;
+
+fn main() {
+    let descriptors = list_glasses();
+    if descriptors.is_empty() {
+        println!("No glasses found");
+    }
+    for descriptor in descriptors {
+        println!(
+            "{} ({:04x}:{:04x}) via {:?} at {}, serial={}",
+            descriptor.model.name(),
+            descriptor.vid,
+            descriptor.pid,
+            descriptor.transport,
+            descriptor.bus_path,
+            descriptor.serial.as_deref().unwrap_or("unknown"),
+        );
+    }
+}
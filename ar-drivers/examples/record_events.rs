@@ -0,0 +1,56 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use ar_drivers::{
+    any_glasses,
+    recording::{RecordingGlasses, ReplayGlasses, ReplaySpeed},
+    ARGlasses,
+};
+use clap::Parser;
+
+/// Record the sensor events of the connected glasses into a file, or
+/// play back such a recording
+#[derive(clap::Parser, Debug)]
+struct CliArgs {
+    /// Recording file
+    path: PathBuf,
+
+    /// Length of the recording in seconds
+    #[arg(long, short, default_value_t = 30)]
+    seconds: u64,
+
+    /// Print the events of an existing recording instead of recording
+    #[clap(long, short)]
+    replay: bool,
+}
+
+fn main() {
+    let args = CliArgs::parse();
+    if args.replay {
+        let mut glasses = ReplayGlasses::open(&args.path, ReplaySpeed::AsFastAsPossible).unwrap();
+        println!("Recording header: {:#?}", glasses.header());
+        while let Ok(event) = glasses.read_event() {
+            println!("Event: {:?}", event);
+        }
+        return;
+    }
+
+    let glasses = any_glasses().unwrap();
+    let mut glasses = RecordingGlasses::create(glasses, &args.path).unwrap();
+    println!("Got glasses, serial={}", glasses.serial().unwrap());
+
+    let started = Instant::now();
+    let mut n = 0;
+    while started.elapsed() < Duration::from_secs(args.seconds) {
+        glasses.read_event().unwrap();
+        n += 1;
+    }
+    glasses.flush().unwrap();
+    println!("Recorded {} events into {}", n, args.path.display());
+}
@@ -2,23 +2,66 @@
 // This file is part of ar-drivers-rs
 // Licensed under the MIT license. See LICENSE file in the project root for details.
 
-use ar_drivers::any_fusion;
+use ar_drivers::{any_fusion_with, eskf::EskfConfig, madgwick::MadgwickConfig, FusionAlgorithm};
+use clap::Parser;
+
+/// Print the attitude of the glasses
+#[derive(clap::Parser, Debug)]
+struct CliArgs {
+    /// Sensor fusion algorithm
+    #[arg(value_enum, default_value_t = CliAlgorithm::Naive)]
+    algorithm: CliAlgorithm,
+
+    /// Gain of the Madgwick filter, in rad/s
+    #[clap(long, short, default_value_t = MadgwickConfig::default().beta)]
+    beta: f32,
+
+    /// Correct the yaw with the magnetometer (Madgwick and ESKF only)
+    #[clap(long, short)]
+    magnetometer: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone)]
+enum CliAlgorithm {
+    /// Simple complementary filter
+    Naive,
+    /// Madgwick's gradient descent filter
+    Madgwick,
+    /// Error-state Kalman filter, also estimating the gyro bias
+    Eskf,
+}
 
 fn main() {
-    let mut fusion = any_fusion().unwrap(); // Declare conn as mutable
+    let args = CliArgs::parse();
+    let algorithm = match args.algorithm {
+        CliAlgorithm::Naive => FusionAlgorithm::NaiveCF,
+        CliAlgorithm::Madgwick => FusionAlgorithm::Madgwick(MadgwickConfig {
+            beta: args.beta,
+            use_magnetometer: args.magnetometer,
+        }),
+        CliAlgorithm::Eskf => FusionAlgorithm::Eskf(EskfConfig {
+            use_magnetometer: args.magnetometer,
+            ..Default::default()
+        }),
+    };
+    let mut fusion = any_fusion_with(algorithm).unwrap(); // Declare conn as mutable
 
     let serial = fusion.glasses().serial().unwrap();
     println!("Got glasses, serial={}", serial);
 
     println!("");
     loop {
-        fusion.update();
+        if let Err(e) = fusion.update() {
+            println!("Error reading the glasses: {e}");
+            break;
+        }
         let quaternion = fusion.attitude_quaternion();
         let frd = fusion.attitude_frd_deg();
         let inconsistency = fusion.inconsistency();
 
         println!("quaternion:\t{:10.7}", quaternion);
         println!("euler:\t{:10.7}", frd.transpose());
+        println!("at display:\t{:10.7}", fusion.predict_display());
 
         println!("inconsistency:\t{:10.7}", inconsistency);
     }
@@ -2,7 +2,9 @@
 // This file is part of ar-drivers-rs
 // Licensed under the MIT license. See LICENSE file in the project root for details.
 
-use ar_drivers::{any_glasses, DisplayMode};
+use std::time::Duration;
+
+use ar_drivers::{any_glasses, split::split_glasses, DisplayLayout, DisplayMode};
 use clap::Parser;
 
 /// Set AR glasses display mode
@@ -12,6 +14,14 @@ struct CliArgs {
     #[arg(value_enum, default_value_t = CliDisplayMode::Stereo)]
     mode: CliDisplayMode,
 
+    /// Select the mode with this exact refresh rate (in Hz) and the layout of `mode`
+    #[clap(long, short)]
+    refresh_rate: Option<u32>,
+
+    /// List the display modes supported by the glasses, and exit
+    #[clap(long, short)]
+    list: bool,
+
     /// Keep running after setting the mode.
     /// This is needed for some AR glasses to stay on in SBS mode.
     #[clap(long, short)]
@@ -44,24 +54,70 @@ fn main() {
     let args = CliArgs::parse();
     let mut glasses = any_glasses().unwrap();
     println!("Got glasses, serial={}", glasses.serial().unwrap());
-    println!(
-        "Display mode was: {:?}",
-        glasses.get_display_mode().unwrap()
-    );
+    match glasses.get_display_mode_info() {
+        Ok(mode) => println!("Display mode was: {}", mode),
+        Err(_) => println!(
+            "Display mode was: {:?}",
+            glasses.get_display_mode().unwrap()
+        ),
+    }
+    if args.list {
+        println!("Supported display modes:");
+        for mode in glasses.supported_display_modes() {
+            println!("  {}", mode);
+        }
+        return;
+    }
 
-    glasses
-        .set_display_mode(match args.mode {
-            CliDisplayMode::SameOnBoth => DisplayMode::SameOnBoth,
-            CliDisplayMode::Stereo => DisplayMode::Stereo,
-            CliDisplayMode::HalfSBS => DisplayMode::HalfSBS,
-            CliDisplayMode::HighRefreshRate => DisplayMode::HighRefreshRate,
-            CliDisplayMode::HighRefreshRateSBS => DisplayMode::HighRefreshRateSBS,
-        })
-        .unwrap();
+    let display_mode = match args.mode {
+        CliDisplayMode::SameOnBoth => DisplayMode::SameOnBoth,
+        CliDisplayMode::Stereo => DisplayMode::Stereo,
+        CliDisplayMode::HalfSBS => DisplayMode::HalfSBS,
+        CliDisplayMode::HighRefreshRate => DisplayMode::HighRefreshRate,
+        CliDisplayMode::HighRefreshRateSBS => DisplayMode::HighRefreshRateSBS,
+    };
+    let exact_mode = args.refresh_rate.map(|refresh_rate| {
+        let layout = match display_mode {
+            DisplayMode::SameOnBoth | DisplayMode::HighRefreshRate => DisplayLayout::Mirror,
+            DisplayMode::Stereo | DisplayMode::HighRefreshRateSBS => DisplayLayout::SideBySide,
+            DisplayMode::HalfSBS => DisplayLayout::HalfSideBySide,
+        };
+        glasses
+            .supported_display_modes()
+            .into_iter()
+            .find(|mode| mode.layout == layout && mode.refresh_rate == refresh_rate)
+            .expect("No supported display mode with this refresh rate")
+    });
+    match exact_mode {
+        Some(exact_mode) => glasses.set_display_mode_info(exact_mode).unwrap(),
+        None => glasses.set_display_mode(display_mode).unwrap(),
+    }
 
     if args.keep_running {
+        // Keep reading events on a separate thread, while periodically checking
+        // that the glasses are still in the requested mode.
+        let (mut reader, controller) = split_glasses(glasses);
+        std::thread::spawn(move || loop {
+            reader.read_event().unwrap();
+        });
         loop {
-            glasses.read_event().unwrap();
+            std::thread::sleep(Duration::from_secs(1));
+            if let Some(exact_mode) = exact_mode {
+                let current_mode = controller.get_display_mode_info().unwrap();
+                if current_mode != exact_mode {
+                    println!("Display mode changed to {}, setting it back", current_mode);
+                    controller.set_display_mode_info(exact_mode).unwrap();
+                }
+                continue;
+            }
+            let current_mode = controller.get_display_mode().unwrap();
+            if current_mode != display_mode {
+                println!(
+                    "Display mode changed to {:?}, setting it back",
+                    current_mode
+                );
+                controller.set_display_mode(display_mode).unwrap();
+            }
         }
     }
 }
@@ -0,0 +1,352 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Mapping device timestamps to the host's monotonic clock. See [`ClockSync`] and
+//! [`SyncedGlasses`]
+//!
+//! Every driver has its own clock: some report the device's own time, others count
+//! samples or take the time of the read. None of them can be directly compared to
+//! [`Instant`], e.g. for matching sensor data with rendered frames.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::{util::forward_glasses, ARGlasses, GlassesEvent, Result};
+
+/// Settings of [`ClockSync`]
+#[derive(Debug, Clone)]
+pub struct ClockSyncConfig {
+    /// The offset and the drift are fitted on the samples of this long period
+    /// (in device time). Longer windows give a more stable estimate, but follow
+    /// temperature dependent drift changes slower.
+    pub window: Duration,
+    /// Only the sample with the lowest latency is kept from each interval of this length
+    pub interval: Duration,
+    /// A forward jump of the device time this much larger than the elapsed host time,
+    /// or a backward jump larger than this, is considered a reset of the device clock
+    /// (e.g. because the glasses rebooted). Smaller backward steps are treated as
+    /// out of order samples.
+    pub reset_threshold: Duration,
+}
+
+impl Default for ClockSyncConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(60),
+            interval: Duration::from_secs(1),
+            reset_threshold: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Estimates the offset and the drift between the device clock and the host's
+/// monotonic clock, from the time the samples were received.
+///
+/// The transfer latency is always positive, and varies from sample to sample, so the
+/// samples with the lowest latency are the most accurate: the model is a line fitted
+/// on the lowest latency sample of each [`ClockSyncConfig::interval`]. The estimated
+/// host times are never later than the time of reception, and never decrease.
+#[derive(Debug, Clone)]
+pub struct ClockSync {
+    config: ClockSyncConfig,
+    /// Host time is measured from here
+    epoch: Instant,
+    state: Option<SyncState>,
+    resets: u32,
+}
+
+#[derive(Debug, Clone)]
+struct SyncState {
+    /// Device time is measured from here, to keep the precision of the f64 math
+    device_origin: u64,
+    last_device: u64,
+    /// Host time of the last reception, in usecs since the epoch
+    last_received: f64,
+    last_estimate: f64,
+    /// Lowest (host - device) offset of each interval: (device time, offset), in usecs
+    minima: VecDeque<(f64, f64)>,
+    /// The model: host = device + offset + drift * device
+    offset: f64,
+    drift: f64,
+}
+
+impl ClockSync {
+    /// Create an estimator without any samples
+    pub fn new(config: ClockSyncConfig) -> Self {
+        Self {
+            config,
+            epoch: Instant::now(),
+            state: None,
+            resets: 0,
+        }
+    }
+
+    /// The settings used by the estimator
+    pub fn config(&self) -> &ClockSyncConfig {
+        &self.config
+    }
+
+    /// Process a sample taken at `device_timestamp` (in microseconds), and received
+    /// at `received`. Returns the estimated host time of the sample.
+    pub fn update(&mut self, device_timestamp: u64, received: Instant) -> Instant {
+        let host = self.to_micros(received);
+        let reset_threshold = self.config.reset_threshold.as_micros() as f64;
+        let Some(state) = &mut self.state else {
+            self.state = Some(SyncState::new(device_timestamp, host));
+            return received;
+        };
+
+        let device_elapsed = device_timestamp as f64 - state.last_device as f64;
+        let host_elapsed = host - state.last_received;
+        if device_elapsed < -reset_threshold || device_elapsed > host_elapsed + reset_threshold {
+            self.resets += 1;
+            self.state = Some(SyncState::new(device_timestamp, host));
+            return received;
+        }
+        if device_elapsed < 0.0 {
+            // Out of order sample: not used for the fit, and the estimate is clamped
+            // to keep the host times monotonic
+            let last_estimate = state.last_estimate;
+            return self.to_instant(last_estimate);
+        }
+
+        state.add_sample(device_timestamp, host, &self.config);
+        let estimate = state
+            .estimate(device_timestamp)
+            .min(host)
+            .max(state.last_estimate);
+        state.last_device = device_timestamp;
+        state.last_received = host;
+        state.last_estimate = estimate;
+        self.to_instant(estimate)
+    }
+
+    /// Estimated host time of a device timestamp (in microseconds), using the current
+    /// model. `None` before the first sample.
+    pub fn host_time(&self, device_timestamp: u64) -> Option<Instant> {
+        let state = self.state.as_ref()?;
+        Some(self.to_instant(state.estimate(device_timestamp)))
+    }
+
+    /// Relative rate difference of the clocks. E.g. 1e-5 means that the device clock
+    /// is 10ppm slower than the host's.
+    pub fn drift(&self) -> f64 {
+        self.state.as_ref().map_or(0.0, |state| state.drift)
+    }
+
+    /// Number of device clock resets detected so far
+    pub fn resets(&self) -> u32 {
+        self.resets
+    }
+
+    /// Forget every sample, e.g. because the glasses were reconnected
+    pub fn reset(&mut self) {
+        self.state = None;
+    }
+
+    fn to_micros(&self, instant: Instant) -> f64 {
+        match instant.checked_duration_since(self.epoch) {
+            Some(since) => since.as_secs_f64() * 1_000_000.0,
+            None => -(self.epoch - instant).as_secs_f64() * 1_000_000.0,
+        }
+    }
+
+    fn to_instant(&self, micros: f64) -> Instant {
+        let duration = Duration::from_secs_f64(micros.abs() / 1_000_000.0);
+        if micros >= 0.0 {
+            self.epoch + duration
+        } else {
+            self.epoch - duration
+        }
+    }
+}
+
+impl Default for ClockSync {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl SyncState {
+    fn new(device_timestamp: u64, host: f64) -> Self {
+        Self {
+            device_origin: device_timestamp,
+            last_device: device_timestamp,
+            last_received: host,
+            last_estimate: host,
+            minima: VecDeque::from([(0.0, host)]),
+            offset: host,
+            drift: 0.0,
+        }
+    }
+
+    fn add_sample(&mut self, device_timestamp: u64, host: f64, config: &ClockSyncConfig) {
+        let device = (device_timestamp - self.device_origin) as f64;
+        let offset = host - device;
+        let interval = config.interval.as_micros().max(1) as f64;
+        match self.minima.back_mut() {
+            Some(last) if (last.0 / interval).floor() == (device / interval).floor() => {
+                if offset < last.1 {
+                    *last = (device, offset);
+                }
+            }
+            _ => self.minima.push_back((device, offset)),
+        }
+        let window = config.window.as_micros() as f64;
+        while self
+            .minima
+            .front()
+            .is_some_and(|&(oldest, _)| device - oldest > window)
+        {
+            self.minima.pop_front();
+        }
+        self.fit();
+    }
+
+    /// Least squares line on the minima. The interval in progress is left out (unless
+    /// it is the only one), as its minimum may still come from a high latency sample.
+    fn fit(&mut self) {
+        let count = (self.minima.len() - 1).max(1);
+        let minima = || self.minima.iter().take(count);
+        let n = count as f64;
+        let mean_device = minima().map(|m| m.0).sum::<f64>() / n;
+        let mean_offset = minima().map(|m| m.1).sum::<f64>() / n;
+        let (covariance, variance) =
+            minima().fold((0.0, 0.0), |(covariance, variance), &(device, offset)| {
+                let d = device - mean_device;
+                (covariance + d * (offset - mean_offset), variance + d * d)
+            });
+        // Too short for a meaningful drift estimate
+        self.drift = if count > 2 && variance > 0.0 {
+            covariance / variance
+        } else {
+            0.0
+        };
+        self.offset = mean_offset - self.drift * mean_device;
+    }
+
+    fn estimate(&self, device_timestamp: u64) -> f64 {
+        let device = device_timestamp as f64 - self.device_origin as f64;
+        device + self.offset + self.drift * device
+    }
+}
+
+/// An event, and the estimated host time it happened at
+#[derive(Debug, Clone)]
+pub struct TimedEvent {
+    /// The event itself, unchanged
+    pub event: GlassesEvent,
+    /// Estimated host time of the event, see [`ClockSync`]. Events without a device
+    /// timestamp get the time they were received.
+    pub host_time: Instant,
+}
+
+/// Glasses wrapper that keeps a [`ClockSync`] up to date with the timestamps of
+/// the events. Use [`SyncedGlasses::read_timed_event`] to get the host time of the
+/// events, or [`SyncedGlasses::clock`] to convert device timestamps later.
+pub struct SyncedGlasses {
+    glasses: Box<dyn ARGlasses>,
+    clock: ClockSync,
+}
+
+impl SyncedGlasses {
+    /// Wrap already opened glasses
+    pub fn new(glasses: Box<dyn ARGlasses>, config: ClockSyncConfig) -> Self {
+        Self {
+            glasses,
+            clock: ClockSync::new(config),
+        }
+    }
+
+    /// Get a single sensor event, with its estimated host time. Blocks.
+    pub fn read_timed_event(&mut self) -> Result<TimedEvent> {
+        let event = self.glasses.read_event()?;
+        let received = Instant::now();
+        let host_time = match event.timestamp() {
+            Some(timestamp) => self.clock.update(timestamp, received),
+            None => {
+                if let GlassesEvent::Connected | GlassesEvent::Disconnected = event {
+                    // The device clock may have been restarted
+                    self.clock.reset();
+                }
+                received
+            }
+        };
+        Ok(TimedEvent { event, host_time })
+    }
+
+    /// The clock estimator
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    /// Get the wrapped glasses back
+    pub fn into_inner(self) -> Box<dyn ARGlasses> {
+        self.glasses
+    }
+}
+
+impl ARGlasses for SyncedGlasses {
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        Ok(self.read_timed_event()?.event)
+    }
+
+    forward_glasses!(glasses);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Device clock 50ppm slow, starting at an arbitrary time, sampled at 1kHz,
+    /// received with 0.2-2.2ms latency
+    fn simulate(sync: &mut ClockSync, start: Instant, device_start: u64, secs: u64) -> f64 {
+        let mut max_error: f64 = 0.0;
+        for i in 0..secs * 1000 {
+            let true_time = Duration::from_micros(i * 1000);
+            let device_timestamp = device_start + (i as f64 * 1000.0 / (1.0 + 50e-6)) as u64;
+            let latency = Duration::from_micros(200 + (i * 7919) % 2000);
+            let estimate = sync.update(device_timestamp, start + true_time + latency);
+            let error = if estimate > start + true_time {
+                (estimate - (start + true_time)).as_secs_f64()
+            } else {
+                -(start + true_time - estimate).as_secs_f64()
+            };
+            if i > 5000 {
+                max_error = max_error.max(error.abs());
+            }
+        }
+        max_error
+    }
+
+    #[test]
+    fn test_offset_and_drift() {
+        let mut sync = ClockSync::default();
+        let start = Instant::now();
+        let max_error = simulate(&mut sync, start, 123_456_789_000, 120);
+        assert!(max_error < 0.0005, "{max_error}");
+        assert!((sync.drift() - 50e-6).abs() < 5e-6, "{}", sync.drift());
+    }
+
+    #[test]
+    fn test_reset_and_out_of_order() {
+        let mut sync = ClockSync::default();
+        let start = Instant::now();
+        simulate(&mut sync, start, 5_000_000, 10);
+
+        // Slightly out of order sample: the host time does not go backwards
+        let last = sync.update(15_000_500, start + Duration::from_millis(10_001));
+        let out_of_order = sync.update(14_999_800, start + Duration::from_millis(10_002));
+        assert_eq!(out_of_order, last);
+        assert_eq!(sync.resets(), 0);
+
+        // The device restarted its clock
+        let restart = start + Duration::from_secs(20);
+        let max_error = simulate(&mut sync, restart, 0, 10);
+        assert_eq!(sync.resets(), 1);
+        assert!(max_error < 0.0005, "{max_error}");
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Per-user settings files in a simple `key = values` text format
+
+use std::path::{Path, PathBuf};
+
+use crate::{Error, Result};
+
+/// `$XDG_CONFIG_HOME/ar-drivers/` or `~/.config/ar-drivers/` (`%APPDATA%\ar-drivers\`
+/// on Windows). `None` if none of these are set.
+pub(crate) fn config_directory() -> Option<PathBuf> {
+    let base = if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(dir) = std::env::var_os("APPDATA") {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".config")
+    };
+    Some(base.join("ar-drivers"))
+}
+
+/// `<config directory>/<prefix>_<serial>.txt`, with the serial number made safe
+/// for file names
+pub(crate) fn device_file(prefix: &str, serial: &str) -> Option<PathBuf> {
+    let serial: String = serial
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Some(config_directory()?.join(format!("{prefix}_{serial}.txt")))
+}
+
+/// Ordered `key = values` pairs. Values are separated by whitespace.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConfigFile {
+    entries: Vec<(String, String)>,
+}
+
+impl ConfigFile {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(Self {
+            entries: contents
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect(),
+        })
+    }
+
+    /// Write the file, creating the directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|(key, value)| format!("{key} = {value}\n"))
+            .collect();
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: impl ToString) {
+        let value = value.to_string();
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+
+    pub fn set_values(&mut self, key: &str, values: &[f32]) {
+        let values: Vec<_> = values.iter().map(|v| v.to_string()).collect();
+        self.set(key, values.join(" "));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Exactly `count` numbers, or `None` if the key is missing. Any other
+    /// value is an `Error::Other(error)`.
+    pub fn values(&self, key: &str, count: usize, error: &'static str) -> Result<Option<Vec<f32>>> {
+        let Some(value) = self.get(key) else {
+            return Ok(None);
+        };
+        let values = value
+            .split_whitespace()
+            .map(|v| v.parse().map_err(|_| Error::Other(error)))
+            .collect::<Result<Vec<f32>>>()?;
+        if values.len() != count {
+            return Err(Error::Other(error));
+        }
+        Ok(Some(values))
+    }
+}
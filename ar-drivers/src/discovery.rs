@@ -0,0 +1,315 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Enumerating connected glasses, and opening a specific one.
+//! See [`list_glasses`] and [`GlassesSelector`]
+
+use std::time::Duration;
+
+use crate::{ARGlasses, Error, Result};
+
+/// Model of a supported glasses device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GlassesModel {
+    /// Rokid Air
+    RokidAir,
+    /// Rokid Max
+    RokidMax,
+    /// XREAL Air (original)
+    XrealAir,
+    /// XREAL Air 2
+    XrealAir2,
+    /// XREAL Air 2 Pro
+    XrealAir2Pro,
+    /// Nreal Light
+    NrealLight,
+    /// Grawoow G530 (a.k.a. MetaVision M53)
+    GrawoowG530,
+    /// Mad Gaze Glow
+    MadGazeGlow,
+    /// Simulated glasses, see [`crate::simulated::SimulatedGlasses`]
+    #[cfg(feature = "simulated")]
+    Simulated,
+}
+
+impl GlassesModel {
+    /// Human readable name of the model. Same as [`ARGlasses::name`] of the opened device.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GlassesModel::RokidAir => "Rokid Air",
+            GlassesModel::RokidMax => "Rokid Max",
+            GlassesModel::XrealAir => "XREAL Air",
+            GlassesModel::XrealAir2 => "XREAL Air 2",
+            GlassesModel::XrealAir2Pro => "XREAL Air 2 Pro",
+            GlassesModel::NrealLight => "Nreal Light",
+            GlassesModel::GrawoowG530 => "Grawoow G530",
+            GlassesModel::MadGazeGlow => "Mad Gaze Glow",
+            #[cfg(feature = "simulated")]
+            GlassesModel::Simulated => "Simulated glasses",
+        }
+    }
+}
+
+/// The way the host communicates with the glasses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    /// Raw USB, using [`rusb`]
+    Usb,
+    /// USB HID, using [`hidapi`]
+    Hid,
+    /// USB serial port, using [`serialport`]
+    Serial,
+    /// No actual hardware
+    #[cfg(feature = "simulated")]
+    Simulated,
+}
+
+/// Lightweight description of a connected device, returned by [`list_glasses`].
+/// Getting it does not claim the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlassesDescriptor {
+    /// Model of the glasses
+    pub model: GlassesModel,
+    /// USB Vendor ID of the main (control) interface
+    pub vid: u16,
+    /// USB Product ID of the main (control) interface
+    pub pid: u16,
+    /// Serial number, same as [`ARGlasses::serial`]. Only filled in if it could be
+    /// read without claiming the device.
+    pub serial: Option<String>,
+    /// Transport of the main (control) interface
+    pub transport: Transport,
+    /// Transport-specific location of the main interface: `bus:address` for
+    /// [`Transport::Usb`], the device path for [`Transport::Hid`] and the port name
+    /// for [`Transport::Serial`]. Only valid while the device stays connected.
+    pub bus_path: String,
+}
+
+/// Options for opening glasses, used by [`open_glasses_with_options`] and [`GlassesSelector`]
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    /// Maximum time [`ARGlasses::read_event`] waits for sensor data before returning
+    /// [`Error::PacketTimeout`]. `None` keeps the driver's default (typically 250ms).
+    /// Not supported by Mad Gaze Glow.
+    pub read_timeout: Option<Duration>,
+    /// Send [`crate::GlassesEvent::AmbientLight`] events, if the glasses support it.
+    pub ambient_light_events: bool,
+    /// Send [`crate::GlassesEvent::VSync`] events, if the glasses support it.
+    pub vsync_events: bool,
+    /// Apply the saved [`crate::user_calibration::UserCalibration`] of the glasses, if
+    /// there is one (see [`crate::user_calibration::UserCalibration::load_for`]).
+    pub user_calibration: bool,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            read_timeout: None,
+            ambient_light_events: true,
+            vsync_events: true,
+            user_calibration: true,
+        }
+    }
+}
+
+/// List all connected supported glasses, without opening them.
+///
+/// Backends that fail to enumerate (e.g. because of missing permissions) are skipped.
+/// With the `simulated` feature, the simulated glasses are always listed last.
+/// [`GlassesSelector::open`] only falls back to them if no real glasses could be tried.
+pub fn list_glasses() -> Vec<GlassesDescriptor> {
+    #[allow(unused_mut)]
+    let mut result = Vec::new();
+    #[cfg(feature = "rokid")]
+    result.extend(crate::rokid::list());
+    #[cfg(feature = "nreal")]
+    if let Ok(hidapi) = hidapi::HidApi::new() {
+        result.extend(crate::nreal_air::list(&hidapi));
+        result.extend(crate::nreal_light::list(&hidapi));
+    }
+    #[cfg(feature = "grawoow")]
+    result.extend(crate::grawoow::list());
+    #[cfg(feature = "mad_gaze")]
+    result.extend(crate::mad_gaze::list());
+    #[cfg(feature = "simulated")]
+    result.push(crate::simulated::descriptor());
+    result
+}
+
+/// Open the glasses described by `descriptor`, with default options
+pub fn open_glasses(descriptor: &GlassesDescriptor) -> Result<Box<dyn ARGlasses>> {
+    open_glasses_with_options(descriptor, &Default::default())
+}
+
+/// Open the glasses described by `descriptor`
+pub fn open_glasses_with_options(
+    descriptor: &GlassesDescriptor,
+    options: &OpenOptions,
+) -> Result<Box<dyn ARGlasses>> {
+    let glasses: Box<dyn ARGlasses> = match descriptor.model {
+        #[cfg(feature = "rokid")]
+        GlassesModel::RokidAir | GlassesModel::RokidMax => {
+            Box::new(crate::rokid::RokidAir::open(descriptor, options)?)
+        }
+        #[cfg(feature = "nreal")]
+        GlassesModel::XrealAir | GlassesModel::XrealAir2 | GlassesModel::XrealAir2Pro => {
+            Box::new(crate::nreal_air::NrealAir::open(descriptor, options)?)
+        }
+        #[cfg(feature = "nreal")]
+        GlassesModel::NrealLight => {
+            Box::new(crate::nreal_light::NrealLight::open(descriptor, options)?)
+        }
+        #[cfg(feature = "grawoow")]
+        GlassesModel::GrawoowG530 => {
+            Box::new(crate::grawoow::GrawoowG530::open(descriptor, options)?)
+        }
+        #[cfg(feature = "mad_gaze")]
+        GlassesModel::MadGazeGlow => {
+            Box::new(crate::mad_gaze::MadGazeGlow::open(descriptor, options)?)
+        }
+        #[cfg(feature = "simulated")]
+        GlassesModel::Simulated => {
+            Box::new(crate::simulated::SimulatedGlasses::new(Default::default()))
+        }
+        #[allow(unreachable_patterns)]
+        _ => return Err(Error::NotImplemented),
+    };
+    Ok(if options.user_calibration {
+        crate::user_calibration::apply_saved(glasses, descriptor.serial.as_deref())
+    } else {
+        glasses
+    })
+}
+
+/// Builder for selecting and opening one of the connected glasses
+///
+/// ```ignore
+/// let glasses = GlassesSelector::new()
+///     .model(GlassesModel::RokidMax)
+///     .serial("1234567")
+///     .read_timeout(Duration::from_millis(100))
+///     .open()?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct GlassesSelector {
+    model: Option<GlassesModel>,
+    serial: Option<String>,
+    options: OpenOptions,
+}
+
+impl GlassesSelector {
+    /// Select any of the supported glasses
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Only select glasses of this model
+    pub fn model(mut self, model: GlassesModel) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Only select glasses with this serial number
+    pub fn serial(mut self, serial: impl Into<String>) -> Self {
+        self.serial = Some(serial.into());
+        self
+    }
+
+    /// See [`OpenOptions::read_timeout`]
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.options.read_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`OpenOptions::ambient_light_events`]
+    pub fn ambient_light_events(mut self, enabled: bool) -> Self {
+        self.options.ambient_light_events = enabled;
+        self
+    }
+
+    /// See [`OpenOptions::vsync_events`]
+    pub fn vsync_events(mut self, enabled: bool) -> Self {
+        self.options.vsync_events = enabled;
+        self
+    }
+
+    /// See [`OpenOptions::user_calibration`]
+    pub fn user_calibration(mut self, enabled: bool) -> Self {
+        self.options.user_calibration = enabled;
+        self
+    }
+
+    /// Replace all options at once
+    pub fn options(mut self, options: OpenOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Check whether a descriptor matches the selection. Descriptors without a serial
+    /// number match any serial, as it can only be checked after opening.
+    pub fn matches(&self, descriptor: &GlassesDescriptor) -> bool {
+        self.model.is_none_or(|model| model == descriptor.model)
+            && match (&self.serial, &descriptor.serial) {
+                (Some(wanted), Some(serial)) => wanted == serial,
+                _ => true,
+            }
+    }
+
+    /// Open the first connected glasses that match the selection.
+    ///
+    /// Returns the error of the last failed attempt, or [`Error::NotFound`] if there
+    /// were no matching glasses at all. The simulated glasses are only opened if there
+    /// was no failed attempt to open real ones, so that the real error is not hidden.
+    pub fn open(&self) -> Result<Box<dyn ARGlasses>> {
+        self.open_first(&list_glasses())
+    }
+
+    fn open_first(&self, descriptors: &[GlassesDescriptor]) -> Result<Box<dyn ARGlasses>> {
+        let mut last_error = None;
+        for descriptor in descriptors.iter().filter(|d| self.matches(d)) {
+            #[cfg(feature = "simulated")]
+            if descriptor.model == GlassesModel::Simulated && last_error.is_some() {
+                break;
+            }
+            let mut glasses = match open_glasses_with_options(descriptor, &self.options) {
+                Ok(glasses) => glasses,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            if let (Some(wanted), None) = (&self.serial, &descriptor.serial) {
+                if glasses.serial().ok().as_ref() != Some(wanted) {
+                    continue;
+                }
+            }
+            return Ok(glasses);
+        }
+        Err(last_error.unwrap_or(Error::NotFound))
+    }
+}
+
+#[cfg(all(test, feature = "simulated", feature = "rokid"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_simulated_fallback_after_open_error() {
+        let selector = GlassesSelector::new().user_calibration(false);
+        let simulated = crate::simulated::descriptor();
+        let unplugged = GlassesDescriptor {
+            model: GlassesModel::RokidMax,
+            vid: crate::rokid::RokidAir::VID,
+            pid: crate::rokid::RokidAir::PID,
+            serial: None,
+            transport: Transport::Usb,
+            bus_path: "unplugged".into(),
+        };
+        assert!(selector
+            .open_first(&[unplugged, simulated.clone()])
+            .is_err());
+        let glasses = selector.open_first(&[simulated]).unwrap();
+        assert_eq!(glasses.name(), GlassesModel::Simulated.name());
+    }
+}
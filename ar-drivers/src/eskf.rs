@@ -0,0 +1,297 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Error-state Kalman filter estimating attitude and gyroscope bias. See [`Eskf`]
+
+use nalgebra::{Matrix3, Matrix6, SMatrix, UnitQuaternion, Vector3, Vector6};
+
+use crate::{rub_to_frd, Fusion, GlassesEvent};
+
+type Matrix3x6 = SMatrix<f64, 3, 6>;
+
+/// Noise parameters and settings of [`Eskf`]. The noise values depend on the IMU of
+/// the glasses; the defaults are conservative values that work with all of them.
+#[derive(Debug, Clone)]
+pub struct EskfConfig {
+    /// Standard deviation of the gyroscope noise of a single sample, in rad/s
+    pub gyro_noise: f32,
+    /// Random walk of the gyroscope bias, in rad/s/sqrt(s)
+    pub gyro_bias_walk: f32,
+    /// Standard deviation of the accelerometer noise, in m/s^2. Head movements are
+    /// also treated as noise, so this should be much larger than the sensor noise.
+    pub accelerometer_noise: f32,
+    /// Accelerometer readings with a norm further from 1G than this (in m/s^2) are
+    /// not used, as the glasses are clearly accelerating
+    pub acceleration_gate: f32,
+    /// Standard deviation of the magnetometer noise, relative to the field strength
+    pub magnetometer_noise: f32,
+    /// Initial standard deviation of the roll and pitch, in radians. The yaw is
+    /// always initially unknown.
+    pub initial_attitude_std: f32,
+    /// Initial standard deviation of the gyroscope bias, in rad/s
+    pub initial_bias_std: f32,
+    /// Correct the yaw (and estimate the yaw bias) with the magnetometer. Needs a
+    /// calibrated magnetometer, see [`crate::mag_calibration`]. Ignored for glasses
+    /// without a magnetometer.
+    pub use_magnetometer: bool,
+}
+
+impl Default for EskfConfig {
+    fn default() -> Self {
+        Self {
+            gyro_noise: 0.003,
+            gyro_bias_walk: 0.0001,
+            accelerometer_noise: 0.5,
+            acceleration_gate: 1.0,
+            magnetometer_noise: 0.05,
+            initial_attitude_std: 0.05,
+            initial_bias_std: 0.02,
+            use_magnetometer: false,
+        }
+    }
+}
+
+/// Error-state (multiplicative) extended Kalman filter, see Joan Solà's
+/// "Quaternion kinematics for the error-state Kalman filter".
+///
+/// The nominal state is the attitude and the gyroscope bias. The error state is a
+/// small rotation (in the device frame) and a bias correction, with a 6x6 covariance.
+/// Gyroscope readings drive the prediction step; accelerometer and (optionally)
+/// magnetometer readings are separate measurement updates, so they can come at
+/// different rates. The magnetometer update only corrects the yaw.
+///
+/// The attitude is initialized from the first accelerometer reading. Like every
+/// [`Fusion`], it is in the FRD frame. [`Fusion::inconsistency`] is the square root of
+/// the trace of [`Eskf::attitude_covariance`], i.e. a rough 1-sigma attitude error in
+/// radians.
+pub struct Eskf {
+    config: EskfConfig,
+    attitude: UnitQuaternion<f64>,
+    gyro_bias: Vector3<f64>,
+    /// Last gyroscope reading (FRD)
+    gyroscope: Vector3<f64>,
+    covariance: Matrix6<f64>,
+    last_timestamp: Option<u64>,
+    initialized: bool,
+}
+
+impl Eskf {
+    const GRAVITY: f64 = 9.81;
+    /// Propagation is skipped over longer pauses of the gyroscope (in usecs),
+    /// e.g. after a reconnection
+    const MAX_SAMPLE_GAP: u64 = 1_000_000;
+
+    /// Create the filter
+    pub fn new(config: EskfConfig) -> Self {
+        Self {
+            config,
+            attitude: UnitQuaternion::identity(),
+            gyro_bias: Vector3::zeros(),
+            gyroscope: Vector3::zeros(),
+            covariance: Matrix6::zeros(),
+            last_timestamp: None,
+            initialized: false,
+        }
+    }
+
+    /// The settings used by the filter
+    pub fn config(&self) -> &EskfConfig {
+        &self.config
+    }
+
+    /// Estimated gyroscope bias, in the FRD frame, in rad/s
+    pub fn gyro_bias(&self) -> Vector3<f32> {
+        self.gyro_bias.map(|c| c as f32)
+    }
+
+    /// Covariance of the attitude error, in the FRD device frame, in rad^2
+    pub fn attitude_covariance(&self) -> Matrix3<f32> {
+        self.covariance.fixed_view::<3, 3>(0, 0).map(|c| c as f32)
+    }
+
+    fn initialize(&mut self, acc: &Vector3<f64>) {
+        // At rest, the accelerometer measures -gravity, which is -Z in NED
+        let Some(attitude) = UnitQuaternion::rotation_between(acc, &Vector3::new(0.0, 0.0, -1.0))
+        else {
+            return;
+        };
+        self.attitude = attitude;
+        // The yaw (rotation around the world's vertical) is completely unknown
+        let vertical = attitude.inverse() * Vector3::z();
+        let attitude_variance = (self.config.initial_attitude_std as f64).powi(2);
+        let yaw_variance = std::f64::consts::PI.powi(2);
+        let mut covariance = Matrix6::zeros();
+        covariance.fixed_view_mut::<3, 3>(0, 0).copy_from(
+            &(Matrix3::identity() * attitude_variance
+                + vertical * vertical.transpose() * (yaw_variance - attitude_variance)),
+        );
+        covariance
+            .fixed_view_mut::<3, 3>(3, 3)
+            .copy_from(&(Matrix3::identity() * (self.config.initial_bias_std as f64).powi(2)));
+        self.covariance = covariance;
+        self.initialized = true;
+    }
+
+    fn propagate(&mut self, gyro: &Vector3<f64>, timestamp: u64) {
+        self.gyroscope = *gyro;
+        let dt = match self.last_timestamp.replace(timestamp) {
+            Some(last) if timestamp > last && timestamp - last < Self::MAX_SAMPLE_GAP => {
+                (timestamp - last) as f64 / 1_000_000.0
+            }
+            _ => return,
+        };
+        let increment = UnitQuaternion::from_scaled_axis((gyro - self.gyro_bias) * dt);
+        self.attitude *= increment;
+
+        let mut transition = Matrix6::identity();
+        transition
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&increment.inverse().to_rotation_matrix().into_inner());
+        transition
+            .fixed_view_mut::<3, 3>(0, 3)
+            .copy_from(&(Matrix3::identity() * -dt));
+        let process_noise = Matrix6::from_diagonal(&Vector6::new(
+            (self.config.gyro_noise as f64 * dt).powi(2),
+            (self.config.gyro_noise as f64 * dt).powi(2),
+            (self.config.gyro_noise as f64 * dt).powi(2),
+            (self.config.gyro_bias_walk as f64).powi(2) * dt,
+            (self.config.gyro_bias_walk as f64).powi(2) * dt,
+            (self.config.gyro_bias_walk as f64).powi(2) * dt,
+        ));
+        self.covariance = transition * self.covariance * transition.transpose() + process_noise;
+    }
+
+    fn update_accelerometer(&mut self, acc: &Vector3<f64>) {
+        if (acc.norm() - Self::GRAVITY).abs() > self.config.acceleration_gate as f64 {
+            return;
+        }
+        let expected = self.attitude.inverse() * Vector3::new(0.0, 0.0, -Self::GRAVITY);
+        let mut jacobian = Matrix3x6::zeros();
+        jacobian
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&expected.cross_matrix());
+        let noise = (self.config.accelerometer_noise as f64).powi(2);
+        self.correct(&(acc - expected), &jacobian, noise);
+    }
+
+    fn update_magnetometer(&mut self, mag: &Vector3<f64>) {
+        let Some(mag) = mag.try_normalize(f64::EPSILON) else {
+            return;
+        };
+        // The expected field is the horizontal part of the measurement, pointing north,
+        // plus its vertical part. So the local inclination does not matter.
+        let world = self.attitude * mag;
+        let reference = Vector3::new((world.x * world.x + world.y * world.y).sqrt(), 0.0, world.z);
+        let expected = self.attitude.inverse() * reference;
+        // Only allow a correction around the world's vertical axis, so that magnetic
+        // disturbances do not affect roll and pitch
+        let vertical = self.attitude.inverse() * Vector3::z();
+        let mut jacobian = Matrix3x6::zeros();
+        jacobian
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&(expected.cross_matrix() * vertical * vertical.transpose()));
+        let noise = (self.config.magnetometer_noise as f64).powi(2);
+        self.correct(&(mag - expected), &jacobian, noise);
+    }
+
+    /// Kalman update with a 3D measurement with isotropic noise, then injection of the
+    /// error state into the nominal state
+    fn correct(&mut self, innovation: &Vector3<f64>, jacobian: &Matrix3x6, noise: f64) {
+        let noise = Matrix3::identity() * noise;
+        let innovation_covariance = jacobian * self.covariance * jacobian.transpose() + noise;
+        let Some(inverse) = innovation_covariance.try_inverse() else {
+            return;
+        };
+        let gain = self.covariance * jacobian.transpose() * inverse;
+        let error = gain * innovation;
+        self.attitude *= UnitQuaternion::from_scaled_axis(error.fixed_rows::<3>(0).into_owned());
+        self.gyro_bias += error.fixed_rows::<3>(3);
+        // Joseph form, to keep the covariance symmetric and positive definite
+        let i_kh = Matrix6::identity() - gain * jacobian;
+        self.covariance =
+            i_kh * self.covariance * i_kh.transpose() + gain * noise * gain.transpose();
+    }
+}
+
+impl Fusion for Eskf {
+    fn ingest(&mut self, event: &GlassesEvent) {
+        match event {
+            GlassesEvent::AccGyro {
+                accelerometer,
+                gyroscope,
+                timestamp,
+            } => {
+                let acc = rub_to_frd(accelerometer).cast();
+                if !self.initialized {
+                    self.initialize(&acc);
+                }
+                self.propagate(&rub_to_frd(gyroscope).cast(), *timestamp);
+                self.update_accelerometer(&acc);
+            }
+            GlassesEvent::Magnetometer { magnetometer, .. }
+                if self.config.use_magnetometer && self.initialized =>
+            {
+                self.update_magnetometer(&rub_to_frd(magnetometer).cast());
+            }
+            GlassesEvent::Disconnected => {
+                // Timestamps may restart
+                self.last_timestamp = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn attitude_quaternion(&self) -> UnitQuaternion<f32> {
+        self.attitude.cast()
+    }
+
+    fn inconsistency(&self) -> f32 {
+        self.attitude_covariance().trace().sqrt()
+    }
+
+    fn angular_velocity(&self) -> Vector3<f32> {
+        (self.gyroscope - self.gyro_bias).cast()
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.last_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frd_to_rub;
+
+    #[test]
+    fn test_estimates_gyro_bias() {
+        let truth = UnitQuaternion::from_euler_angles(0.3, -0.2, 0.5);
+        let bias = Vector3::new(0.01, -0.02, 0.015);
+        let gravity = Vector3::new(0.0, 0.0, -9.81);
+        let field = Vector3::new(20.0, 0.0, 44.0);
+        let mut fusion = Eskf::new(EskfConfig {
+            use_magnetometer: true,
+            ..Default::default()
+        });
+        // One minute of stationary readings at 100Hz, magnetometer at 50Hz
+        for i in 0..6000 {
+            let timestamp = i * 10_000;
+            fusion.ingest(&GlassesEvent::AccGyro {
+                accelerometer: frd_to_rub(&(truth.inverse() * gravity)),
+                gyroscope: frd_to_rub(&bias),
+                timestamp,
+            });
+            if i % 2 == 0 {
+                fusion.ingest(&GlassesEvent::Magnetometer {
+                    magnetometer: frd_to_rub(&(truth.inverse() * field)),
+                    timestamp,
+                });
+            }
+        }
+        assert!((fusion.gyro_bias() - bias).amax() < 1e-3);
+        assert!(fusion.attitude_quaternion().angle_to(&truth) < 0.01);
+        // Confident after a minute of consistent measurements
+        assert!(fusion.inconsistency() < 0.05);
+    }
+}
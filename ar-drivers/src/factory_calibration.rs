@@ -0,0 +1,286 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Calibration data stored on the glasses by the manufacturer. See [`FactoryCalibration`]
+
+use nalgebra::{Matrix3, Vector3};
+#[cfg(feature = "tinyjson")]
+use nalgebra::{Quaternion, Translation3, UnitQuaternion};
+#[cfg(feature = "tinyjson")]
+use tinyjson::JsonValue;
+
+use crate::DisplayMatrices;
+
+/// Typed version of the calibration data read from the glasses during connection.
+///
+/// All vectors and matrices are in the raw sensor frame of the glasses, exactly as
+/// stored on the device (i.e. not converted to the RUB frame of [`crate::GlassesEvent`]).
+#[derive(Debug, Clone, Default)]
+pub struct FactoryCalibration {
+    /// Accelerometer bias, in m/s^2
+    pub accelerometer_bias: Vector3<f64>,
+    /// Gyroscope bias, in rad/s
+    pub gyro_bias: Vector3<f64>,
+    /// Accelerometer scale (and misalignment) matrix, if the glasses store one
+    pub accelerometer_scale: Option<Matrix3<f64>>,
+    /// Gyroscope scale (and misalignment) matrix, if the glasses store one
+    pub gyro_scale: Option<Matrix3<f64>>,
+    /// Intrinsics and extrinsics of the left and right displays, if the glasses store them
+    pub displays: Option<(DisplayMatrices, DisplayMatrices)>,
+}
+
+/// The calibration data did not have the expected format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalibrationError {
+    /// Path of the offending value in the calibration JSON, e.g. `IMU.device_1.gyro_bias[2]`
+    pub path: String,
+    /// What was wrong with the value
+    pub kind: CalibrationErrorKind,
+}
+
+/// See [`CalibrationError`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalibrationErrorKind {
+    /// The value is missing
+    Missing,
+    /// The value is not of the expected type
+    WrongType {
+        /// The expected JSON type
+        expected: &'static str,
+    },
+    /// The array does not have the expected number of elements
+    WrongLength {
+        /// Expected number of elements
+        expected: usize,
+        /// Actual number of elements
+        found: usize,
+    },
+}
+
+impl std::error::Error for CalibrationError {}
+
+impl std::fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            CalibrationErrorKind::Missing => write!(f, "{} is missing", self.path),
+            CalibrationErrorKind::WrongType { expected } => {
+                write!(f, "{} is not {}", self.path, expected)
+            }
+            CalibrationErrorKind::WrongLength { expected, found } => write!(
+                f,
+                "{} has {} elements instead of {}",
+                self.path, found, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "tinyjson")]
+impl FactoryCalibration {
+    /// Parse the calibration JSON of XREAL (Nreal) glasses, as returned by
+    /// [`crate::nreal_air::NrealAir::get_config_json`] and
+    /// [`crate::nreal_light::NrealLight::get_config_json`].
+    ///
+    /// The `display` section is optional, but if it is present, it has to be complete.
+    pub fn from_xreal_json(json: &JsonValue) -> Result<Self, CalibrationError> {
+        let root = JsonPath::root(json);
+        let imu = root.key("IMU")?.key("device_1")?;
+        let scale = |key| -> Result<_, CalibrationError> {
+            match imu.optional_key(key)? {
+                Some(scale) => Ok(Some(Matrix3::from_diagonal(&scale.vector3()?))),
+                None => Ok(None),
+            }
+        };
+        Ok(Self {
+            accelerometer_bias: imu.key("accel_bias")?.vector3()?,
+            gyro_bias: imu.key("gyro_bias")?.vector3()?,
+            accelerometer_scale: scale("scale_accel")?,
+            gyro_scale: scale("scale_gyro")?,
+            displays: match root.optional_key("display")? {
+                Some(display) => Some(Self::parse_xreal_displays(&display)?),
+                None => None,
+            },
+        })
+    }
+
+    /// Parse the calibration JSON of Grawoow glasses, as returned by
+    /// [`crate::grawoow::GrawoowG530::get_config_json`].
+    ///
+    /// `RM_acc` and `RM_gyro` are 3x4 matrices: the scale matrix, followed by the bias.
+    pub fn from_grawoow_json(json: &JsonValue) -> Result<Self, CalibrationError> {
+        let imu = JsonPath::root(json).key("imu")?.index(0)?;
+        let acc: [f64; 12] = imu.key("RM_acc")?.array()?;
+        let gyro: [f64; 12] = imu.key("RM_gyro")?.array()?;
+        Ok(Self {
+            accelerometer_bias: Vector3::from_column_slice(&acc[9..]),
+            gyro_bias: Vector3::from_column_slice(&gyro[9..]),
+            accelerometer_scale: Some(Matrix3::from_row_slice(&acc[..9])),
+            gyro_scale: Some(Matrix3::from_row_slice(&gyro[..9])),
+            displays: None,
+        })
+    }
+
+    fn parse_xreal_displays(
+        json: &JsonPath,
+    ) -> Result<(DisplayMatrices, DisplayMatrices), CalibrationError> {
+        let [width, height]: [f64; 2] = json.key("resolution")?.array()?;
+        let resolution = (width as u32, height as u32);
+
+        let side_descriptor = |side: &str| -> Result<DisplayMatrices, CalibrationError> {
+            let translation = json.key(&format!("target_p_{side}_display"))?.vector3()?;
+            let [x, y, z, w]: [f64; 4] = json.key(&format!("target_q_{side}_display"))?.array()?;
+            let rotation = UnitQuaternion::from_quaternion(Quaternion::new(w, x, y, z));
+            let intrinsic: [f64; 9] = json.key(&format!("k_{side}_display"))?.array()?;
+            Ok(DisplayMatrices {
+                intrinsic_matrix: Matrix3::from_row_slice(&intrinsic),
+                resolution,
+                isometry: Translation3::from(translation) * rotation,
+            })
+        };
+        let mut left = side_descriptor("left")?;
+        let mut right = side_descriptor("right")?;
+        // The calibration seems to be based on a reference point near the right lens.
+        // We will center the translation component between the displays.
+        let mean = (left.isometry.translation.vector + right.isometry.translation.vector) * 0.5;
+        left.isometry.translation.vector -= mean;
+        right.isometry.translation.vector -= mean;
+        Ok((left, right))
+    }
+}
+
+/// A JSON value together with its path from the root, for error reporting
+#[cfg(feature = "tinyjson")]
+struct JsonPath<'a> {
+    value: &'a JsonValue,
+    path: String,
+}
+
+#[cfg(feature = "tinyjson")]
+impl<'a> JsonPath<'a> {
+    fn root(value: &'a JsonValue) -> Self {
+        Self {
+            value,
+            path: String::new(),
+        }
+    }
+
+    fn error(&self, kind: CalibrationErrorKind) -> CalibrationError {
+        CalibrationError {
+            path: self.path.clone(),
+            kind,
+        }
+    }
+
+    fn optional_key(&self, key: &str) -> Result<Option<JsonPath<'a>>, CalibrationError> {
+        let JsonValue::Object(object) = self.value else {
+            return Err(self.error(CalibrationErrorKind::WrongType {
+                expected: "an object",
+            }));
+        };
+        Ok(object.get(key).map(|value| JsonPath {
+            value,
+            path: self.key_path(key),
+        }))
+    }
+
+    fn key(&self, key: &str) -> Result<JsonPath<'a>, CalibrationError> {
+        self.optional_key(key)?.ok_or_else(|| CalibrationError {
+            path: self.key_path(key),
+            kind: CalibrationErrorKind::Missing,
+        })
+    }
+
+    fn key_path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+
+    fn elements(&self) -> Result<&'a [JsonValue], CalibrationError> {
+        match self.value {
+            JsonValue::Array(array) => Ok(array),
+            _ => Err(self.error(CalibrationErrorKind::WrongType {
+                expected: "an array",
+            })),
+        }
+    }
+
+    fn index(&self, index: usize) -> Result<JsonPath<'a>, CalibrationError> {
+        let path = format!("{}[{}]", self.path, index);
+        match self.elements()?.get(index) {
+            Some(value) => Ok(JsonPath { value, path }),
+            None => Err(CalibrationError {
+                path,
+                kind: CalibrationErrorKind::Missing,
+            }),
+        }
+    }
+
+    fn number(&self) -> Result<f64, CalibrationError> {
+        match self.value {
+            JsonValue::Number(n) => Ok(*n),
+            _ => Err(self.error(CalibrationErrorKind::WrongType {
+                expected: "a number",
+            })),
+        }
+    }
+
+    fn array<const N: usize>(&self) -> Result<[f64; N], CalibrationError> {
+        let elements = self.elements()?;
+        if elements.len() != N {
+            return Err(self.error(CalibrationErrorKind::WrongLength {
+                expected: N,
+                found: elements.len(),
+            }));
+        }
+        let mut result = [0.0; N];
+        for (i, r) in result.iter_mut().enumerate() {
+            *r = self.index(i)?.number()?;
+        }
+        Ok(result)
+    }
+
+    fn vector3(&self) -> Result<Vector3<f64>, CalibrationError> {
+        Ok(Vector3::from(self.array::<3>()?))
+    }
+}
+
+#[cfg(all(test, feature = "tinyjson"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xreal_errors_name_the_path() {
+        let parse = |s: &str| FactoryCalibration::from_xreal_json(&s.parse().unwrap());
+        let calibration = parse(
+            r#"{"IMU": {"device_1": {"accel_bias": [0.1, 0.2, 0.3], "gyro_bias": [1, 2, 3]}}}"#,
+        )
+        .unwrap();
+        assert_eq!(calibration.gyro_bias, Vector3::new(1.0, 2.0, 3.0));
+        assert!(calibration.gyro_scale.is_none() && calibration.displays.is_none());
+
+        let error = parse(r#"{"IMU": {"device_1": {"accel_bias": [0.1, 0.2, 0.3]}}}"#);
+        assert_eq!(
+            error.unwrap_err(),
+            CalibrationError {
+                path: "IMU.device_1.gyro_bias".into(),
+                kind: CalibrationErrorKind::Missing
+            }
+        );
+        let error = parse(r#"{"IMU": {"device_1": {"accel_bias": [0.1, "x", 0.3]}}}"#);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "IMU.device_1.accel_bias[1] is not a number"
+        );
+        let error = parse(r#"{"IMU": {"device_1": {"accel_bias": [0.1, 0.3]}}}"#);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "IMU.device_1.accel_bias has 2 elements instead of 3"
+        );
+        let error = parse(r#"{"IMU": []}"#);
+        assert_eq!(error.unwrap_err().to_string(), "IMU is not an object");
+    }
+}
@@ -0,0 +1,347 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Comparing the [`crate::Fusion`] implementations against synthetic ground truth. See [`run`]
+//!
+//! Each [`Scenario`] is a scripted head motion, played back by
+//! [`SimulatedGlasses`] with the noise and bias of [`BenchmarkConfig::simulation`].
+//! The events are fed to the filter, and its attitude is compared to the ground truth
+//! after every accelerometer/gyroscope sample.
+
+use std::time::Duration;
+
+use nalgebra::{UnitQuaternion, Vector3};
+
+use crate::{
+    eskf::EskfConfig,
+    madgwick::MadgwickConfig,
+    rub_to_frd_rotation,
+    simulated::{Motion, MotionSegment, SimulatedGlasses, SimulationConfig, Trajectory},
+    ARGlasses, FusionAlgorithm, GlassesEvent,
+};
+
+/// A named head motion to benchmark the filters with
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    /// Short name, for reports
+    pub name: &'static str,
+    /// The motion itself. It is looped until [`BenchmarkConfig::duration`] is reached.
+    pub trajectory: Trajectory,
+}
+
+impl Scenario {
+    /// Slowly looking left and right, like reading a wide document
+    pub fn slow_pan() -> Self {
+        Self::looping(
+            "slow pan",
+            vec![segment(
+                20.0,
+                Motion::Oscillate {
+                    axis: Vector3::y(),
+                    amplitude: 1.0,
+                    frequency: 0.05,
+                },
+            )],
+        )
+    }
+
+    /// Fast, short head turns between two targets, with fixations in between
+    pub fn saccades() -> Self {
+        let turn = |x: f32, y: f32| Motion::Rotate(Vector3::new(x, y, 0.0));
+        Self::looping(
+            "saccades",
+            vec![
+                segment(0.1, turn(0.0, 4.0)),
+                segment(0.9, Motion::Still),
+                segment(0.1, turn(-2.0, -4.0)),
+                segment(0.9, Motion::Still),
+                segment(0.1, turn(2.0, 0.0)),
+                segment(0.9, Motion::Still),
+            ],
+        )
+    }
+
+    /// Nodding, at a walking pace
+    pub fn nods() -> Self {
+        Self::looping(
+            "nods",
+            vec![segment(
+                1.0,
+                Motion::Oscillate {
+                    axis: Vector3::x(),
+                    amplitude: 0.4,
+                    frequency: 2.0,
+                },
+            )],
+        )
+    }
+
+    /// Tilting the head 90 degrees to the side, and looking straight down, holding both
+    pub fn tilts() -> Self {
+        let quarter_turn =
+            |axis: Vector3<f32>| segment(1.0, Motion::Rotate(axis * std::f32::consts::FRAC_PI_2));
+        Self::looping(
+            "90° tilts",
+            vec![
+                quarter_turn(Vector3::z()),
+                segment(3.0, Motion::Still),
+                quarter_turn(-Vector3::z()),
+                segment(1.0, Motion::Still),
+                quarter_turn(-Vector3::x()),
+                segment(3.0, Motion::Still),
+                quarter_turn(Vector3::x()),
+                segment(1.0, Motion::Still),
+            ],
+        )
+    }
+
+    /// The glasses lying on a desk
+    pub fn still() -> Self {
+        Self {
+            name: "still",
+            trajectory: Trajectory::still(),
+        }
+    }
+
+    /// All of the above
+    pub fn all() -> Vec<Self> {
+        vec![
+            Self::slow_pan(),
+            Self::saccades(),
+            Self::nods(),
+            Self::tilts(),
+            Self::still(),
+        ]
+    }
+
+    fn looping(name: &'static str, segments: Vec<MotionSegment>) -> Self {
+        Self {
+            name,
+            trajectory: Trajectory {
+                segments,
+                looping: true,
+            },
+        }
+    }
+}
+
+fn segment(secs: f32, motion: Motion) -> MotionSegment {
+    MotionSegment {
+        duration: Duration::from_secs_f32(secs),
+        motion,
+    }
+}
+
+/// Settings of [`run`]
+#[derive(Debug, Clone)]
+pub struct BenchmarkConfig {
+    /// Sensor characteristics: sample rates, noise and bias, and the initial attitude
+    /// the filters have to converge to. The trajectory is taken from the [`Scenario`],
+    /// and the simulation always runs as fast as possible, without scripted events.
+    pub simulation: SimulationConfig,
+    /// Simulated time of each run
+    pub duration: Duration,
+    /// The filter is considered converged when the tilt error stays below this, in radians
+    pub convergence_threshold: f32,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            simulation: SimulationConfig {
+                // Pitched up and rolled, but facing north, so that the heading
+                // estimate of the filters is correct from the start
+                initial_attitude: UnitQuaternion::from_axis_angle(&Vector3::x_axis(), 0.2)
+                    * UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -0.3),
+                ..Default::default()
+            },
+            duration: Duration::from_secs(60),
+            convergence_threshold: 2.0f32.to_radians(),
+        }
+    }
+}
+
+/// Results of a single [`run`]. All angles are in radians.
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+    /// The filter that was benchmarked
+    pub algorithm: FusionAlgorithm,
+    /// Name of the [`Scenario`]
+    pub scenario: &'static str,
+    /// RMS of the angle between the estimated and the true attitude
+    pub rms_error: f32,
+    /// RMS of the angle between the estimated and the true direction of gravity,
+    /// i.e. the error without the heading
+    pub rms_tilt_error: f32,
+    /// Largest attitude error after convergence
+    pub max_error: f32,
+    /// Growth rate of the attitude error after convergence (least squares fit),
+    /// in radians per minute. Mostly heading drift for filters without a magnetometer.
+    pub drift_per_minute: f32,
+    /// Time until the tilt error stays below [`BenchmarkConfig::convergence_threshold`].
+    /// `None` if it never does.
+    pub convergence_time: Option<Duration>,
+}
+
+/// Every built-in filter, with and without the magnetometer where applicable
+pub fn all_algorithms() -> Vec<FusionAlgorithm> {
+    vec![
+        FusionAlgorithm::NaiveCF,
+        FusionAlgorithm::Madgwick(Default::default()),
+        FusionAlgorithm::Madgwick(MadgwickConfig {
+            use_magnetometer: true,
+            ..Default::default()
+        }),
+        FusionAlgorithm::Eskf(Default::default()),
+        FusionAlgorithm::Eskf(EskfConfig {
+            use_magnetometer: true,
+            ..Default::default()
+        }),
+    ]
+}
+
+/// Run every algorithm on every scenario
+pub fn run_all(
+    algorithms: &[FusionAlgorithm],
+    scenarios: &[Scenario],
+    config: &BenchmarkConfig,
+) -> Vec<BenchmarkResult> {
+    scenarios
+        .iter()
+        .flat_map(|scenario| {
+            algorithms
+                .iter()
+                .map(move |algorithm| run(algorithm, scenario, config))
+        })
+        .collect()
+}
+
+/// Feed the simulated events of `scenario` to a new instance of `algorithm`, and
+/// compare its output to the ground truth
+pub fn run(
+    algorithm: &FusionAlgorithm,
+    scenario: &Scenario,
+    config: &BenchmarkConfig,
+) -> BenchmarkResult {
+    let mut glasses = SimulatedGlasses::new(SimulationConfig {
+        trajectory: scenario.trajectory.clone(),
+        scripted_events: Vec::new(),
+        realtime: false,
+        ..config.simulation.clone()
+    });
+    let mut fusion = algorithm.clone().create();
+    let end = config.duration.as_micros() as u64;
+    // (time in seconds, attitude error, tilt error)
+    let mut errors = Vec::new();
+    while glasses.time() < end {
+        let Ok(event) = glasses.read_event() else {
+            break;
+        };
+        fusion.ingest(&event);
+        if let GlassesEvent::AccGyro { .. } = event {
+            let truth = rub_to_frd_rotation(&glasses.true_attitude());
+            let estimate = fusion.attitude_quaternion();
+            let tilt_error =
+                (estimate.inverse() * Vector3::z()).angle(&(truth.inverse() * Vector3::z()));
+            errors.push((
+                glasses.time() as f32 / 1_000_000.0,
+                estimate.angle_to(&truth),
+                tilt_error,
+            ));
+        }
+    }
+
+    let rms = |values: &mut dyn Iterator<Item = f32>| {
+        let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v * v, count + 1));
+        (sum / count.max(1) as f32).sqrt()
+    };
+    let converged_from = errors
+        .iter()
+        .rposition(|&(_, _, tilt)| tilt >= config.convergence_threshold)
+        .map_or(0, |i| i + 1);
+    let convergence_time = errors
+        .get(converged_from)
+        .map(|&(time, _, _)| Duration::from_secs_f32(time));
+    let converged = match errors.get(converged_from..) {
+        Some(converged) if !converged.is_empty() => converged,
+        _ => &errors[..],
+    };
+    BenchmarkResult {
+        algorithm: algorithm.clone(),
+        scenario: scenario.name,
+        rms_error: rms(&mut errors.iter().map(|e| e.1)),
+        rms_tilt_error: rms(&mut errors.iter().map(|e| e.2)),
+        max_error: converged.iter().map(|e| e.1).fold(0.0, f32::max),
+        drift_per_minute: slope(converged) * 60.0,
+        convergence_time,
+    }
+}
+
+/// Least squares slope of the attitude error over time, per second
+fn slope(errors: &[(f32, f32, f32)]) -> f32 {
+    let n = errors.len() as f64;
+    if n < 2.0 {
+        return 0.0;
+    }
+    let mean_time = errors.iter().map(|e| e.0 as f64).sum::<f64>() / n;
+    let mean_error = errors.iter().map(|e| e.1 as f64).sum::<f64>() / n;
+    let (covariance, variance) =
+        errors
+            .iter()
+            .fold((0.0, 0.0), |(covariance, variance), &(time, error, _)| {
+                let dt = time as f64 - mean_time;
+                (
+                    covariance + dt * (error as f64 - mean_error),
+                    variance + dt * dt,
+                )
+            });
+    if variance > 0.0 {
+        (covariance / variance) as f32
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_filters_converge() {
+        let config = BenchmarkConfig {
+            simulation: SimulationConfig {
+                sample_rate: 100.0,
+                magnetometer_rate: Some(50.0),
+                ..BenchmarkConfig::default().simulation
+            },
+            duration: Duration::from_secs(20),
+            ..Default::default()
+        };
+        for result in run_all(&all_algorithms(), &Scenario::all(), &config) {
+            let convergence_time = result
+                .convergence_time
+                .unwrap_or_else(|| panic!("{} did not converge: {:?}", result.algorithm, result));
+            assert!(convergence_time < Duration::from_secs(10), "{result:?}");
+            // Includes the convergence from the initial 20° tilt error
+            assert!(result.rms_tilt_error < 0.1, "{result:?}");
+        }
+    }
+
+    #[test]
+    fn test_perfect_sensors() {
+        let mut config = BenchmarkConfig {
+            duration: Duration::from_secs(10),
+            ..Default::default()
+        };
+        config.simulation.initial_attitude = UnitQuaternion::identity();
+        config.simulation.gyro_noise = 0.0;
+        config.simulation.gyro_bias = Vector3::zeros();
+        config.simulation.accelerometer_noise = 0.0;
+        config.simulation.magnetometer_noise = 0.0;
+        let result = run(&FusionAlgorithm::NaiveCF, &Scenario::still(), &config);
+        assert!(result.convergence_time.unwrap() < Duration::from_millis(10));
+        assert!(result.rms_error < 1e-4, "{result:?}");
+        assert!(result.drift_per_minute.abs() < 1e-4, "{result:?}");
+    }
+}
@@ -13,7 +13,13 @@ use rusb::{request_type, DeviceHandle, GlobalContext};
 use tinyjson::JsonValue;
 
 use crate::{
-    util::get_interface_for_endpoint, ARGlasses, DisplayMode, Error, GlassesEvent, Result, Side,
+    factory_calibration::FactoryCalibration, util::get_interface_for_endpoint, ARGlasses,
+    DisplayLayout, DisplayMode, DisplayModeInfo, Error, GlassesEvent, Result, Side,
+};
+#[cfg(not(target_os = "android"))]
+use crate::{
+    util::{get_device_by_bus_path, usb_bus_path},
+    GlassesDescriptor, GlassesModel, OpenOptions, Transport,
 };
 
 /// The main structure representing a connected Grawoow G530 (a.k.a. MetaVision M53) glasses
@@ -21,9 +27,12 @@ pub struct GrawoowG530 {
     mcu_handle: DeviceHandle<GlobalContext>,
     ov580_handle: DeviceHandle<GlobalContext>,
     config_json: JsonValue,
+    calibration: FactoryCalibration,
     gyro_bias: Vector3<f32>,
     accelerometer_bias: Vector3<f32>,
     start: Instant,
+    read_timeout: Duration,
+    rejected_packets: u64,
 }
 
 const OV580_ENDPOINT: u8 = 0x89;
@@ -39,7 +48,7 @@ impl ARGlasses for GrawoowG530 {
     fn read_event(&mut self) -> Result<GlassesEvent> {
         let mut packet_data = [0u8; 0x80];
         self.ov580_handle
-            .read_interrupt(OV580_ENDPOINT, &mut packet_data, OV580_TIMEOUT)?;
+            .read_interrupt(OV580_ENDPOINT, &mut packet_data, self.read_timeout)?;
         self.parse_imu_packet(&packet_data)
     }
 
@@ -62,6 +71,13 @@ impl ARGlasses for GrawoowG530 {
         Ok(())
     }
 
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        vec![
+            DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+            DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+        ]
+    }
+
     fn display_fov(&self) -> f32 {
         // Measurement result
         22f32.to_radians()
@@ -88,6 +104,10 @@ impl ARGlasses for GrawoowG530 {
     fn display_delay(&self) -> u64 {
         15000
     }
+
+    fn rejected_packets(&self) -> u64 {
+        self.rejected_packets
+    }
 }
 
 impl GrawoowG530 {
@@ -130,6 +150,32 @@ impl GrawoowG530 {
         )
     }
 
+    /// Connect to the device found by [`crate::list_glasses`]
+    #[cfg(not(target_os = "android"))]
+    pub fn open(descriptor: &GlassesDescriptor, options: &OpenOptions) -> Result<Self> {
+        let mcu = get_device_by_bus_path(&descriptor.bus_path)?;
+        // The MCU and the OV580 are behind the same hub inside the glasses,
+        // so the OV580 of these glasses only differs in the last port number.
+        let hub_ports = |device: &rusb::Device<GlobalContext>| {
+            let mut ports = device.port_numbers().unwrap_or_default();
+            ports.pop();
+            (device.bus_number(), ports)
+        };
+        let ov580 = rusb::DeviceList::new()?
+            .iter()
+            .find(|device| {
+                device.device_descriptor().is_ok_and(|desc| {
+                    desc.vendor_id() == Self::OV580_VID && desc.product_id() == Self::OV580_PID
+                }) && hub_ports(device) == hub_ports(&mcu)
+            })
+            .ok_or(Error::NotFound)?;
+        let mut result = Self::new_common(mcu.open()?, ov580.open()?)?;
+        if let Some(read_timeout) = options.read_timeout {
+            result.read_timeout = read_timeout;
+        }
+        Ok(result)
+    }
+
     fn new_common(
         mut mcu_handle: DeviceHandle<GlobalContext>,
         mut ov580_handle: DeviceHandle<GlobalContext>,
@@ -147,14 +193,28 @@ impl GrawoowG530 {
             mcu_handle,
             ov580_handle,
             config_json: tinyjson::JsonValue::Null,
+            calibration: Default::default(),
             gyro_bias: Default::default(),
             accelerometer_bias: Default::default(),
             start: Instant::now(),
+            read_timeout: OV580_TIMEOUT,
+            rejected_packets: 0,
         };
         result.read_calibration()?;
         Ok(result)
     }
 
+    /// Returns the calibration data stored on the Glasses. No transformation
+    /// is done on the data, except for Json Parsing.
+    pub fn get_config_json(&self) -> &JsonValue {
+        &self.config_json
+    }
+
+    /// Returns the parsed calibration data stored on the Glasses
+    pub fn factory_calibration(&self) -> &FactoryCalibration {
+        &self.calibration
+    }
+
     fn read_calibration(&mut self) -> Result<()> {
         let mut calib_string = Vec::new();
         for _ in 0..100 {
@@ -179,22 +239,13 @@ impl GrawoowG530 {
             .parse()
             .map_err(|_| Error::Other("Invalid glasses config format (JSON parse error)"))?;
 
-        let json = &self.config_json["imu"][0]["RM_acc"];
-        self.accelerometer_bias = Vector3::new(
-            *json[9].get::<f64>().unwrap() as f32,
-            *json[10].get::<f64>().unwrap() as f32,
-            *json[11].get::<f64>().unwrap() as f32,
-        );
-        let json = &self.config_json["imu"][0]["RM_gyro"];
-        self.gyro_bias = Vector3::new(
-            *json[9].get::<f64>().unwrap() as f32,
-            *json[10].get::<f64>().unwrap() as f32,
-            *json[11].get::<f64>().unwrap() as f32,
-        );
+        self.calibration = FactoryCalibration::from_grawoow_json(&self.config_json)?;
+        self.accelerometer_bias = self.calibration.accelerometer_bias.map(|c| c as f32);
+        self.gyro_bias = self.calibration.gyro_bias.map(|c| c as f32);
         Ok(())
     }
 
-    fn command(&self, cmd_id: u16, additional_data: &[u8]) -> Result<Vec<u8>> {
+    fn command(&mut self, cmd_id: u16, additional_data: &[u8]) -> Result<Vec<u8>> {
         self.send_command_request(cmd_id, additional_data)?;
         self.recv_command_result(cmd_id)
     }
@@ -227,7 +278,7 @@ impl GrawoowG530 {
         )?;
         Ok(())
     }
-    fn recv_command_result(&self, cmd_id: u16) -> Result<Vec<u8>> {
+    fn recv_command_result(&mut self, cmd_id: u16) -> Result<Vec<u8>> {
         let mut result = [0; 0x100];
         self.mcu_handle.read_control(
             request_type(
@@ -246,11 +297,20 @@ impl GrawoowG530 {
             || result[2] != (cmd_id >> 8) as u8
             || result[3] != (cmd_id & 0xff) as u8
             || result[4] != 0
-        // TODO: check checksum
         {
             return Err(Error::Other("Protocol error"));
         }
         let len = result[5] as usize;
+        if 6 + len >= result.len() {
+            self.rejected_packets += 1;
+            return Err(Error::Other("Malformed packet received"));
+        }
+        // Same as in send_command: sum of everything after the header
+        let checksum: u32 = result[2..(6 + len)].iter().map(|x| *x as u32).sum();
+        if result[6 + len] != checksum as u8 {
+            self.rejected_packets += 1;
+            return Err(Error::ChecksumMismatch);
+        }
         Ok(result[6..(6 + len)].into())
     }
 
@@ -284,3 +344,29 @@ impl GrawoowG530 {
         })
     }
 }
+
+/// List connected Grawoow glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn list() -> Vec<GlassesDescriptor> {
+    let Ok(devices) = rusb::DeviceList::new() else {
+        return Vec::new();
+    };
+    devices
+        .iter()
+        .filter(|device| {
+            device.device_descriptor().is_ok_and(|desc| {
+                desc.vendor_id() == GrawoowG530::MCU_VID
+                    && desc.product_id() == GrawoowG530::MCU_PID
+            })
+        })
+        .map(|device| GlassesDescriptor {
+            model: GlassesModel::GrawoowG530,
+            vid: GrawoowG530::MCU_VID,
+            pid: GrawoowG530::MCU_PID,
+            // The glasses serial can only be queried with an MCU command
+            serial: None,
+            transport: Transport::Usb,
+            bus_path: usb_bus_path(&device),
+        })
+        .collect()
+}
@@ -0,0 +1,226 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Temperature dependent gyroscope bias compensation. See [`TemperatureBiasModel`]
+
+use std::collections::BTreeMap;
+
+use nalgebra::Vector3;
+
+use crate::{
+    stationary::{StationaryConfig, StationaryDetector},
+    GlassesEvent,
+};
+
+/// Settings of [`TemperatureBiasModel`]
+#[derive(Debug, Clone)]
+pub struct TemperatureBiasConfig {
+    /// Settings of the stationary detection. The average gyroscope reading of each
+    /// stationary block is a bias sample.
+    pub detector: StationaryConfig,
+    /// Bias samples are grouped by temperature with this resolution, in Celsius
+    pub temperature_resolution: f32,
+}
+
+impl Default for TemperatureBiasConfig {
+    fn default() -> Self {
+        Self {
+            detector: Default::default(),
+            temperature_resolution: 0.5,
+        }
+    }
+}
+
+/// Learns the gyroscope bias as a linear function of the IMU temperature, using
+/// the periods when the glasses are stationary.
+///
+/// Feed every event to [`TemperatureBiasModel::update`] (or use
+/// [`TemperatureBiasModel::compensate`] to also correct them). Needs glasses that send
+/// [`GlassesEvent::Temperature`] events; nothing is learned before the first one.
+///
+/// Stationary periods are detected with [`StationaryDetector`]. For a simpler,
+/// temperature independent estimate, see [`crate::stationary::GyroBiasEstimator`].
+///
+/// Bias samples are averaged per temperature bin, so that long stationary periods at a
+/// single temperature do not dominate the fit. Outside the learned temperature range
+/// the bias at the closest end of the range is used.
+#[derive(Debug, Clone)]
+pub struct TemperatureBiasModel {
+    config: TemperatureBiasConfig,
+    temperature: Option<f32>,
+    detector: StationaryDetector,
+    bins: BTreeMap<i32, Bin>,
+    fit: Option<Fit>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Bin {
+    temperature_sum: f64,
+    bias_sum: Vector3<f64>,
+    count: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Fit {
+    intercept: Vector3<f64>,
+    slope: Vector3<f64>,
+    min_temperature: f64,
+    max_temperature: f64,
+}
+
+impl TemperatureBiasModel {
+    /// Create an empty model
+    pub fn new(config: TemperatureBiasConfig) -> Self {
+        Self {
+            detector: StationaryDetector::new(config.detector.clone()),
+            config,
+            temperature: None,
+            bins: Default::default(),
+            fit: None,
+        }
+    }
+
+    /// Process an event
+    pub fn update(&mut self, event: &GlassesEvent) {
+        match event {
+            GlassesEvent::Temperature { temperature, .. } => {
+                self.temperature = Some(*temperature);
+            }
+            GlassesEvent::AccGyro {
+                accelerometer,
+                gyroscope,
+                timestamp,
+            } => self.update_block(accelerometer, gyroscope, *timestamp),
+            GlassesEvent::Disconnected => {
+                self.temperature = None;
+                self.detector.reset();
+            }
+            _ => (),
+        }
+    }
+
+    /// Process an event, then subtract the estimated bias from the gyroscope reading
+    /// if it is an [`GlassesEvent::AccGyro`] event
+    pub fn compensate(&mut self, event: &mut GlassesEvent) {
+        self.update(event);
+        if let GlassesEvent::AccGyro { gyroscope, .. } = event {
+            if let Some(bias) = self.bias() {
+                *gyroscope -= bias;
+            }
+        }
+    }
+
+    /// Estimated bias at the last received temperature, in rad/s
+    pub fn bias(&self) -> Option<Vector3<f32>> {
+        self.bias_at(self.temperature?)
+    }
+
+    /// Estimated bias at the specified temperature (Celsius), in rad/s
+    pub fn bias_at(&self, temperature: f32) -> Option<Vector3<f32>> {
+        let fit = self.fit.as_ref()?;
+        let temperature = (temperature as f64).clamp(fit.min_temperature, fit.max_temperature);
+        Some((fit.intercept + fit.slope * temperature).map(|c| c as f32))
+    }
+
+    /// The temperature range (Celsius) the model has bias samples for
+    pub fn learned_range(&self) -> Option<(f32, f32)> {
+        let fit = self.fit.as_ref()?;
+        Some((fit.min_temperature as f32, fit.max_temperature as f32))
+    }
+
+    fn update_block(&mut self, accelerometer: &Vector3<f32>, gyroscope: &Vector3<f32>, ts: u64) {
+        let Some(temperature) = self.temperature else {
+            return;
+        };
+        let Some(block) = self.detector.add_sample(accelerometer, gyroscope, ts) else {
+            return;
+        };
+        if block.stationary {
+            let bin = self
+                .bins
+                .entry((temperature / self.config.temperature_resolution).round() as i32)
+                .or_default();
+            bin.temperature_sum += temperature as f64;
+            bin.bias_sum += block.mean_gyroscope;
+            bin.count += 1;
+            self.refit();
+        }
+    }
+
+    /// Least squares line fit on the bin averages, each bin having the same weight
+    fn refit(&mut self) {
+        let points: Vec<(f64, Vector3<f64>)> = self
+            .bins
+            .values()
+            .map(|bin| {
+                let n = bin.count as f64;
+                (bin.temperature_sum / n, bin.bias_sum / n)
+            })
+            .collect();
+        let n = points.len() as f64;
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_bias = points.iter().map(|(_, b)| b).sum::<Vector3<f64>>() / n;
+        let var_t = points
+            .iter()
+            .map(|(t, _)| (t - mean_t).powi(2))
+            .sum::<f64>();
+        let slope = if var_t > 1e-6 {
+            points
+                .iter()
+                .map(|(t, b)| (b - mean_bias) * (t - mean_t))
+                .sum::<Vector3<f64>>()
+                / var_t
+        } else {
+            Vector3::zeros()
+        };
+        self.fit = Some(Fit {
+            intercept: mean_bias - slope * mean_t,
+            slope,
+            min_temperature: points.iter().map(|(t, _)| *t).fold(f64::INFINITY, f64::min),
+            max_temperature: points
+                .iter()
+                .map(|(t, _)| *t)
+                .fold(f64::NEG_INFINITY, f64::max),
+        });
+    }
+}
+
+impl Default for TemperatureBiasModel {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stationary::tests::imu_sample;
+
+    #[test]
+    fn test_learns_linear_bias() {
+        let true_bias = |t: f32| {
+            Vector3::new(0.002, -0.001, 0.0005) * (t - 25.0) + Vector3::new(0.003, 0.001, -0.002)
+        };
+        let mut model = TemperatureBiasModel::default();
+        // Warming up from 25 to 35 degrees in 10 minutes, sampled at 100Hz, with
+        // the glasses moving in every other 10 second period.
+        for i in 0..60_000u64 {
+            let temperature = 25.0 + i as f32 / 6000.0;
+            if i % 100 == 0 {
+                model.update(&GlassesEvent::Temperature {
+                    temperature,
+                    timestamp: i * 10_000,
+                });
+            }
+            model.update(&imu_sample(i, (i / 1000) % 2 == 1, true_bias(temperature)));
+        }
+        let (min, max) = model.learned_range().unwrap();
+        assert!(min < 26.0 && max > 34.0);
+        for t in [26.0, 30.0, 34.0] {
+            assert!((model.bias_at(t).unwrap() - true_bias(t)).amax() < 2e-4);
+        }
+        // Outside of the learned range, the closest value is used
+        assert_eq!(model.bias_at(50.0), model.bias_at(40.0));
+    }
+}
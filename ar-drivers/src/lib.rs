@@ -19,9 +19,14 @@
 //! }
 //! ```
 //!
-//! As opposed to e.g. Rokid's own API, this is all that you get, since this is what comes
-//! out of the hardware. To get quaternions, you should probably use a lib that implements
-//! Madgwicks algorithm or a proper EKF. One good choice is the `eskf` crate.
+//! To choose between multiple connected glasses, use [`list_glasses`] and [`open_glasses`],
+//! or [`GlassesSelector`].
+//!
+//! As opposed to e.g. Rokid's own API, this is mostly what comes out of the hardware.
+//! To get quaternions, use one of the built-in [`Fusion`] implementations (see
+//! [`any_fusion_with`], [`madgwick::Madgwick`] and [`eskf::Eskf`]). The filters do not own
+//! the glasses: they are fed with [`Fusion::ingest`], so the events can also come from
+//! a recording or another thread.
 //!
 //! ## Feature flags
 //!
@@ -33,24 +38,66 @@
 //!
 //! All of them are enabled by default, which may bring in some unwanted dependencies if you
 //! only want to support a specific type.
+//!
+//! The `tokio` feature (not enabled by default) adds [`stream::GlassesStream`], an async
+//! [`futures_core::Stream`] interface for glasses events.
+//!
+//! The `simulated` feature (not enabled by default) adds [`simulated::SimulatedGlasses`],
+//! a fake device producing synthetic sensor data, for development without hardware.
+//! It also adds [`fusion_benchmark`], for comparing the [`Fusion`] implementations against
+//! the simulated ground truth.
+
+use std::{ops::RangeInclusive, time::Instant};
 
 use nalgebra::{Isometry3, Matrix3, UnitQuaternion, Vector2, Vector3};
 
-use crate::naive_cf::NaiveCF;
+use crate::{
+    clock_sync::ClockSync,
+    eskf::{Eskf, EskfConfig},
+    factory_calibration::CalibrationError,
+    madgwick::{Madgwick, MadgwickConfig},
+    naive_cf::NaiveCF,
+};
 
+pub mod clock_sync;
+mod config_file;
+#[cfg(not(target_os = "android"))]
+mod discovery;
+pub mod eskf;
+pub mod factory_calibration;
+#[cfg(feature = "simulated")]
+pub mod fusion_benchmark;
 #[cfg(feature = "grawoow")]
 pub mod grawoow;
+pub mod gyro_bias;
 #[cfg(feature = "mad_gaze")]
 pub mod mad_gaze;
+pub mod madgwick;
+pub mod mag_calibration;
 mod naive_cf;
 #[cfg(feature = "nreal")]
 pub mod nreal_air;
 #[cfg(feature = "nreal")]
 pub mod nreal_light;
+pub mod recording;
 #[cfg(feature = "rokid")]
 pub mod rokid;
+#[cfg(feature = "simulated")]
+pub mod simulated;
+pub mod split;
+pub mod stationary;
+#[cfg(feature = "tokio")]
+pub mod stream;
+pub mod supervisor;
+pub mod user_calibration;
 mod util;
 
+#[cfg(not(target_os = "android"))]
+pub use discovery::{
+    list_glasses, open_glasses, open_glasses_with_options, GlassesDescriptor, GlassesModel,
+    GlassesSelector, OpenOptions, Transport,
+};
+
 /// Possible errors resulting from `ar-drivers` API calls
 #[derive(Debug)]
 pub enum Error {
@@ -73,6 +120,12 @@ pub enum Error {
     /// timeout error that is sent (e.g. UsbError can contain a timeout), and
     /// also this is usually a fatal one.
     PacketTimeout,
+    /// A received packet was corrupted (malformed, or its checksum did not match).
+    /// The packet was dropped, see [`ARGlasses::rejected_packets`].
+    ChecksumMismatch,
+    /// The calibration data stored on the glasses could not be parsed. See
+    /// [`CalibrationError`] for the offending value.
+    InvalidCalibration(CalibrationError),
     /// Other fatal error, usually a problem with the library itself, or
     /// a device support issue. File a bug if you encounter this.
     Other(&'static str),
@@ -88,11 +141,11 @@ high level interface of glasses & state estimation, with the following built-in
 - roll/pitch <= acc + gyro (complementary filter)
   - assuming that acc vector always pointed up, spacecraft moving in that direction can create 1G artificial gravity
     - TODO: this obviously assumes no steadily accelerating frame, at which point up d_acc has to be used for correction
-  - TODO: use ESKF (error-state/multiplicatory KF, https://arxiv.org/abs/1711.02508)
+  - or ESKF (error-state/multiplicatory KF, https://arxiv.org/abs/1711.02508), see eskf.rs
 - gyro-yaw <= gyro (integrate over time)
 - mag-yaw <= mag + roll/pitch (arctan)
-  - TODO: mag calibration?
-     (continuous ellipsoid fitting, assuming homogeneous E-M environment & hardpoint-mounted E-M interference)
+  - mag calibration: ellipsoid fitting, assuming homogeneous E-M environment & hardpoint-mounted E-M interference,
+    see mag_calibration.rs (TODO: continuous)
 - yaw <= mag-yaw + gyro-gyro (complementary filter)
   - TODO: use EKF
 
@@ -102,8 +155,10 @@ CAUTION: unlike [[GlassesEvent]], all states & outputs should use FRD reference
 FRD is the standard frame for aerospace, and is also the default frame for NALgebra
 */
 pub trait Fusion: Send {
-    fn glasses(&mut self) -> &mut Box<dyn ARGlasses>;
-    // TODO: only declared mutable as many API of ARGlasses are also mutable
+    /// process a single event. does not depend on any device, so events can come from
+    /// a [`recording::ReplayGlasses`], a network stream, or the app's own reader thread.
+    /// see [`GlassesFusion`] for reading the events from glasses directly
+    fn ingest(&mut self, event: &GlassesEvent);
 
     /// primary estimation output
     /// can be used to convert to Euler angles of different conventions
@@ -112,7 +167,27 @@ pub trait Fusion: Send {
     /// use FRD frame as error in Quaternion is multiplicative & is over-defined
     fn inconsistency(&self) -> f32;
 
-    fn update(&mut self) -> ();
+    /// latest angular velocity (FRD, rad/s), with the estimated bias removed if the
+    /// filter estimates it
+    fn angular_velocity(&self) -> Vector3<f32>;
+
+    /// device timestamp (usecs) of the last processed gyro sample,
+    /// None before the first one
+    fn timestamp(&self) -> Option<u64>;
+
+    /// attitude extrapolated `dt` seconds after the last gyro sample (negative goes back),
+    /// assuming constant angular velocity
+    fn predict(&self, dt: f32) -> UnitQuaternion<f32> {
+        self.attitude_quaternion() * UnitQuaternion::from_scaled_axis(self.angular_velocity() * dt)
+    }
+
+    /// attitude extrapolated to a device timestamp (usecs), see [`Fusion::predict`]
+    fn attitude_at(&self, timestamp: u64) -> UnitQuaternion<f32> {
+        match self.timestamp() {
+            Some(last) => self.predict((timestamp as i64 - last as i64) as f32 / 1_000_000.0),
+            None => self.attitude_quaternion(),
+        }
+    }
 }
 
 impl dyn Fusion {
@@ -126,10 +201,160 @@ impl dyn Fusion {
     }
 }
 
-pub fn any_fusion() -> Result<Box<dyn Fusion>> {
-    // let glasses = any_glasses()?;
-    let glasses = any_glasses()?;
-    Ok(Box::new(NaiveCF::new(glasses)?))
+/// Convert a vector from the RUB frame of [`GlassesEvent`] to the FRD frame of [`Fusion`]
+pub(crate) fn rub_to_frd(v: &Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(-v.z, v.x, -v.y)
+}
+
+/// Inverse of [`rub_to_frd`]
+#[cfg(test)]
+pub(crate) fn frd_to_rub(v: &Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.y, -v.z, -v.x)
+}
+
+/// Convert a rotation between RUB frames (e.g. a device to world attitude) to the
+/// FRD/NED frames of [`Fusion`]
+#[cfg(feature = "simulated")]
+pub(crate) fn rub_to_frd_rotation(rotation: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+    #[rustfmt::skip]
+    let change_of_basis = UnitQuaternion::from_rotation_matrix(
+        &nalgebra::Rotation3::from_matrix_unchecked(Matrix3::new(
+            0.0, 0.0, -1.0,
+            1.0, 0.0, 0.0,
+            0.0, -1.0, 0.0,
+        )),
+    );
+    change_of_basis * rotation * change_of_basis.inverse()
+}
+
+/// Sensor fusion algorithm, see [`FusionAlgorithm::create`] and [`any_fusion_with`]
+#[derive(Debug, Clone, Default)]
+pub enum FusionAlgorithm {
+    /// Simple complementary filter, with magnetometer yaw correction if available
+    #[default]
+    NaiveCF,
+    /// See [`madgwick::Madgwick`]
+    Madgwick(MadgwickConfig),
+    /// See [`eskf::Eskf`]
+    Eskf(EskfConfig),
+}
+
+impl FusionAlgorithm {
+    /// Create the filter, to be fed with [`Fusion::ingest`]
+    pub fn create(self) -> Box<dyn Fusion> {
+        match self {
+            FusionAlgorithm::NaiveCF => Box::new(NaiveCF::new()),
+            FusionAlgorithm::Madgwick(config) => Box::new(Madgwick::new(config)),
+            FusionAlgorithm::Eskf(config) => Box::new(Eskf::new(config)),
+        }
+    }
+}
+
+impl std::fmt::Display for FusionAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FusionAlgorithm::NaiveCF => write!(f, "NaiveCF"),
+            FusionAlgorithm::Madgwick(config) if config.use_magnetometer => {
+                write!(f, "Madgwick+mag")
+            }
+            FusionAlgorithm::Madgwick(_) => write!(f, "Madgwick"),
+            FusionAlgorithm::Eskf(config) if config.use_magnetometer => write!(f, "ESKF+mag"),
+            FusionAlgorithm::Eskf(_) => write!(f, "ESKF"),
+        }
+    }
+}
+
+/// Glasses together with a [`Fusion`] that is fed from them. Dereferences to the
+/// [`Fusion`], so its outputs can be queried directly.
+pub struct GlassesFusion {
+    glasses: Box<dyn ARGlasses>,
+    fusion: Box<dyn Fusion>,
+    clock: ClockSync,
+}
+
+impl GlassesFusion {
+    /// Feed `fusion` with the events of `glasses`
+    pub fn new(glasses: Box<dyn ARGlasses>, fusion: Box<dyn Fusion>) -> Self {
+        Self {
+            glasses,
+            fusion,
+            clock: Default::default(),
+        }
+    }
+
+    /// The glasses the events are read from
+    pub fn glasses(&mut self) -> &mut Box<dyn ARGlasses> {
+        &mut self.glasses
+    }
+
+    /// Read a single event from the glasses, and feed it to the fusion. Blocks until
+    /// the event arrives. [`Error::PacketTimeout`] and [`Error::ChecksumMismatch`] are
+    /// ignored, the next call simply tries again. Other errors (e.g. the glasses were
+    /// disconnected) are returned.
+    pub fn update(&mut self) -> Result<()> {
+        match self.glasses.read_event() {
+            Ok(event) => {
+                if let Some(timestamp) = event.timestamp() {
+                    self.clock.update(timestamp, Instant::now());
+                } else if let GlassesEvent::Connected | GlassesEvent::Disconnected = event {
+                    // The device clock may have been restarted
+                    self.clock.reset();
+                }
+                self.fusion.ingest(&event);
+            }
+            Err(Error::PacketTimeout | Error::ChecksumMismatch) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// Attitude extrapolated to when the display actually shows a frame rendered now,
+    /// using [`ARGlasses::display_delay`]. The time elapsed since the last sample (see
+    /// [`GlassesFusion::clock`]) is added, so it can be called from e.g. a render thread
+    /// any time after [`GlassesFusion::update`].
+    pub fn predict_display(&self) -> UnitQuaternion<f32> {
+        let since_last_sample = self
+            .fusion
+            .timestamp()
+            .and_then(|timestamp| self.clock.host_time(timestamp))
+            .map_or(0.0, |host_time| host_time.elapsed().as_secs_f32());
+        self.fusion
+            .predict(since_last_sample + self.glasses.display_delay() as f32 / 1_000_000.0)
+    }
+
+    /// Host time estimator of the timestamps of the events read by [`GlassesFusion::update`]
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    /// Split into the glasses and the fusion
+    pub fn into_parts(self) -> (Box<dyn ARGlasses>, Box<dyn Fusion>) {
+        (self.glasses, self.fusion)
+    }
+}
+
+impl std::ops::Deref for GlassesFusion {
+    type Target = dyn Fusion;
+
+    fn deref(&self) -> &Self::Target {
+        self.fusion.as_ref()
+    }
+}
+
+impl std::ops::DerefMut for GlassesFusion {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.fusion.as_mut()
+    }
+}
+
+/// Open the first available glasses (see [`any_glasses`]) with the default fusion algorithm
+pub fn any_fusion() -> Result<GlassesFusion> {
+    any_fusion_with(Default::default())
+}
+
+/// Open the first available glasses (see [`any_glasses`]) with the selected fusion algorithm
+pub fn any_fusion_with(algorithm: FusionAlgorithm) -> Result<GlassesFusion> {
+    Ok(GlassesFusion::new(any_glasses()?, algorithm.create()))
 }
 
 impl std::error::Error for Error {
@@ -142,6 +367,7 @@ impl std::error::Error for Error {
             Error::HidError(e) => Some(e),
             #[cfg(feature = "serialport")]
             Error::SerialPortError(e) => Some(e),
+            Error::InvalidCalibration(e) => Some(e),
             _ => None,
         }
     }
@@ -160,6 +386,8 @@ impl std::fmt::Display for Error {
             Error::NotFound => "Glasses not found",
             Error::NotImplemented => "Not implemented for these glasses",
             Error::PacketTimeout => "Packet timeout",
+            Error::ChecksumMismatch => "Packet checksum mismatch",
+            Error::InvalidCalibration(_) => "Invalid factory calibration data",
             Error::Other(s) => s,
         })
     }
@@ -205,8 +433,43 @@ pub enum GlassesEvent {
     ProximityFar,
     /// Ambient light level. Unit is vendor-specific
     AmbientLight(u16),
+    /// Temperature of the IMU. Sent periodically (about once per second)
+    Temperature {
+        /// Temperature in degrees Celsius
+        temperature: f32,
+        /// Timestamp, in device time, in microseconds
+        timestamp: u64,
+    },
     /// V-sync happened on the device
-    VSync,
+    VSync {
+        /// Time of the V-sync, in device time, in microseconds, if the glasses report it
+        timestamp: Option<u64>,
+    },
+    /// Display brightness was changed on the glasses (e.g. with the hardware buttons).
+    /// Same unit as [`ARGlasses::get_brightness`]
+    BrightnessChanged(u8),
+    /// Audio volume was changed on the glasses (e.g. with the hardware buttons).
+    /// Unit is vendor-specific
+    VolumeChanged(u8),
+    /// The glasses were reconnected after a [`GlassesEvent::Disconnected`] event.
+    /// Only sent by [`supervisor::SupervisedGlasses`]
+    Connected,
+    /// The glasses were unplugged or stopped responding.
+    /// Only sent by [`supervisor::SupervisedGlasses`]
+    Disconnected,
+}
+
+impl GlassesEvent {
+    /// Device time of the event, in microseconds, if it has one
+    pub fn timestamp(&self) -> Option<u64> {
+        match *self {
+            GlassesEvent::AccGyro { timestamp, .. }
+            | GlassesEvent::Magnetometer { timestamp, .. }
+            | GlassesEvent::Temperature { timestamp, .. } => Some(timestamp),
+            GlassesEvent::VSync { timestamp } => timestamp,
+            _ => None,
+        }
+    }
 }
 
 /// Display mode used by [`ARGlasses::set_display_mode`]
@@ -226,6 +489,73 @@ pub enum DisplayMode {
     HighRefreshRateSBS,
 }
 
+/// Arrangement of the left and right eye pictures in the video signal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisplayLayout {
+    /// The same picture is shown to both eyes
+    Mirror,
+    /// The left half of the picture is the left eye, the right half is the right eye
+    SideBySide,
+    /// Like [`DisplayLayout::SideBySide`], but with a video signal of half the width,
+    /// which is then stretched horizontally
+    HalfSideBySide,
+}
+
+/// Exact description of a display mode, see [`ARGlasses::supported_display_modes`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DisplayModeInfo {
+    /// Arrangement of the eye pictures
+    pub layout: DisplayLayout,
+    /// Resolution of the picture of a single eye in the video signal, in pixels
+    pub resolution: (u32, u32),
+    /// Refresh rate, in Hz
+    pub refresh_rate: u32,
+}
+
+impl DisplayModeInfo {
+    /// Create a new display mode description
+    pub const fn new(layout: DisplayLayout, width: u32, height: u32, refresh_rate: u32) -> Self {
+        Self {
+            layout,
+            resolution: (width, height),
+            refresh_rate,
+        }
+    }
+
+    /// The [`DisplayMode`] that describes this mode best. Anything above 60Hz is
+    /// considered high refresh rate.
+    pub fn display_mode(&self) -> DisplayMode {
+        match (self.layout, self.refresh_rate > 60) {
+            (DisplayLayout::Mirror, false) => DisplayMode::SameOnBoth,
+            (DisplayLayout::Mirror, true) => DisplayMode::HighRefreshRate,
+            (DisplayLayout::SideBySide, false) => DisplayMode::Stereo,
+            (DisplayLayout::SideBySide, true) => DisplayMode::HighRefreshRateSBS,
+            (DisplayLayout::HalfSideBySide, _) => DisplayMode::HalfSBS,
+        }
+    }
+
+    /// Resolution of the whole video signal, in pixels
+    pub fn signal_resolution(&self) -> (u32, u32) {
+        match self.layout {
+            DisplayLayout::Mirror => self.resolution,
+            DisplayLayout::SideBySide | DisplayLayout::HalfSideBySide => {
+                (self.resolution.0 * 2, self.resolution.1)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for DisplayModeInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (width, height) = self.signal_resolution();
+        write!(
+            f,
+            "{:?} {}x{} @ {}Hz",
+            self.layout, width, height, self.refresh_rate
+        )
+    }
+}
+
 /// Display side used by [`ARGlasses::view_matrix`]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
@@ -244,7 +574,45 @@ pub trait ARGlasses: Send {
     /// Get the display mode of the glasses. See [`DisplayMode`]
     fn get_display_mode(&mut self) -> Result<DisplayMode>;
     /// Set the display mode of the glasses. See [`DisplayMode`]
+    ///
+    /// If more than one supported mode matches `display_mode`, the drivers choose the
+    /// most typical one. Use [`ARGlasses::set_display_mode_info`] to select an exact mode.
     fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()>;
+    /// All display modes supported by the glasses, in the order of preference used by
+    /// [`ARGlasses::set_display_mode`]
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        Vec::new()
+    }
+    /// Get the exact display mode of the glasses
+    fn get_display_mode_info(&mut self) -> Result<DisplayModeInfo> {
+        let display_mode = self.get_display_mode()?;
+        self.supported_display_modes()
+            .into_iter()
+            .find(|mode| mode.display_mode() == display_mode)
+            .ok_or(Error::NotImplemented)
+    }
+    /// Set the exact display mode of the glasses. `display_mode` has to be one of
+    /// [`ARGlasses::supported_display_modes`]
+    fn set_display_mode_info(&mut self, display_mode: DisplayModeInfo) -> Result<()> {
+        if !self.supported_display_modes().contains(&display_mode) {
+            return Err(Error::Other("Display mode not supported"));
+        }
+        self.set_display_mode(display_mode.display_mode())
+    }
+    /// Get the display brightness, in the device-specific range returned by
+    /// [`ARGlasses::brightness_range`]
+    fn get_brightness(&mut self) -> Result<u8> {
+        Err(Error::NotImplemented)
+    }
+    /// Set the display brightness. Values outside [`ARGlasses::brightness_range`]
+    /// are rejected.
+    fn set_brightness(&mut self, _brightness: u8) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+    /// Valid range of the display brightness, lowest to brightest
+    fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        Err(Error::NotImplemented)
+    }
     /// Field of view of the display along the horizontal axis, in radians
     fn display_fov(&self) -> f32;
     /// Transformation from IMU frame to display frame, at the specified
@@ -266,6 +634,71 @@ pub trait ARGlasses: Send {
     /// a relative measure between different glasses.
     /// In the future this may depend on the current display mode.
     fn display_delay(&self) -> u64;
+    /// Number of received packets that were dropped because of a checksum mismatch,
+    /// or because their contents were implausible (for protocols without checksums).
+    fn rejected_packets(&self) -> u64 {
+        0
+    }
+    /// Features supported by the glasses. Does not communicate with the device.
+    ///
+    /// The default implementation only fills in what can be derived from the other
+    /// methods, and assumes host timestamps.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            cameras: self.cameras().map_or(0, |cameras| cameras.len()),
+            brightness_read: self.brightness_range().is_ok(),
+            brightness_write: self.brightness_range().is_ok(),
+            stereo: self
+                .supported_display_modes()
+                .iter()
+                .any(|mode| mode.layout != DisplayLayout::Mirror),
+            ..Default::default()
+        }
+    }
+}
+
+/// Features supported by a specific glasses model, see [`ARGlasses::capabilities`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Sends [`GlassesEvent::Magnetometer`] events
+    pub magnetometer: bool,
+    /// Sends [`GlassesEvent::ProximityNear`] and [`GlassesEvent::ProximityFar`] events
+    pub proximity: bool,
+    /// Sends [`GlassesEvent::AmbientLight`] events (if enabled in [`OpenOptions`])
+    pub ambient_light: bool,
+    /// Sends [`GlassesEvent::VSync`] events (if enabled in [`OpenOptions`])
+    pub vsync: bool,
+    /// Sends [`GlassesEvent::Temperature`] events
+    pub temperature: bool,
+    /// Sends [`GlassesEvent::BrightnessChanged`] and [`GlassesEvent::VolumeChanged`]
+    /// events when the hardware buttons are used
+    pub button_events: bool,
+    /// [`GlassesEvent::KeyPress`] key IDs are below this number. 0 if there are no
+    /// key events.
+    pub key_count: u8,
+    /// Number of cameras described by [`ARGlasses::cameras`]
+    pub cameras: usize,
+    /// [`ARGlasses::get_brightness`] is supported
+    pub brightness_read: bool,
+    /// [`ARGlasses::set_brightness`] is supported
+    pub brightness_write: bool,
+    /// At least one stereo (side-by-side) display mode is supported
+    pub stereo: bool,
+    /// Where the timestamps of the sensor events come from
+    pub timestamps: TimestampSource,
+}
+
+/// See [`Capabilities::timestamps`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampSource {
+    /// Sent by the glasses, measured by the device clock
+    Device,
+    /// Calculated from the number of received samples and the nominal sample rate
+    SampleCount,
+    /// Taken from the host clock when the data is received, so it includes transfer
+    /// jitter
+    #[default]
+    Host,
 }
 
 /// Represents one built-in camera
@@ -298,43 +731,23 @@ pub struct DisplayMatrices {
     pub isometry: Isometry3<f64>,
 }
 
-fn upcast<G: ARGlasses + 'static>(result: Result<G>) -> Result<Box<dyn ARGlasses>> {
-    result.map(|glasses| Box::new(glasses) as Box<dyn ARGlasses>)
-}
-
 /// Convenience function to detect and connect to any of the supported glasses
+///
+/// Opens the first glasses returned by [`list_glasses`] that can be opened. Use
+/// [`GlassesSelector`] to pick specific glasses, or to change [`OpenOptions`].
+///
+/// With the `simulated` feature, [`simulated::SimulatedGlasses`] is returned if the
+/// [`simulated::SIMULATED_ENV_VAR`] environment variable is set, or if no real glasses
+/// were found at all (see [`GlassesSelector::open`]).
 #[cfg(not(target_os = "android"))]
 pub fn any_glasses() -> Result<Box<dyn ARGlasses>> {
-    let glasses_factories: Vec<(&str, fn() -> Result<Box<dyn ARGlasses>>)> = vec![
-        #[cfg(feature = "rokid")]
-        ("RokidAir", || upcast(rokid::RokidAir::new())),
-        #[cfg(feature = "nreal")]
-        ("NrealAir", || upcast(nreal_air::NrealAir::new())),
-        #[cfg(feature = "nreal")]
-        ("NrealLight", || upcast(nreal_light::NrealLight::new())),
-        #[cfg(feature = "grawoow")]
-        ("GrawoowG530", || upcast(grawoow::GrawoowG530::new())),
-        #[cfg(feature = "mad_gaze")]
-        ("MadGazeGlow", || upcast(mad_gaze::MadGazeGlow::new())),
-    ];
-
-    glasses_factories
-        .into_iter()
-        .find_map(|(glasses_type, factory)| {
-            let factory: fn() -> Result<Box<dyn ARGlasses>> = factory;
-
-            factory()
-                .map_err(|e| {
-                    //
-                    println!("can't find {}: {}", glasses_type, e)
-                })
-                .ok()
-                .map(|v| {
-                    println!("found {}", glasses_type);
-                    v
-                })
-        })
-        .ok_or(Error::NotFound)
+    #[cfg(feature = "simulated")]
+    if std::env::var_os(simulated::SIMULATED_ENV_VAR).is_some() {
+        return Ok(Box::new(simulated::SimulatedGlasses::new(
+            Default::default(),
+        )));
+    }
+    GlassesSelector::new().open()
 }
 
 impl From<std::io::Error> for Error {
@@ -364,8 +777,51 @@ impl From<serialport::Error> for Error {
     }
 }
 
+impl From<CalibrationError> for Error {
+    fn from(e: CalibrationError) -> Self {
+        Error::InvalidCalibration(e)
+    }
+}
+
 impl From<&'static str> for Error {
     fn from(e: &'static str) -> Self {
         Error::Other(e)
     }
 }
+
+#[cfg(all(test, feature = "simulated"))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::simulated::{Motion, MotionSegment, SimulatedGlasses, SimulationConfig, Trajectory};
+
+    #[test]
+    fn test_predict_display_adds_elapsed_time() {
+        let glasses = SimulatedGlasses::new(SimulationConfig {
+            trajectory: Trajectory {
+                segments: vec![MotionSegment {
+                    duration: Duration::from_secs(1),
+                    motion: Motion::Rotate(Vector3::new(0.0, 1.0, 0.0)),
+                }],
+                looping: true,
+            },
+            gyro_noise: 0.0,
+            gyro_bias: Vector3::zeros(),
+            realtime: false,
+            ..Default::default()
+        });
+        let mut fusion = GlassesFusion::new(Box::new(glasses), Box::new(NaiveCF::new()));
+        for _ in 0..100 {
+            fusion.update().unwrap();
+        }
+        let display_delay = fusion.glasses().display_delay() as f32 / 1_000_000.0;
+
+        std::thread::sleep(Duration::from_millis(100));
+        // Turning at 1 rad/sec
+        let extra = fusion
+            .predict_display()
+            .angle_to(&fusion.predict(display_delay));
+        assert!((0.1..0.2).contains(&extra), "{extra}");
+    }
+}
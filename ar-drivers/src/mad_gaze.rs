@@ -5,13 +5,18 @@
 //! Mad Gaze Glow AR glasses support. See [`MadGazeGlow`]
 //! It only uses [`serialport`] for communication.
 
-use std::{collections::VecDeque, io::Seek, thread::sleep, time::Duration};
+use std::{collections::VecDeque, io::Seek, ops::RangeInclusive, thread::sleep, time::Duration};
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
-use serialport::{SerialPort, SerialPortType, UsbPortInfo};
+use serialport::{SerialPort, SerialPortInfo, SerialPortType, UsbPortInfo};
 
-use crate::{ARGlasses, DisplayMode, Error, GlassesEvent, Result, Side};
+use crate::{
+    ARGlasses, Capabilities, DisplayLayout, DisplayMode, DisplayModeInfo, Error, GlassesEvent,
+    Result, Side, TimestampSource,
+};
+#[cfg(not(target_os = "android"))]
+use crate::{GlassesDescriptor, GlassesModel, OpenOptions, Transport};
 
 /*
         Sensor axes:
@@ -50,7 +55,9 @@ pub struct MadGazeGlow {
     serial: SerialFraming,
     timestamp: u64,
     last_magnetometer_timestamp: u64,
+    last_temperature_timestamp: Option<u64>,
     pending_events: VecDeque<GlassesEvent>,
+    brightness: Option<u8>,
 }
 
 impl ARGlasses for MadGazeGlow {
@@ -68,6 +75,13 @@ impl ARGlasses for MadGazeGlow {
                 self.update_ak09911()?;
                 self.last_magnetometer_timestamp = self.timestamp;
             }
+            if self
+                .last_temperature_timestamp
+                .is_none_or(|last| last + TEMPERATURE_PERIOD < self.timestamp)
+            {
+                self.update_bmi160_temperature()?;
+                self.last_temperature_timestamp = Some(self.timestamp);
+            }
             self.update_bmi160()?;
         }
     }
@@ -95,6 +109,27 @@ impl ARGlasses for MadGazeGlow {
         }
     }
 
+    /// The brightness cannot be queried from the glasses, so this returns the last value
+    /// set through [`ARGlasses::set_brightness`], or [`Error::NotImplemented`] before that.
+    fn get_brightness(&mut self) -> Result<u8> {
+        self.brightness.ok_or(Error::NotImplemented)
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        self.set_sceen_brightness(brightness)
+    }
+
+    fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        Ok(1..=7)
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        vec![
+            DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+            DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+        ]
+    }
+
     fn display_fov(&self) -> f32 {
         // The 23.5 degrees here is an actual measurement result
         //
@@ -118,6 +153,17 @@ impl ARGlasses for MadGazeGlow {
         "Mad Gaze Glow"
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            magnetometer: true,
+            temperature: true,
+            brightness_write: true,
+            stereo: true,
+            timestamps: TimestampSource::SampleCount,
+            ..Default::default()
+        }
+    }
+
     fn display_delay(&self) -> u64 {
         // TODO: never actuallz calibrated
         15000
@@ -131,16 +177,36 @@ const BMI160_ADDRESS: u8 = 104;
 
 // In useconds
 const MAGNETOMETER_PERIOD: u64 = 50000;
+const TEMPERATURE_PERIOD: u64 = 1000000;
 
 impl MadGazeGlow {
     /// Find a connected Mad Gaze Glow device and connect to it.
     /// Only one instance should be alive at a time.
     pub fn new() -> Result<Self> {
+        let port = SerialFraming::available_ports()?
+            .into_iter()
+            .next()
+            .ok_or(Error::NotFound)?;
+        Self::new_common(SerialFraming::open(&port.port_name)?)
+    }
+
+    /// Connect to the device found by [`crate::list_glasses`]
+    ///
+    /// [`OpenOptions::read_timeout`] is not supported: events are read in batches over a
+    /// serial line with its own fixed timeout.
+    #[cfg(not(target_os = "android"))]
+    pub fn open(descriptor: &GlassesDescriptor, _options: &OpenOptions) -> Result<Self> {
+        Self::new_common(SerialFraming::open(&descriptor.bus_path)?)
+    }
+
+    fn new_common(serial: SerialFraming) -> Result<Self> {
         let mut result = Self {
-            serial: SerialFraming::new()?,
+            serial,
             pending_events: Default::default(),
             timestamp: 0,
             last_magnetometer_timestamp: 0,
+            last_temperature_timestamp: None,
+            brightness: None,
         };
         result.init_ak09911()?;
         result.init_bmi160()?;
@@ -149,9 +215,13 @@ impl MadGazeGlow {
 
     /// Set the screen brightness. Allowed values are between 1-7
     pub fn set_sceen_brightness(&mut self, brightness: u8) -> Result<()> {
+        if !(1..=7).contains(&brightness) {
+            return Err(Error::Other("Brightness out of range"));
+        }
         let command = [brightness];
         let result = self.serial.do_command(b"SLB", &command)?;
         if result == [0] {
+            self.brightness = Some(brightness);
             Ok(())
         } else {
             Err(Error::Other("Invalid answer to the SLB command"))
@@ -240,6 +310,20 @@ impl MadGazeGlow {
         Ok(())
     }
 
+    fn update_bmi160_temperature(&mut self) -> Result<()> {
+        // 0 is 23 degrees, and 1 LSB is 1/512 degrees
+        let read_result = self.read_i2c(BMI160_ADDRESS, 0x20, 2)?;
+        let raw = std::io::Cursor::new(read_result).read_i16::<LittleEndian>()?;
+        // Invalid value, sent when the sensor is not ready yet
+        if raw != -32768 {
+            self.pending_events.push_back(GlassesEvent::Temperature {
+                temperature: 23.0 + raw as f32 / 512.0,
+                timestamp: self.timestamp,
+            });
+        }
+        Ok(())
+    }
+
     fn update_bmi160(&mut self) -> Result<()> {
         // At more than 2 the data cannot be read at 100Hz
         const MAX_ENTRIES: u8 = 2;
@@ -285,25 +369,27 @@ struct SerialFraming {
 }
 
 impl SerialFraming {
-    pub fn new() -> Result<Self> {
-        let ports = serialport::available_ports()?;
-        let ports: Vec<_> = ports
+    const VID: u16 = 1204;
+    const PID: u16 = 2;
+
+    pub fn available_ports() -> Result<Vec<SerialPortInfo>> {
+        Ok(serialport::available_ports()?
             .into_iter()
             .filter(|p| {
                 matches!(
                     p.port_type,
                     SerialPortType::UsbPort(UsbPortInfo {
-                        vid: 1204,
-                        pid: 2,
+                        vid: Self::VID,
+                        pid: Self::PID,
                         ..
                     })
                 )
             })
-            .collect();
-        if ports.is_empty() {
-            return Err(Error::NotFound);
-        }
-        let port = serialport::new(&ports[0].port_name, 921600)
+            .collect())
+    }
+
+    pub fn open(port_name: &str) -> Result<Self> {
+        let port = serialport::new(port_name, 921600)
             .data_bits(serialport::DataBits::Eight)
             .stop_bits(serialport::StopBits::One)
             .parity(serialport::Parity::None)
@@ -375,3 +461,21 @@ impl SerialFraming {
         .collect()
     }
 }
+
+/// List connected Mad Gaze glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn list() -> Vec<GlassesDescriptor> {
+    SerialFraming::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|port| GlassesDescriptor {
+            model: GlassesModel::MadGazeGlow,
+            vid: SerialFraming::VID,
+            pid: SerialFraming::PID,
+            // The glasses serial can only be queried with a command
+            serial: None,
+            transport: Transport::Serial,
+            bus_path: port.port_name,
+        })
+        .collect()
+}
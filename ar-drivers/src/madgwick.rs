@@ -0,0 +1,256 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Madgwick's gradient descent orientation filter. See [`Madgwick`]
+
+use nalgebra::{Matrix3x4, Quaternion, UnitQuaternion, Vector3, Vector4};
+
+use crate::{rub_to_frd, Fusion, GlassesEvent};
+
+/// Settings of [`Madgwick`]
+#[derive(Debug, Clone)]
+pub struct MadgwickConfig {
+    /// Gain of the correction step, in rad/s. Higher values converge faster and drift
+    /// less, but let more accelerometer (and magnetometer) noise through.
+    pub beta: f32,
+    /// Also correct the yaw with the magnetometer (MARG mode). The magnetometer has
+    /// to be calibrated (at least for hard iron distortion), otherwise the yaw is
+    /// pulled in the wrong direction. Ignored for glasses without a magnetometer.
+    pub use_magnetometer: bool,
+}
+
+impl Default for MadgwickConfig {
+    fn default() -> Self {
+        Self {
+            beta: 0.1,
+            use_magnetometer: false,
+        }
+    }
+}
+
+/// Quaternion based orientation filter, as described in Sebastian Madgwick's
+/// "An efficient orientation filter for inertial and inertial/magnetic sensor arrays".
+///
+/// The gyroscope readings are integrated, and the result is corrected with a
+/// gradient descent step towards the attitude where the accelerometer points up
+/// (and, in MARG mode, the magnetometer points north), with a rate of
+/// [`MadgwickConfig::beta`].
+///
+/// Like every [`Fusion`], the attitude is in the FRD frame. Since it is never
+/// converted to Euler angles internally, there is no gimbal lock.
+///
+/// [`Fusion::inconsistency`] is the (low pass filtered) angle between the measured
+/// and the estimated gravity direction, in radians.
+pub struct Madgwick {
+    config: MadgwickConfig,
+    attitude: UnitQuaternion<f32>,
+    /// Last gyroscope reading (FRD)
+    angular_velocity: Vector3<f32>,
+    /// Last magnetometer reading (FRD), only stored in MARG mode
+    magnetometer: Option<Vector3<f32>>,
+    last_timestamp: Option<u64>,
+    inconsistency: f32,
+}
+
+impl Madgwick {
+    /// Accelerometer readings with a smaller norm (in m/s^2) are considered free fall,
+    /// and are not used for correction
+    const MIN_ACCELERATION: f32 = 1.0;
+    /// Larger gaps (in usecs) between gyro samples are not integrated
+    const MAX_SAMPLE_GAP: u64 = 1_000_000;
+    const INCONSISTENCY_DECAY: f32 = 0.9;
+
+    /// Create the filter, starting from the identity attitude
+    pub fn new(config: MadgwickConfig) -> Self {
+        Self {
+            config,
+            attitude: UnitQuaternion::identity(),
+            angular_velocity: Vector3::zeros(),
+            magnetometer: None,
+            last_timestamp: None,
+            inconsistency: 0.0,
+        }
+    }
+
+    /// The settings used by the filter
+    pub fn config(&self) -> &MadgwickConfig {
+        &self.config
+    }
+
+    fn update_imu(&mut self, acc: &Vector3<f32>, gyro: &Vector3<f32>, timestamp: u64) {
+        self.angular_velocity = *gyro;
+        let dt = match self.last_timestamp.replace(timestamp) {
+            Some(last) if timestamp > last && timestamp - last < Self::MAX_SAMPLE_GAP => {
+                (timestamp - last) as f32 / 1_000_000.0
+            }
+            _ => return,
+        };
+        let q = self.attitude.into_inner();
+        let mut q_dot = q * Quaternion::from_imag(*gyro) * 0.5;
+        if let Some(step) = self.correction_step(acc) {
+            q_dot -= step * self.config.beta;
+        }
+        self.attitude = UnitQuaternion::from_quaternion(q + q_dot * dt);
+    }
+
+    /// Normalized gradient of the error function, or None if there is nothing to correct
+    fn correction_step(&mut self, acc: &Vector3<f32>) -> Option<Quaternion<f32>> {
+        if acc.norm() < Self::MIN_ACCELERATION {
+            return None;
+        }
+        // The accelerometer feels the reaction to gravity, so "down" is the opposite
+        let down = -acc.normalize();
+        let q = self.attitude.quaternion();
+        let (w, x, y, z) = (q.w, q.i, q.j, q.k);
+
+        // The estimated direction of gravity (0, 0, 1) in the sensor frame,
+        // minus the measured direction
+        let f_g = Vector3::new(
+            2.0 * (x * z - w * y),
+            2.0 * (w * x + y * z),
+            1.0 - 2.0 * (x * x + y * y),
+        ) - down;
+        #[rustfmt::skip]
+        let j_g = Matrix3x4::new(
+            -2.0 * y, 2.0 * z, -2.0 * w, 2.0 * x,
+            2.0 * x, 2.0 * w, 2.0 * z, 2.0 * y,
+            0.0, -4.0 * x, -4.0 * y, 0.0,
+        );
+        let mut gradient: Vector4<f32> = j_g.transpose() * f_g;
+        // f_g is the chord between two unit vectors
+        let angle = 2.0 * (f_g.norm() / 2.0).min(1.0).asin();
+        self.inconsistency = self.inconsistency * Self::INCONSISTENCY_DECAY
+            + angle * (1.0 - Self::INCONSISTENCY_DECAY);
+
+        if let Some(m) = self
+            .magnetometer
+            .and_then(|m| m.try_normalize(f32::EPSILON))
+        {
+            // Reference field: the measured one in the world frame, rotated to the north,
+            // so that only the yaw is corrected
+            let h = self.attitude * m;
+            let (bx, bz) = ((h.x * h.x + h.y * h.y).sqrt(), h.z);
+            let f_b = Vector3::new(
+                bx * (1.0 - 2.0 * (y * y + z * z)) + 2.0 * bz * (x * z - w * y),
+                2.0 * bx * (x * y - w * z) + 2.0 * bz * (w * x + y * z),
+                2.0 * bx * (x * z + w * y) + bz * (1.0 - 2.0 * (x * x + y * y)),
+            ) - m;
+            #[rustfmt::skip]
+            let j_b = Matrix3x4::new(
+                -2.0 * bz * y, 2.0 * bz * z, -4.0 * bx * y - 2.0 * bz * w, -4.0 * bx * z + 2.0 * bz * x,
+                -2.0 * bx * z + 2.0 * bz * x, 2.0 * bx * y + 2.0 * bz * w, 2.0 * bx * x + 2.0 * bz * z, -2.0 * bx * w + 2.0 * bz * y,
+                2.0 * bx * y, 2.0 * bx * z - 4.0 * bz * x, 2.0 * bx * w - 4.0 * bz * y, 2.0 * bx * x,
+            );
+            gradient += j_b.transpose() * f_b;
+        }
+
+        let gradient = gradient.try_normalize(f32::EPSILON)?;
+        Some(Quaternion::new(
+            gradient[0],
+            gradient[1],
+            gradient[2],
+            gradient[3],
+        ))
+    }
+}
+
+impl Fusion for Madgwick {
+    fn ingest(&mut self, event: &GlassesEvent) {
+        match event {
+            GlassesEvent::AccGyro {
+                accelerometer,
+                gyroscope,
+                timestamp,
+            } => self.update_imu(
+                &rub_to_frd(accelerometer),
+                &rub_to_frd(gyroscope),
+                *timestamp,
+            ),
+            GlassesEvent::Magnetometer { magnetometer, .. } if self.config.use_magnetometer => {
+                self.magnetometer = Some(rub_to_frd(magnetometer));
+            }
+            GlassesEvent::Disconnected => {
+                // Timestamps may restart, and the magnetometer reading is stale
+                self.last_timestamp = None;
+                self.magnetometer = None;
+            }
+            _ => (),
+        }
+    }
+
+    fn attitude_quaternion(&self) -> UnitQuaternion<f32> {
+        self.attitude
+    }
+
+    fn inconsistency(&self) -> f32 {
+        self.inconsistency
+    }
+
+    fn angular_velocity(&self) -> Vector3<f32> {
+        self.angular_velocity
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.last_timestamp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frd_to_rub;
+
+    /// Feed 20 seconds of stationary readings at 100Hz
+    fn converge(fusion: &mut Madgwick, truth: &UnitQuaternion<f32>) {
+        let gravity = Vector3::new(0.0, 0.0, -9.81);
+        let field = Vector3::new(20.0, 0.0, 44.0);
+        for i in 0..2000 {
+            let timestamp = i * 10_000;
+            fusion.ingest(&GlassesEvent::Magnetometer {
+                magnetometer: frd_to_rub(&(truth.inverse() * field)),
+                timestamp,
+            });
+            fusion.ingest(&GlassesEvent::AccGyro {
+                accelerometer: frd_to_rub(&(truth.inverse() * gravity)),
+                gyroscope: Vector3::zeros(),
+                timestamp,
+            });
+        }
+    }
+
+    #[test]
+    fn test_converges_to_true_attitude() {
+        let truth = UnitQuaternion::from_euler_angles(0.3, -0.2, 1.0);
+        let mut imu_only = Madgwick::new(Default::default());
+        converge(&mut imu_only, &truth);
+        let (roll, pitch, _) = imu_only.attitude_quaternion().euler_angles();
+        assert!((roll - 0.3).abs() < 0.01 && (pitch + 0.2).abs() < 0.01);
+        assert!(imu_only.inconsistency() < 0.01);
+
+        let mut marg = Madgwick::new(MadgwickConfig {
+            use_magnetometer: true,
+            ..Default::default()
+        });
+        converge(&mut marg, &truth);
+        assert!(marg.attitude_quaternion().angle_to(&truth) < 0.01);
+    }
+
+    #[test]
+    fn test_prediction() {
+        let mut fusion = Madgwick::new(Default::default());
+        assert_eq!(fusion.attitude_at(1000), UnitQuaternion::identity());
+        // Turning right (positive yaw in FRD) at 1 rad/s, level
+        for i in 0..100 {
+            fusion.ingest(&GlassesEvent::AccGyro {
+                accelerometer: Vector3::new(0.0, 9.81, 0.0),
+                gyroscope: Vector3::new(0.0, -1.0, 0.0),
+                timestamp: i * 10_000,
+            });
+        }
+        let yaw = |q: UnitQuaternion<f32>| q.euler_angles().2;
+        let current = yaw(fusion.attitude_quaternion());
+        assert!((yaw(fusion.predict(0.1)) - current - 0.1).abs() < 1e-4);
+        assert!((yaw(fusion.attitude_at(990_000 + 20_000)) - current - 0.02).abs() < 1e-4);
+    }
+}
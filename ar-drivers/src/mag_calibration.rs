@@ -0,0 +1,300 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Magnetometer hard and soft iron calibration. See [`MagCalibrator`] and [`MagCalibration`]
+
+use std::path::{Path, PathBuf};
+
+use nalgebra::{DMatrix, DVector, Matrix3, Vector3};
+
+use crate::{
+    config_file::{device_file, ConfigFile},
+    Error, GlassesEvent, Result,
+};
+
+/// Collects magnetometer samples while the user rotates the glasses in every direction,
+/// and fits an ellipsoid on them.
+///
+/// Without distortion, the readings would lie on a sphere centered at the origin.
+/// Magnetized parts of the glasses (hard iron) shift the center, while ferromagnetic
+/// parts and sensor scale errors (soft iron) make it an ellipsoid.
+#[derive(Debug, Clone, Default)]
+pub struct MagCalibrator {
+    samples: Vec<Vector3<f64>>,
+    min: Vector3<f64>,
+    max: Vector3<f64>,
+}
+
+/// Result of [`MagCalibrator::fit`]. All values are in the (RUB) frame of the
+/// [`GlassesEvent::Magnetometer`] readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MagCalibration {
+    /// Hard iron offset, subtracted from the raw readings
+    pub hard_iron: Vector3<f32>,
+    /// Soft iron correction matrix, applied after subtracting the offset
+    pub soft_iron: Matrix3<f32>,
+    /// Magnitude of the corrected readings, in the unit of the raw readings
+    pub field_strength: f32,
+    /// RMS deviation of the corrected magnitudes, relative to [`Self::field_strength`].
+    /// A good fit is below 0.02.
+    pub residual: f32,
+    /// Fraction of directions covered by the samples, see [`MagCalibrator::coverage`]
+    pub coverage: f32,
+}
+
+/// Directions used for [`MagCalibrator::coverage`]: the centers of the faces, edges
+/// and corners of a cube
+fn coverage_directions() -> impl Iterator<Item = Vector3<f64>> {
+    (0..27).filter(|i| *i != 13).map(|i| {
+        Vector3::new(
+            (i % 3) as f64 - 1.0,
+            ((i / 3) % 3) as f64 - 1.0,
+            (i / 9) as f64 - 1.0,
+        )
+    })
+}
+
+impl MagCalibrator {
+    /// Samples closer than this (relative to the field strength) to the previous one
+    /// are dropped, so that holding the glasses still does not bias the fit
+    const MIN_SAMPLE_DISTANCE: f64 = 0.02;
+    const MAX_SAMPLES: usize = 10_000;
+    /// Minimum number of samples for [`MagCalibrator::fit`]
+    pub const MIN_SAMPLES: usize = 50;
+    const NOT_ELLIPSOID: Error = Error::Other("Magnetometer samples do not fit an ellipsoid");
+
+    /// Create an empty calibrator
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Process an event, collecting [`GlassesEvent::Magnetometer`] samples
+    pub fn update(&mut self, event: &GlassesEvent) {
+        if let GlassesEvent::Magnetometer { magnetometer, .. } = event {
+            self.add_sample(magnetometer);
+        }
+    }
+
+    /// Add a raw magnetometer reading
+    pub fn add_sample(&mut self, magnetometer: &Vector3<f32>) {
+        let sample = magnetometer.map(|c| c as f64);
+        if let Some(last) = self.samples.last() {
+            if (sample - last).norm() < sample.norm() * Self::MIN_SAMPLE_DISTANCE
+                || self.samples.len() >= Self::MAX_SAMPLES
+            {
+                return;
+            }
+            self.min = self.min.inf(&sample);
+            self.max = self.max.sup(&sample);
+        } else {
+            self.min = sample;
+            self.max = sample;
+        }
+        self.samples.push(sample);
+    }
+
+    /// Number of collected samples
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Fraction (0..1) of the 26 main directions (as seen from the roughly estimated
+    /// center) that have a sample nearby. Should be close to 1 before fitting.
+    pub fn coverage(&self) -> f32 {
+        let center = (self.min + self.max) / 2.0;
+        let mut covered = [false; 26];
+        for sample in &self.samples {
+            let Some(direction) = (sample - center).try_normalize(f64::EPSILON) else {
+                continue;
+            };
+            let closest = coverage_directions()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| {
+                    a.normalize()
+                        .dot(&direction)
+                        .total_cmp(&b.normalize().dot(&direction))
+                })
+                .map(|(i, _)| i);
+            if let Some(closest) = closest {
+                covered[closest] = true;
+            }
+        }
+        covered.iter().filter(|c| **c).count() as f32 / covered.len() as f32
+    }
+
+    /// Fit an ellipsoid on the collected samples (algebraic least squares)
+    pub fn fit(&self) -> Result<MagCalibration> {
+        if self.samples.len() < Self::MIN_SAMPLES {
+            return Err(Error::Other("Not enough magnetometer samples"));
+        }
+        // Centering and scaling for numerical stability
+        let offset = (self.min + self.max) / 2.0;
+        let scale = (self.max - self.min).amax().max(f64::EPSILON) / 2.0;
+        let points: Vec<_> = self.samples.iter().map(|s| (s - offset) / scale).collect();
+
+        // a*x^2 + b*y^2 + c*z^2 + 2d*xy + 2e*xz + 2f*yz + 2g*x + 2h*y + 2i*z = 1
+        let design = DMatrix::from_fn(points.len(), 9, |row, col| {
+            let p = &points[row];
+            match col {
+                0 => p.x * p.x,
+                1 => p.y * p.y,
+                2 => p.z * p.z,
+                3 => 2.0 * p.x * p.y,
+                4 => 2.0 * p.x * p.z,
+                5 => 2.0 * p.y * p.z,
+                6 => 2.0 * p.x,
+                7 => 2.0 * p.y,
+                _ => 2.0 * p.z,
+            }
+        });
+        let solution = design
+            .svd(true, true)
+            .solve(&DVector::repeat(points.len(), 1.0), 1e-12)
+            .map_err(Error::Other)?;
+        let quadric = Matrix3::new(
+            solution[0],
+            solution[3],
+            solution[4],
+            solution[3],
+            solution[1],
+            solution[5],
+            solution[4],
+            solution[5],
+            solution[2],
+        );
+        let linear = Vector3::new(solution[6], solution[7], solution[8]);
+        let center = -quadric.try_inverse().ok_or(Self::NOT_ELLIPSOID)? * linear;
+        // (p - center)^T * shape * (p - center) = 1
+        let shape = quadric / (1.0 + center.dot(&(quadric * center)));
+        let eigen = shape.symmetric_eigen();
+        if eigen.eigenvalues.min() <= 0.0 {
+            return Err(Self::NOT_ELLIPSOID);
+        }
+        // The radius of the sphere with the same volume
+        let radius = eigen.eigenvalues.product().powf(-1.0 / 6.0);
+        let shape_sqrt = eigen.eigenvectors
+            * Matrix3::from_diagonal(&eigen.eigenvalues.map(f64::sqrt))
+            * eigen.eigenvectors.transpose();
+
+        let soft_iron = shape_sqrt * radius;
+        let hard_iron = center * scale + offset;
+        let field_strength = radius * scale;
+        let residual = (self
+            .samples
+            .iter()
+            .map(|s| ((soft_iron * (s - hard_iron)).norm() / field_strength - 1.0).powi(2))
+            .sum::<f64>()
+            / self.samples.len() as f64)
+            .sqrt();
+        Ok(MagCalibration {
+            hard_iron: hard_iron.map(|c| c as f32),
+            soft_iron: soft_iron.map(|c| c as f32),
+            field_strength: field_strength as f32,
+            residual: residual as f32,
+            coverage: self.coverage(),
+        })
+    }
+}
+
+impl MagCalibration {
+    const INVALID_FILE: &str = "Invalid magnetometer calibration file";
+
+    /// Correct a raw magnetometer reading
+    pub fn apply(&self, magnetometer: &Vector3<f32>) -> Vector3<f32> {
+        self.soft_iron * (magnetometer - self.hard_iron)
+    }
+
+    /// Correct the reading if the event is a [`GlassesEvent::Magnetometer`] event
+    pub fn compensate(&self, event: &mut GlassesEvent) {
+        if let GlassesEvent::Magnetometer { magnetometer, .. } = event {
+            *magnetometer = self.apply(magnetometer);
+        }
+    }
+
+    /// The default location of the calibration file of the glasses with this serial
+    /// number: `$XDG_CONFIG_HOME/ar-drivers/` or `~/.config/ar-drivers/`
+    /// (`%APPDATA%\ar-drivers\` on Windows). `None` if none of these are set.
+    ///
+    /// Note that [`crate::user_calibration::UserCalibration`] stores the magnetometer
+    /// calibration too, and only falls back to this file.
+    pub fn default_path(serial: &str) -> Option<PathBuf> {
+        device_file("mag", serial)
+    }
+
+    /// Save the calibration in a simple `key = values` text format,
+    /// creating the directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = ConfigFile::new();
+        self.write_to(&mut file, "");
+        file.save(path)
+    }
+
+    /// Load a calibration saved with [`MagCalibration::save`]
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::read_from(&ConfigFile::load(path)?, "")?.ok_or(Error::Other(Self::INVALID_FILE))
+    }
+
+    pub(crate) fn write_to(&self, file: &mut ConfigFile, prefix: &str) {
+        file.set_values(&format!("{prefix}hard_iron"), self.hard_iron.as_slice());
+        // Row-major, as it is written in the documentation
+        file.set_values(
+            &format!("{prefix}soft_iron"),
+            self.soft_iron.transpose().as_slice(),
+        );
+        file.set(&format!("{prefix}field_strength"), self.field_strength);
+        file.set(&format!("{prefix}residual"), self.residual);
+        file.set(&format!("{prefix}coverage"), self.coverage);
+    }
+
+    /// `None` if there is no calibration in the file
+    pub(crate) fn read_from(file: &ConfigFile, prefix: &str) -> Result<Option<Self>> {
+        let value =
+            |key: &str, count| file.values(&format!("{prefix}{key}"), count, Self::INVALID_FILE);
+        let Some(hard_iron) = value("hard_iron", 3)? else {
+            return Ok(None);
+        };
+        let required =
+            |key: &str, count| value(key, count)?.ok_or(Error::Other(Self::INVALID_FILE));
+        Ok(Some(Self {
+            hard_iron: Vector3::from_column_slice(&hard_iron),
+            soft_iron: Matrix3::from_row_slice(&required("soft_iron", 9)?),
+            field_strength: required("field_strength", 1)?[0],
+            residual: required("residual", 1)?[0],
+            coverage: required("coverage", 1)?[0],
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_distorted_sphere() {
+        let hard_iron = Vector3::new(10.0, -5.0, 3.0);
+        let distortion = Matrix3::new(1.2, 0.1, 0.0, 0.1, 0.9, 0.05, 0.0, 0.05, 1.0);
+        let mut calibrator = MagCalibrator::new();
+        // Spiral over the whole sphere
+        for i in 0..2000 {
+            let z = i as f32 / 1000.0 - 1.0;
+            let angle = i as f32 * 0.3;
+            let r = (1.0 - z * z).sqrt();
+            let field = Vector3::new(r * angle.cos(), r * angle.sin(), z) * 45.0;
+            calibrator.add_sample(&(distortion * field + hard_iron));
+        }
+        assert!(calibrator.coverage() > 0.99);
+        let calibration = calibrator.fit().unwrap();
+        assert!((calibration.hard_iron - hard_iron).amax() < 0.01);
+        assert!(calibration.residual < 1e-4);
+        let corrected = calibration.apply(&(distortion * Vector3::x() * 45.0 + hard_iron));
+        assert!((corrected.norm() / calibration.field_strength - 1.0).abs() < 1e-3);
+
+        let path = std::env::temp_dir().join("ar_drivers_test_mag_calibration.txt");
+        calibration.save(&path).unwrap();
+        let loaded = MagCalibration::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!((loaded.soft_iron - calibration.soft_iron).amax() < 1e-5);
+        assert_eq!(loaded.hard_iron, calibration.hard_iron);
+    }
+}
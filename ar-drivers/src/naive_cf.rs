@@ -24,92 +24,54 @@
 //!
 //!most glasses have acc & grav/acc readings in 1 bundle, but I prefer not using this assumption and still update them independently
 //!
+//!magnetometer readings (if the glasses have one) correct the yaw the same way: the tilt-compensated
+//!heading of the field is pulled towards the heading of the first reading, so the initial yaw is kept.
+//!the field is only learned once the roll/pitch has converged, so a headset that starts tilted does not
+//!learn a skewed inclination and heading.
+//!readings with a field magnitude or inclination far from the learned values (e.g. near a laptop or
+//!a steel desk) are ignored. if the disturbance is persistent (e.g. the user moved), the values are relearned.
+//!
 
 use nalgebra::{UnitQuaternion, Vector3};
 
-use crate::{ARGlasses, Error, Fusion, GlassesEvent};
-
-#[test]
-pub fn __get_correction() {
-    let acc = Vector3::new(3.0176868, -0.74084723, 9.24847);
-    let axis = Vector3::new(-0.0015257122, -0.9227901, -0.38530007);
-    let angle = 2.262818;
-
-    let rotation = UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), angle);
-
-    let correction = NaiveCF::get_rotation_verified(&acc, &rotation).unwrap();
-
-    println!(
-        "compute: {}, {} => {}",
-        acc.transpose(),
-        rotation,
-        correction
-    );
-}
-
-type Result<T> = std::result::Result<T, Error>;
+use crate::{rub_to_frd, Fusion, GlassesEvent};
 
 pub struct NaiveCF {
-    pub glasses: Box<dyn ARGlasses>,
-
     //estimation
     pub attitude: UnitQuaternion<f32>,
 
     //just old readings
     //prevAcc: (Vector3<f32>, u64),
-    pub prev_gyro: (Vector3<f32>, u64), //FRD
+    pub prev_gyro: Option<(Vector3<f32>, u64)>, //FRD, None before the first reading
     //prevMag: (Vector3<f32>, u64),
     pub inconsistency: f32, //roll, pitch. yaw
+
+    //learned magnetic field in world frame, None before the first magnetometer reading
+    pub mag_reference: Option<MagReference>,
+    pub mag_rejections: u32,         //consecutive disturbed readings
+    pub tilt_converged_samples: u32, //consecutive acc readings with a small correction
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MagReference {
+    pub magnitude: f32,   //same unit as the readings
+    pub inclination: f32, //radians, positive is down
+    pub heading: f32,     //radians, of the first reading, fixed
 }
 
 impl NaiveCF {
-    pub fn new(glasses: Box<dyn ARGlasses>) -> Result<Self> {
-        //let attitude = ;
-        //let prev_gyro = ;
-        let mut fusion = NaiveCF {
-            glasses,
+    pub fn new() -> Self {
+        NaiveCF {
             attitude: UnitQuaternion::identity(),
             // attitude: UnitQuaternion::from_euler_angles(0.0, 0.0, std::f32::consts::PI), // seeing backwards
-            prev_gyro: (Vector3::zeros(), 0),
+            prev_gyro: None,
             inconsistency: 0.0,
-        };
-
-        loop {
-            //wait for the first non-zero gyro reading
-            let next_event = fusion.next_event();
-            match next_event {
-                GlassesEvent::AccGyro {
-                    accelerometer: _,
-                    gyroscope,
-                    timestamp,
-                } => {
-                    //if gyroscope != Vector3::zeros() {
-                    fusion.prev_gyro = (gyroscope, timestamp);
-                    return Ok(fusion);
-                    //}
-                }
-                _ => {}
-            }
+            mag_reference: None,
+            mag_rejections: 0,
+            tilt_converged_samples: 0,
         }
     }
 
-    ///read until next valid event. Blocks.
-    fn next_event(&mut self) -> GlassesEvent {
-        loop {
-            match self.glasses.read_event() {
-                Ok(event) => return event,
-                Err(e) => {
-                    println!("Error reading event: {}", e);
-                }
-            }
-        }
-    }
-
-    fn rub_to_frd(v: &Vector3<f32>) -> Vector3<f32> {
-        let result = Vector3::new(-v.z, v.x, -v.y);
-        result
-    }
-
     const BASE_GRAV_RATIO: f32 = 0.005;
 
     const GYRO_SPEED_IN_TIMESTAMP_FACTOR: f32 = 1000.0 * 1000.0; //microseconds
@@ -117,14 +79,33 @@ impl NaiveCF {
     const INCONSISTENCY_DECAY: f32 = 0.90;
 
     const UP_FRD: Vector3<f32> = Vector3::new(0.0, 0.0, -9.81);
-    //const NORTH_FRD: Vector3<f32> = Vector3::new(0.0, 0.0, -1.0);
+
+    const BASE_MAG_RATIO: f32 = 0.002;
+
+    const MAG_LEARNING_RATE: f32 = 0.01;
+
+    const MAG_MAGNITUDE_TOLERANCE: f32 = 0.15; //relative to the learned magnitude
+
+    const MAG_INCLINATION_TOLERANCE: f32 = 0.1; //radians
+
+    const MAG_RELEARN_REJECTIONS: u32 = 1000; //~10s of continuous disturbance at 100Hz
+
+    const MAG_LEARN_INCONSISTENCY: f32 = 0.0025; //~3 degrees of tilt error, once settled
+
+    const MAG_LEARN_SAMPLES: u32 = 100; //~1s of converged tilt at 100Hz
+                                        //const NORTH_FRD: Vector3<f32> = Vector3::new(0.0, 0.0, -1.0);
 
     //CAUTION: right-multiplication means rotation, unconventionally
 
     fn update_gyro_rub(&mut self, gyro_rub: &Vector3<f32>, t: u64) -> () {
-        let gyro = Self::rub_to_frd(gyro_rub);
+        let gyro = rub_to_frd(gyro_rub);
 
-        let d_t1 = t - self.prev_gyro.1;
+        let Some((_, prev_t)) = self.prev_gyro.filter(|&(_, prev_t)| prev_t < t) else {
+            //first reading, or the timestamps restarted / went backwards: nothing to integrate
+            self.prev_gyro = Some((gyro, t));
+            return;
+        };
+        let d_t1 = t - prev_t;
         let d_t1_f = d_t1 as f32 / Self::GYRO_SPEED_IN_TIMESTAMP_FACTOR;
         let d_s1_t1 = d_t1_f * gyro;
 
@@ -133,11 +114,11 @@ impl NaiveCF {
         // self.attitude = (increment.inverse() * self.attitude.inverse()).inverse();
         self.attitude = self.attitude * increment;
 
-        self.prev_gyro = (gyro, t);
+        self.prev_gyro = Some((gyro, t));
     }
 
     fn update_acc(&mut self, acc_rub: &Vector3<f32>, _t: u64) -> () {
-        let acc = Self::rub_to_frd(acc_rub);
+        let acc = rub_to_frd(acc_rub);
 
         if acc.norm() < 1.0 {
             return; //almost in free fall, or acc disabled, do not correct
@@ -153,6 +134,11 @@ impl NaiveCF {
                 let correction = correction_inv.inverse();
                 self.inconsistency =
                     self.inconsistency * Self::INCONSISTENCY_DECAY + correction.angle();
+                if self.inconsistency < Self::MAG_LEARN_INCONSISTENCY {
+                    self.tilt_converged_samples = self.tilt_converged_samples.saturating_add(1);
+                } else {
+                    self.tilt_converged_samples = 0;
+                }
 
                 // self.attitude = (correction_inv * attitude.inverse()).inverse();
                 self.attitude = attitude * correction;
@@ -163,6 +149,64 @@ impl NaiveCF {
         }
     }
 
+    fn update_mag(&mut self, mag_rub: &Vector3<f32>, _t: u64) {
+        let mag = rub_to_frd(mag_rub);
+
+        if mag.norm() < f32::EPSILON {
+            return; //mag disabled
+        }
+
+        //tilt compensation: the field in the (NED) world frame, using the current roll/pitch
+        let world = self.attitude * mag;
+        let horizontal = (world.x * world.x + world.y * world.y).sqrt();
+        let magnitude = world.norm();
+        let inclination = world.z.atan2(horizontal);
+        let heading = world.y.atan2(world.x);
+
+        let reference = match &mut self.mag_reference {
+            Some(reference) => reference,
+            None if self.tilt_converged_samples < Self::MAG_LEARN_SAMPLES => {
+                return; //the tilt compensation is not reliable yet
+            }
+            None => {
+                self.mag_reference = Some(MagReference {
+                    magnitude,
+                    inclination,
+                    heading,
+                });
+                return;
+            }
+        };
+
+        let disturbed = (magnitude - reference.magnitude).abs()
+            > reference.magnitude * Self::MAG_MAGNITUDE_TOLERANCE
+            || (inclination - reference.inclination).abs() > Self::MAG_INCLINATION_TOLERANCE;
+        if disturbed {
+            self.mag_rejections += 1;
+            if self.mag_rejections >= Self::MAG_RELEARN_REJECTIONS {
+                //the environment changed for good, accept the new field but keep the heading
+                reference.magnitude = magnitude;
+                reference.inclination = inclination;
+                self.mag_rejections = 0;
+            }
+            return;
+        }
+        self.mag_rejections = 0;
+        reference.magnitude += (magnitude - reference.magnitude) * Self::MAG_LEARNING_RATE;
+        reference.inclination += (inclination - reference.inclination) * Self::MAG_LEARNING_RATE;
+
+        //wrap to -PI..PI
+        let error = (heading - reference.heading)
+            .sin()
+            .atan2((heading - reference.heading).cos());
+        let correction =
+            UnitQuaternion::from_axis_angle(&Vector3::z_axis(), -error * Self::BASE_MAG_RATIO);
+
+        self.inconsistency = self.inconsistency * Self::INCONSISTENCY_DECAY + correction.angle();
+        //left-multiplication: rotation around the vertical axis of the world frame
+        self.attitude = correction * self.attitude;
+    }
+
     pub fn get_correction(
         acc: &Vector3<f32>,
         rotation: &UnitQuaternion<f32>,
@@ -196,10 +240,6 @@ impl NaiveCF {
 //unsafe impl Sync for NaiveCF {}
 
 impl Fusion for NaiveCF {
-    fn glasses(&mut self) -> &mut Box<dyn ARGlasses> {
-        &mut self.glasses
-    }
-
     fn attitude_quaternion(&self) -> UnitQuaternion<f32> {
         self.attitude
     }
@@ -208,9 +248,16 @@ impl Fusion for NaiveCF {
         self.inconsistency
     }
 
-    fn update(&mut self) -> () {
-        let event = self.next_event();
-        match event {
+    fn angular_velocity(&self) -> Vector3<f32> {
+        self.prev_gyro.map_or(Vector3::zeros(), |(gyro, _)| gyro)
+    }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.prev_gyro.map(|(_, t)| t)
+    }
+
+    fn ingest(&mut self, event: &GlassesEvent) {
+        match *event {
             GlassesEvent::AccGyro {
                 accelerometer,
                 gyroscope,
@@ -221,9 +268,109 @@ impl Fusion for NaiveCF {
                 self.attitude.renormalize();
                 // self.attitude.renormalize_fast(); // TODO: switch to it after rigorous testing
             }
-            _ => {
-                //TODO: add magnetometer event etc
+            GlassesEvent::Magnetometer {
+                magnetometer,
+                timestamp,
+            } => {
+                self.update_mag(&magnetometer, timestamp);
+                self.attitude.renormalize();
             }
+            _ => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_correction() {
+        let acc = Vector3::new(3.0176868, -0.74084723, 9.24847);
+        let rotation = UnitQuaternion::from_axis_angle(
+            &nalgebra::Unit::new_normalize(Vector3::new(-0.0015257122, -0.9227901, -0.38530007)),
+            2.262818,
+        );
+
+        //the full correction moves the expected gravity onto the measured one
+        let full = NaiveCF::get_rotation(&acc, &rotation).unwrap();
+        let corrected = full * rotation * NaiveCF::UP_FRD;
+        assert!((corrected.normalize() - acc.normalize()).norm() < 1e-5);
+
+        //the scaled one only goes part of the way, around the same axis
+        let partial = NaiveCF::get_correction(&acc, &rotation, 0.25).unwrap();
+        assert!((partial.angle() - full.angle() * 0.25).abs() < 1e-5);
+        assert!(
+            (partial.axis().unwrap().into_inner() - full.axis().unwrap().into_inner()).norm()
+                < 1e-4
+        );
+    }
+
+    #[test]
+    fn test_non_monotonic_timestamps() {
+        let mut fusion = NaiveCF::new();
+        let gyro = Vector3::new(0.0, 1.0, 0.0);
+        for t in [1_000_000, 1_010_000, 1_005_000, 0, 10_000] {
+            fusion.update_gyro_rub(&gyro, t);
+        }
+        //only the two forward steps are integrated
+        assert!((fusion.attitude.angle() - 0.02).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_mag_corrects_yaw_drift() {
+        let mut fusion = NaiveCF::new();
+        //north and down in NED, converted to RUB
+        let field = Vector3::new(0.0, -44.0, -20.0);
+
+        //upright and still, the tilt converges immediately
+        for _ in 0..NaiveCF::MAG_LEARN_SAMPLES {
+            fusion.update_acc(&Vector3::new(0.0, 9.81, 0.0), 0);
+        }
+        fusion.update_mag(&field, 0);
+        //simulated gyro drift
+        fusion.attitude = UnitQuaternion::from_euler_angles(0.0, 0.0, 0.2);
+        //a strong disturbance is ignored
+        fusion.update_mag(&(field * 2.0), 0);
+        assert!((fusion.attitude.euler_angles().2 - 0.2).abs() < 1e-6);
+
+        for _ in 0..5000 {
+            fusion.update_mag(&field, 0);
+        }
+        assert!(fusion.attitude.euler_angles().2.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_mag_learned_after_tilt_converges() {
+        let mut fusion = NaiveCF::new();
+        //north and down in NED
+        let field_ned = Vector3::new(20.0, 0.0, 44.0);
+        //still, rolled and pitched, while the filter starts upright
+        let pose = UnitQuaternion::from_euler_angles(0.5, 0.3, 0.0);
+        let acc = crate::frd_to_rub(&(pose.inverse() * NaiveCF::UP_FRD));
+        let mag = crate::frd_to_rub(&(pose.inverse() * field_ned));
+
+        for i in 0..1000 {
+            let timestamp = i * 10_000;
+            fusion.ingest(&GlassesEvent::AccGyro {
+                accelerometer: acc,
+                gyroscope: Vector3::zeros(),
+                timestamp,
+            });
+            fusion.ingest(&GlassesEvent::Magnetometer {
+                magnetometer: mag,
+                timestamp,
+            });
+        }
+
+        let (roll, pitch, yaw) = fusion.attitude.euler_angles();
+        assert!((roll - 0.5).abs() < 0.02 && (pitch - 0.3).abs() < 0.02);
+        //the field was learned in the converged pose. learned from the first reading, the
+        //heading would be off by more than a radian and pull the yaw with it
+        let reference = fusion.mag_reference.unwrap();
+        assert!((reference.inclination - 44.0f32.atan2(20.0)).abs() < 0.05);
+        assert!(reference.heading.abs() < 0.2);
+        //only off by the yaw picked up while the tilt converged
+        assert!(yaw.abs() < 0.2);
+    }
+}
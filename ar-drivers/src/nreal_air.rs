@@ -8,16 +8,21 @@
 //! Nreal Air AR glasses support. See [`NrealAir`]
 //! It only uses [`hidapi`] for communication.
 
-use std::collections::VecDeque;
+use std::{collections::VecDeque, ops::RangeInclusive};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 use hidapi::{HidApi, HidDevice};
-use nalgebra::{Isometry3, Matrix3, Quaternion, Translation3, UnitQuaternion, Vector3};
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
 use tinyjson::JsonValue;
 
 use crate::{
-    util::crc32_adler, ARGlasses, DisplayMatrices, DisplayMode, Error, GlassesEvent, Result, Side,
+    factory_calibration::FactoryCalibration,
+    util::{crc32_adler, display_mode_id, display_mode_info_by_id},
+    ARGlasses, Capabilities, DisplayLayout, DisplayMatrices, DisplayMode, DisplayModeInfo, Error,
+    GlassesEvent, Result, Side, TimestampSource,
 };
+#[cfg(not(target_os = "android"))]
+use crate::{GlassesDescriptor, GlassesModel, OpenOptions, Transport};
 
 /// The main structure representing a connected Nreal Air glasses
 pub struct NrealAir {
@@ -25,10 +30,76 @@ pub struct NrealAir {
     device: HidDevice,
     pending_packets: VecDeque<McuPacket>,
     imu_device: ImuDevice,
+    rejected_packets: u64,
 }
 
 const COMMAND_TIMEOUT: i32 = 1000;
 const IMU_TIMEOUT: i32 = 250;
+const MAX_BRIGHTNESS: u8 = 7;
+
+/// Firmware display mode IDs of the original Air, in order of preference
+const AIR_DISPLAY_MODES: &[(u8, DisplayModeInfo)] = &[
+    (
+        1,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+    ),
+    (
+        3,
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+    ),
+    // Horizontally stretched SBS
+    (
+        8,
+        DisplayModeInfo::new(DisplayLayout::HalfSideBySide, 960, 1080, 60),
+    ),
+    (
+        5,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 72),
+    ),
+    (
+        4,
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 72),
+    ),
+];
+
+/// Firmware display mode IDs of the Air 2 and Air 2 Pro, in order of preference.
+/// Also contains every known mode ID.
+const AIR_2_DISPLAY_MODES: &[(u8, DisplayModeInfo)] = &[
+    (
+        1,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+    ),
+    (
+        3,
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+    ),
+    (
+        8,
+        DisplayModeInfo::new(DisplayLayout::HalfSideBySide, 960, 1080, 60),
+    ),
+    (
+        11,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 120),
+    ),
+    (
+        10,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 90),
+    ),
+    (
+        5,
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 72),
+    ),
+    (
+        9,
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 90),
+    ),
+    (
+        4,
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 72),
+    ),
+];
+// In useconds
+const TEMPERATURE_PERIOD: u64 = 1_000_000;
 
 const NREAL_VID: u16 = 0x3318;
 const AIR_PID: u16 = 0x0424;
@@ -77,51 +148,66 @@ impl ARGlasses for NrealAir {
     }
 
     fn get_display_mode(&mut self) -> Result<DisplayMode> {
+        Ok(self.get_display_mode_info()?.display_mode())
+    }
+
+    fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
+        self.set_display_mode_id(display_mode_id(self.display_modes(), |mode| {
+            mode.display_mode() == display_mode
+        })?)
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        self.display_modes().iter().map(|(_, mode)| *mode).collect()
+    }
+
+    fn get_display_mode_info(&mut self) -> Result<DisplayModeInfo> {
         let result = self.run_command(McuPacket {
             cmd_id: 0x7,
             ..Default::default()
         })?;
-        match result.get(1) {
-            // Mirror 60Hz
-            Some(1) => Ok(DisplayMode::SameOnBoth),
-            // SBS 60Hz
-            Some(3) => Ok(DisplayMode::Stereo),
-            // SBS 72Hz
-            Some(4) => Ok(DisplayMode::HighRefreshRate),
-            // Mirror 72Hz
-            Some(5) => Ok(DisplayMode::SameOnBoth),
-            // Horizontally stretched SBS, 60Hz
-            Some(8) => Ok(DisplayMode::HalfSBS),
-            // SBS 90Hz
-            Some(9) => Ok(DisplayMode::HighRefreshRate),
-            // Mirror 90Hz
-            Some(10) => Ok(DisplayMode::HighRefreshRate),
-            // Mirror 120Hz
-            Some(11) => Ok(DisplayMode::HighRefreshRate),
-            _ => Err(Error::Other("Unknown display mode")),
-        }
+        let id = *result
+            .get(1)
+            .ok_or(Error::Other("Invalid display mode response"))?;
+        display_mode_info_by_id(AIR_2_DISPLAY_MODES, id)
     }
 
-    fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
-        let display_mode_byte = match display_mode {
-            DisplayMode::SameOnBoth => 1,
-            DisplayMode::HalfSBS => 8,
-            DisplayMode::Stereo => 3,
-            DisplayMode::HighRefreshRate => 11,
-            DisplayMode::HighRefreshRateSBS => 9,
-        };
+    fn set_display_mode_info(&mut self, display_mode: DisplayModeInfo) -> Result<()> {
+        self.set_display_mode_id(display_mode_id(self.display_modes(), |mode| {
+            *mode == display_mode
+        })?)
+    }
+
+    fn get_brightness(&mut self) -> Result<u8> {
         let result = self.run_command(McuPacket {
-            cmd_id: 0x08,
-            data: vec![display_mode_byte],
+            cmd_id: 0x03,
+            ..Default::default()
         })?;
+        result
+            .get(1)
+            .copied()
+            .ok_or(Error::Other("Invalid brightness response"))
+    }
 
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        if brightness > MAX_BRIGHTNESS {
+            return Err(Error::Other("Brightness out of range"));
+        }
+        let result = self.run_command(McuPacket {
+            cmd_id: 0x04,
+            data: vec![brightness],
+        })?;
         if result.first() == Some(&0) {
             Ok(())
         } else {
-            Err(Error::Other("Display mode setting unsuccessful"))
+            Err(Error::Other("Brightness setting unsuccessful"))
         }
     }
 
+    fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        Ok(0..=MAX_BRIGHTNESS)
+    }
+
     // TODO
     fn display_fov(&self) -> f32 {
         // This is a judgement call. The displays have a non-trivial distortion,
@@ -144,13 +230,21 @@ impl ARGlasses for NrealAir {
     }
 
     fn display_matrices(&self) -> Result<(DisplayMatrices, DisplayMatrices)> {
-        self.imu_device.displays.clone().ok_or(Error::NotFound)
+        self.imu_device
+            .calibration
+            .displays
+            .clone()
+            .ok_or(Error::NotFound)
     }
 
     fn display_delay(&self) -> u64 {
         7000
     }
 
+    fn rejected_packets(&self) -> u64 {
+        self.rejected_packets + self.imu_device.rejected_packets
+    }
+
     fn name(&self) -> &'static str {
         match self.model {
             AirModel::Air => "XREAL Air",
@@ -158,6 +252,19 @@ impl ARGlasses for NrealAir {
             AirModel::Air2Pro => "XREAL Air 2 Pro",
         }
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            magnetometer: true,
+            temperature: true,
+            key_count: 3,
+            brightness_read: true,
+            brightness_write: true,
+            stereo: true,
+            timestamps: TimestampSource::Device,
+            ..Default::default()
+        }
+    }
 }
 
 impl NrealAir {
@@ -186,12 +293,43 @@ impl NrealAir {
         let (model, device) = open_nreal_endpoint(4)?;
         Self::new_common(model, device, ImuDevice::new()?)
     }
+
+    /// Connect to the device found by [`crate::list_glasses`]
+    #[cfg(not(target_os = "android"))]
+    pub fn open(descriptor: &GlassesDescriptor, options: &OpenOptions) -> Result<Self> {
+        let hidapi = HidApi::new()?;
+        let mcu_info = hidapi
+            .device_list()
+            .find(|d| d.path().to_string_lossy() == descriptor.bus_path)
+            .ok_or(Error::NotFound)?;
+        let model = AirModel::try_from(mcu_info.product_id())?;
+        // The IMU interface is a separate HID device, of the same physical device,
+        // so it has the same USB serial number
+        let mut imu_candidates = hidapi.device_list().filter(|d| {
+            d.vendor_id() == NREAL_VID
+                && d.product_id() == mcu_info.product_id()
+                && d.interface_number() == 3
+                && d.serial_number() == mcu_info.serial_number()
+        });
+        let imu_info = imu_candidates.next().ok_or(Error::NotFound)?;
+        if mcu_info.serial_number().is_none() && imu_candidates.next().is_some() {
+            return Err(Error::Other(
+                "Multiple glasses without serial numbers, cannot pair their interfaces",
+            ));
+        }
+        let mut imu_device = ImuDevice::new_device(imu_info.open_device(&hidapi)?)?;
+        if let Some(read_timeout) = options.read_timeout {
+            imu_device.read_timeout = read_timeout.as_millis() as i32;
+        }
+        Self::new_common(model, mcu_info.open_device(&hidapi)?, imu_device)
+    }
     fn new_common(model: AirModel, device: HidDevice, imu_device: ImuDevice) -> Result<Self> {
         let mut result = Self {
             model,
             device,
             pending_packets: Default::default(),
             imu_device,
+            rejected_packets: 0,
         };
         // Quick check
         result.serial()?;
@@ -204,13 +342,41 @@ impl NrealAir {
         &self.imu_device.config_json
     }
 
+    /// Returns the parsed calibration data stored on the Glasses
+    pub fn factory_calibration(&self) -> &FactoryCalibration {
+        &self.imu_device.calibration
+    }
+
+    fn display_modes(&self) -> &'static [(u8, DisplayModeInfo)] {
+        match self.model {
+            AirModel::Air => AIR_DISPLAY_MODES,
+            AirModel::Air2 | AirModel::Air2Pro => AIR_2_DISPLAY_MODES,
+        }
+    }
+
+    fn set_display_mode_id(&mut self, id: u8) -> Result<()> {
+        let result = self.run_command(McuPacket {
+            cmd_id: 0x08,
+            data: vec![id],
+        })?;
+
+        if result.first() == Some(&0) {
+            Ok(())
+        } else {
+            Err(Error::Other("Display mode setting unsuccessful"))
+        }
+    }
+
     fn read_mcu_packet(&mut self) -> Result<Option<GlassesEvent>> {
         let packet = if let Some(packet) = self.pending_packets.pop_front() {
             packet
-        } else if let Some(packet) = self.read_packet(0)? {
-            packet
         } else {
-            return Ok(None);
+            match self.read_packet(0) {
+                Ok(Some(packet)) => packet,
+                // Corrupted packets are simply dropped
+                Ok(None) | Err(Error::ChecksumMismatch) => return Ok(None),
+                Err(e) => return Err(e),
+            }
         };
         Ok(match packet {
             McuPacket {
@@ -237,9 +403,11 @@ impl NrealAir {
         if packet_size == 0 {
             Ok(None)
         } else {
-            Ok(Some(
-                McuPacket::deserialize(&result).ok_or(Error::Other("Malformed packet received"))?,
-            ))
+            let packet = McuPacket::deserialize(&result);
+            if let Err(Error::ChecksumMismatch) = packet {
+                self.rejected_packets += 1;
+            }
+            packet.map(Some)
         }
     }
 
@@ -266,9 +434,15 @@ impl NrealAir {
 struct ImuDevice {
     device: HidDevice,
     config_json: JsonValue,
-    displays: Option<(DisplayMatrices, DisplayMatrices)>,
+    calibration: FactoryCalibration,
     gyro_bias: Vector3<f32>,
     accelerometer_bias: Vector3<f32>,
+    read_timeout: i32,
+    pending_events: VecDeque<GlassesEvent>,
+    last_magnetometer: Option<Vector3<f32>>,
+    last_temperature_timestamp: Option<u64>,
+    validator: ReportValidator,
+    rejected_packets: u64,
 }
 
 impl ImuDevice {
@@ -286,9 +460,15 @@ impl ImuDevice {
         let mut result = Self {
             device,
             config_json: JsonValue::Null,
-            displays: None,
+            calibration: Default::default(),
             gyro_bias: Default::default(),
             accelerometer_bias: Default::default(),
+            read_timeout: IMU_TIMEOUT,
+            pending_events: Default::default(),
+            last_magnetometer: None,
+            last_temperature_timestamp: None,
+            validator: Default::default(),
+            rejected_packets: 0,
         };
         // Turn off IMU stream while reading config
         result.command(0x19, &[0x0])?;
@@ -301,7 +481,11 @@ impl ImuDevice {
     }
 
     fn read_config(&mut self) -> Result<()> {
-        let len = u32::from_le_bytes(self.command(0x14, &[])?.try_into().unwrap());
+        let len = u32::from_le_bytes(
+            self.command(0x14, &[])?
+                .try_into()
+                .map_err(|_| Error::Other("Invalid glasses config length"))?,
+        );
         let mut config = Vec::new();
         while config.len() < len as usize {
             let mut config_part = self.command(0x15, &[])?;
@@ -316,78 +500,13 @@ impl ImuDevice {
     }
 
     fn parse_config(&mut self) -> Result<()> {
-        // XXX: This will panic if config is not in expected format.
-        //      should probably return Err() instead.
-        self.displays = Self::parse_display_descriptors(&self.config_json["display"]);
-        let cfg = &self.config_json["IMU"]["device_1"];
-        self.accelerometer_bias = Self::parse_vector(&cfg["accel_bias"]).map(|c| c as f32);
-        self.gyro_bias = Self::parse_vector(&cfg["gyro_bias"]).map(|c| c as f32);
+        self.calibration = FactoryCalibration::from_xreal_json(&self.config_json)?;
+        self.accelerometer_bias = self.calibration.accelerometer_bias.map(|c| c as f32);
+        self.gyro_bias = self.calibration.gyro_bias.map(|c| c as f32);
         Ok(())
     }
 
-    fn parse_display_descriptors(json: &JsonValue) -> Option<(DisplayMatrices, DisplayMatrices)> {
-        let resolution = &json["resolution"];
-        let resolution = (
-            *resolution[0].get::<f64>().unwrap() as u32,
-            *resolution[1].get::<f64>().unwrap() as u32,
-        );
-
-        let side_descriptor =
-            |p_cfg: &JsonValue, q_cfg: &JsonValue, k_cfg: &JsonValue| -> DisplayMatrices {
-                let translation = Self::parse_vector(p_cfg);
-                let rotation = UnitQuaternion::from_quaternion(Self::parse_quaternion(q_cfg));
-                DisplayMatrices {
-                    intrinsic_matrix: Self::parse_matrix3(k_cfg),
-                    resolution,
-                    isometry: Translation3::from(translation) * rotation,
-                }
-            };
-        let mut left = side_descriptor(
-            &json["target_p_left_display"],
-            &json["target_q_left_display"],
-            &json["k_left_display"],
-        );
-        let mut right = side_descriptor(
-            &json["target_p_right_display"],
-            &json["target_q_right_display"],
-            &json["k_right_display"],
-        );
-        // The calibration seems to be based on a reference point near the right lens.
-        // We will center the translation component between the displays.
-        let mean = (left.isometry.translation.vector + right.isometry.translation.vector) * 0.5;
-        left.isometry.translation.vector -= mean;
-        right.isometry.translation.vector -= mean;
-        Some((left, right))
-    }
-
-    fn parse_vector(json: &JsonValue) -> Vector3<f64> {
-        Vector3::new(
-            *json[0].get::<f64>().unwrap(),
-            *json[1].get::<f64>().unwrap(),
-            *json[2].get::<f64>().unwrap(),
-        )
-    }
-
-    fn parse_quaternion(json: &JsonValue) -> Quaternion<f64> {
-        Quaternion::new(
-            *json[3].get::<f64>().unwrap(),
-            *json[0].get::<f64>().unwrap(),
-            *json[1].get::<f64>().unwrap(),
-            *json[2].get::<f64>().unwrap(),
-        )
-    }
-
-    fn parse_matrix3(json: &JsonValue) -> Matrix3<f64> {
-        let vals = json
-            .get::<Vec<_>>()
-            .unwrap()
-            .iter()
-            .map(|v| *v.get::<f64>().unwrap())
-            .collect::<Vec<f64>>();
-        Matrix3::from_row_slice(&vals)
-    }
-
-    fn command(&self, cmd_id: u8, data: &[u8]) -> Result<Vec<u8>> {
+    fn command(&mut self, cmd_id: u8, data: &[u8]) -> Result<Vec<u8>> {
         self.device.write(
             &ImuPacket {
                 cmd_id,
@@ -403,32 +522,153 @@ impl ImuDevice {
                 return Err(Error::PacketTimeout);
             }
 
-            if let Some(result) = ImuPacket::deserialize(&data) {
-                return Ok(result.data);
+            match ImuPacket::deserialize(&data) {
+                Ok(Some(result)) => return Ok(result.data),
+                Ok(None) => (),
+                Err(e) => {
+                    if let Error::ChecksumMismatch = e {
+                        self.rejected_packets += 1;
+                    }
+                    return Err(e);
+                }
             }
         }
         Err(Error::Other("Couldn't get acknowledgement to command"))
     }
 
     pub fn read_packet(&mut self) -> Result<GlassesEvent> {
-        loop {
+        while self.pending_events.is_empty() {
             let mut packet_data = [0u8; 0x80];
-            let data_size = self.device.read_timeout(&mut packet_data, IMU_TIMEOUT)?;
+            let data_size = self
+                .device
+                .read_timeout(&mut packet_data, self.read_timeout)?;
             if data_size == 0 {
                 return Err(Error::PacketTimeout);
             }
 
             if packet_data[0] == 1 && packet_data[1] == 2 {
-                return self.parse_report(&packet_data);
+                // Sensor reports have no known checksum, so corrupted ones can only
+                // be recognized by their contents
+                match ImuReport::parse(&packet_data) {
+                    Ok(report) if self.validator.accept(&report) => self.push_report(report),
+                    _ => self.rejected_packets += 1,
+                }
             };
             // Else try again
         }
+        Ok(self.pending_events.pop_front().unwrap())
+    }
+
+    fn push_report(&mut self, report: ImuReport) {
+        self.pending_events.push_back(GlassesEvent::AccGyro {
+            // The bias fields do not correspond to the raw fields, but for some reason
+            // this looks like the correct zero.
+            accelerometer: report.accelerometer
+                + Vector3::new(
+                    self.accelerometer_bias.x,
+                    self.accelerometer_bias.z,
+                    self.accelerometer_bias.y,
+                ),
+            gyroscope: report.gyroscope
+                + Vector3::new(self.gyro_bias.x, self.gyro_bias.z, self.gyro_bias.y),
+            timestamp: report.timestamp,
+        });
+        if self
+            .last_temperature_timestamp
+            .is_none_or(|last| report.timestamp >= last + TEMPERATURE_PERIOD)
+        {
+            self.last_temperature_timestamp = Some(report.timestamp);
+            self.pending_events.push_back(GlassesEvent::Temperature {
+                temperature: report.temperature,
+                timestamp: report.timestamp,
+            });
+        }
+        // The magnetometer is sampled less often than the IMU, the report simply
+        // repeats the last sample in between.
+        if let Some(magnetometer) = report.magnetometer {
+            if self.last_magnetometer != Some(magnetometer) {
+                self.last_magnetometer = Some(magnetometer);
+                self.pending_events.push_back(GlassesEvent::Magnetometer {
+                    magnetometer,
+                    timestamp: report.timestamp,
+                });
+            }
+        }
+    }
+}
+
+/// Plausibility checks of the sensor reports, in place of a checksum
+#[derive(Debug, Default)]
+struct ReportValidator {
+    last_timestamp: Option<u64>,
+    timestamp_rejections: u32,
+}
+
+impl ReportValidator {
+    // Limits well above the ranges of the sensors (2000 deg/s, 16 g, 49 Gauss)
+    const MAX_GYROSCOPE: f32 = 50.0;
+    const MAX_ACCELEROMETER: f32 = 300.0;
+    const MAX_MAGNETOMETER: f32 = 10_000.0;
+    const TEMPERATURE_RANGE: RangeInclusive<f32> = -40.0..=125.0;
+    /// Larger forward jumps of the timestamp (in usecs) are considered corrupted
+    const MAX_TIMESTAMP_STEP: u64 = 1_000_000;
+    /// After this many consecutive reports rejected because of their timestamp,
+    /// the device clock is assumed to have really jumped
+    const MAX_TIMESTAMP_REJECTIONS: u32 = 10;
+
+    fn accept(&mut self, report: &ImuReport) -> bool {
+        // Written so that NaNs and infinities (e.g. from a zero divisor) fail too
+        let in_range = report.gyroscope.norm() <= Self::MAX_GYROSCOPE
+            && report.accelerometer.norm() <= Self::MAX_ACCELEROMETER
+            && report
+                .magnetometer
+                .is_none_or(|m| m.norm() <= Self::MAX_MAGNETOMETER)
+            && Self::TEMPERATURE_RANGE.contains(&report.temperature);
+        if !in_range {
+            return false;
+        }
+        let monotonic = self.last_timestamp.is_none_or(|last| {
+            report.timestamp > last && report.timestamp - last <= Self::MAX_TIMESTAMP_STEP
+        });
+        if !monotonic && self.timestamp_rejections < Self::MAX_TIMESTAMP_REJECTIONS {
+            self.timestamp_rejections += 1;
+            return false;
+        }
+        self.timestamp_rejections = 0;
+        self.last_timestamp = Some(report.timestamp);
+        true
     }
+}
+
+/// Decoded sensor report of the IMU interface, in RUB frame, without calibration
+#[derive(Debug, PartialEq)]
+struct ImuReport {
+    /// Device time in microseconds
+    timestamp: u64,
+    /// Celsius
+    temperature: f32,
+    /// rad/s
+    gyroscope: Vector3<f32>,
+    /// m/s^2
+    accelerometer: Vector3<f32>,
+    /// uT. None if the report contained an invalid scale.
+    magnetometer: Option<Vector3<f32>>,
+}
 
-    fn parse_report(&mut self, packet_data: &[u8]) -> Result<GlassesEvent> {
-        // TODO: This skips over a 2 byte temperature field that may be useful.
-        let mut reader = std::io::Cursor::new(&packet_data[4..]);
+impl ImuReport {
+    // 1 Gauss = 100 uT
+    const GAUSS_TO_UT: f32 = 100.0;
 
+    // Same as the ICM-4xxxx series' data registers
+    const TEMPERATURE_LSB_PER_CELSIUS: f32 = 132.48;
+    const TEMPERATURE_OFFSET: f32 = 25.0;
+
+    fn parse(packet_data: &[u8]) -> Result<Self> {
+        let mut reader = std::io::Cursor::new(&packet_data[2..]);
+
+        let temperature = reader.read_i16::<LittleEndian>()? as f32
+            / Self::TEMPERATURE_LSB_PER_CELSIUS
+            + Self::TEMPERATURE_OFFSET;
         let timestamp = reader.read_u64::<LittleEndian>()? / 1000;
         let gyro_mul = reader.read_u16::<LittleEndian>()? as f32;
         let gyro_div = reader.read_u32::<LittleEndian>()? as f32;
@@ -436,11 +676,9 @@ impl ImuDevice {
         let gyro_y = reader.read_i24::<LittleEndian>()? as f32;
         let gyro_z = reader.read_i24::<LittleEndian>()? as f32;
         let gyroscope = Vector3::new(
-            // The bias fields do not correspond to the raw fields, but for some reason
-            // this looks like the correct zero.
-            (gyro_x * gyro_mul / gyro_div).to_radians() + self.gyro_bias.x,
-            (gyro_y * gyro_mul / gyro_div).to_radians() + self.gyro_bias.z,
-            (gyro_z * gyro_mul / gyro_div).to_radians() + self.gyro_bias.y,
+            (gyro_x * gyro_mul / gyro_div).to_radians(),
+            (gyro_y * gyro_mul / gyro_div).to_radians(),
+            (gyro_z * gyro_mul / gyro_div).to_radians(),
         );
 
         let acc_mul = reader.read_u16::<LittleEndian>()? as f32;
@@ -449,19 +687,36 @@ impl ImuDevice {
         let acc_y = reader.read_i24::<LittleEndian>()? as f32;
         let acc_z = reader.read_i24::<LittleEndian>()? as f32;
         let accelerometer = Vector3::new(
-            // The bias fields do not correspond to the raw fields, but for some reason
-            // this looks like the correct zero.
-            (acc_x * acc_mul / acc_div) * 9.81 + self.accelerometer_bias.x,
-            (acc_y * acc_mul / acc_div) * 9.81 + self.accelerometer_bias.z,
-            (acc_z * acc_mul / acc_div) * 9.81 + self.accelerometer_bias.y,
+            (acc_x * acc_mul / acc_div) * 9.81,
+            (acc_y * acc_mul / acc_div) * 9.81,
+            (acc_z * acc_mul / acc_div) * 9.81,
         );
-        // TODO: magnetometer. It's in the same format, but it's non-trivially
-        //       rotated.
-        // TODO: Check checksum
-        Ok(GlassesEvent::AccGyro {
-            accelerometer,
-            gyroscope,
+
+        // Same structure as above, but big endian, with 16 bit values, in Gauss.
+        let mag_mul = reader.read_i16::<BigEndian>()? as f32;
+        let mag_div = reader.read_i32::<BigEndian>()? as f32;
+        let mag_x = reader.read_i16::<BigEndian>()? as f32;
+        let mag_y = reader.read_i16::<BigEndian>()? as f32;
+        let mag_z = reader.read_i16::<BigEndian>()? as f32;
+        let magnetometer = if mag_div == 0.0 {
+            None
+        } else {
+            let scale = mag_mul / mag_div * Self::GAUSS_TO_UT;
+            // The magnetometer chip is mounted rotated by 90 degrees around the X axis
+            // compared to the IMU: its Y axis points forward, Z points up.
+            Some(Vector3::new(mag_x, mag_z, -mag_y) * scale)
+        };
+
+        // TODO: Check checksum. Sensor reports have one too, but unlike command
+        //       responses, it is not known what it is calculated on (find_report_checksum
+        //       looks for it on real glasses). Until then, ReportValidator filters out
+        //       the corrupted ones.
+        Ok(Self {
             timestamp,
+            temperature,
+            gyroscope,
+            accelerometer,
+            magnetometer,
         })
     }
 }
@@ -489,15 +744,19 @@ unsafe impl bytemuck::Zeroable for McuRawPacket {}
 unsafe impl bytemuck::Pod for McuRawPacket {}
 
 impl McuPacket {
-    fn deserialize(data: &[u8; 0x40]) -> Option<McuPacket> {
+    fn deserialize(data: &[u8; 0x40]) -> Result<McuPacket> {
         let raw_packet: &McuRawPacket = bytemuck::cast_ref(data);
-        if raw_packet.head != 0xfd {
-            return None;
+        let length = raw_packet.length as usize;
+        // A corrupted header is treated the same as a corrupted payload
+        if raw_packet.head != 0xfd || !(17..=data.len() - 5).contains(&length) {
+            return Err(Error::ChecksumMismatch);
         }
-        // TODO: maybe check CRC?
-        Some(McuPacket {
+        if raw_packet.checksum != crc32_adler(&data[5..(5 + length)]) {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(McuPacket {
             cmd_id: raw_packet.cmd_id,
-            data: raw_packet.data[0..(raw_packet.length as usize - 17)].into(),
+            data: raw_packet.data[0..(length - 17)].into(),
         })
     }
 
@@ -540,16 +799,23 @@ unsafe impl bytemuck::Zeroable for ImuRawPacket {}
 unsafe impl bytemuck::Pod for ImuRawPacket {}
 
 impl ImuPacket {
-    fn deserialize(data: &[u8; 0x40]) -> Option<ImuPacket> {
+    /// Returns None for packets that are not command responses (e.g. sensor reports)
+    fn deserialize(data: &[u8; 0x40]) -> Result<Option<ImuPacket>> {
         let raw_packet: &ImuRawPacket = bytemuck::cast_ref(data);
         if raw_packet.head != 0xaa {
-            return None;
+            return Ok(None);
+        }
+        let length = raw_packet.length as usize;
+        if !(3..=data.len() - 5).contains(&length) {
+            return Err(Error::ChecksumMismatch);
+        }
+        if raw_packet.checksum != crc32_adler(&data[5..(5 + length)]) {
+            return Err(Error::ChecksumMismatch);
         }
-        // TODO: maybe check CRC?
-        Some(ImuPacket {
+        Ok(Some(ImuPacket {
             cmd_id: raw_packet.cmd_id,
-            data: raw_packet.data[0..(raw_packet.length as usize - 3)].into(),
-        })
+            data: raw_packet.data[0..(length - 3)].into(),
+        }))
     }
 
     fn serialize(&self) -> Option<[u8; 0x40]> {
@@ -568,6 +834,31 @@ impl ImuPacket {
     }
 }
 
+/// List connected XREAL Air glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn list(hidapi: &HidApi) -> Vec<GlassesDescriptor> {
+    hidapi
+        .device_list()
+        .filter(|d| d.vendor_id() == NREAL_VID && d.interface_number() == 4)
+        .filter_map(|d| {
+            let model = match AirModel::try_from(d.product_id()).ok()? {
+                AirModel::Air => GlassesModel::XrealAir,
+                AirModel::Air2 => GlassesModel::XrealAir2,
+                AirModel::Air2Pro => GlassesModel::XrealAir2Pro,
+            };
+            Some(GlassesDescriptor {
+                model,
+                vid: d.vendor_id(),
+                pid: d.product_id(),
+                // The glasses serial can only be queried with an MCU command
+                serial: None,
+                transport: Transport::Hid,
+                bus_path: d.path().to_string_lossy().into_owned(),
+            })
+        })
+        .collect()
+}
+
 #[cfg(not(target_os = "android"))]
 fn open_nreal_endpoint(interface: i32) -> Result<(AirModel, HidDevice)> {
     let hidapi = HidApi::new()?;
@@ -582,3 +873,210 @@ fn open_nreal_endpoint(interface: i32) -> Result<(AirModel, HidDevice)> {
     }
     Err(Error::NotFound)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sensor reports of a still headset in a 25 uT horizontal, 40 uT downwards field,
+    // facing north. Only the first 0x40 bytes are shown, the rest is zeros.
+    // TODO: these were assembled by hand from the report layout. Replace them with
+    //       reports captured from the glasses in the same poses (see capture_reports),
+    //       and confirm the magnetometer axes, units and temperature scale with them.
+
+    /// Upright, turning left at 15.625 deg/s
+    const UPRIGHT: [u8; 0x40] = [
+        0x01, 0x02, 0x2d, 0x05, 0xc8, 0x92, 0x8e, 0x15, 0xee, 0x03, 0x00, 0x00, 0xd0, 0x07, 0x00,
+        0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x03, 0xe8, 0x00, 0x00, 0x00, 0xfa, 0xfe, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// Rolled 90 degrees to the right (right side down)
+    const ROLLED_RIGHT: [u8; 0x40] = [
+        0x01, 0x02, 0x2d, 0x05, 0x48, 0x29, 0x27, 0x16, 0xee, 0x03, 0x00, 0x00, 0xd0, 0x07, 0x00,
+        0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00,
+        0x00, 0x80, 0x00, 0x00, 0x00, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x03, 0xe8, 0x01, 0x90, 0x00, 0xfa, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    fn parse(data: &[u8; 0x40]) -> ImuReport {
+        let mut report = [0u8; 0x80];
+        report[..0x40].copy_from_slice(data);
+        ImuReport::parse(&report).unwrap()
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let report = parse(&UPRIGHT);
+        assert_eq!(report.timestamp, 4_321_098_765);
+        assert!((report.temperature - 35.0).abs() < 0.01);
+        // Turning left is a positive rotation around up (+Y)
+        assert!((report.gyroscope - Vector3::new(0.0, 15.625f32.to_radians(), 0.0)).norm() < 1e-6);
+        assert!((report.accelerometer - Vector3::new(0.0, 9.81, 0.0)).norm() < 1e-4);
+        // North is forward (-Z), the field points down (-Y)
+        let magnetometer = report.magnetometer.unwrap();
+        assert!((magnetometer - Vector3::new(0.0, -40.0, -25.0)).norm() < 1e-4);
+
+        let report = parse(&ROLLED_RIGHT);
+        assert_eq!(report.timestamp, 4_321_108_765);
+        assert_eq!(report.gyroscope, Vector3::zeros());
+        // Up is now left (-X), down is right (+X)
+        assert!((report.accelerometer - Vector3::new(-9.81, 0.0, 0.0)).norm() < 1e-4);
+        let magnetometer = report.magnetometer.unwrap();
+        assert!((magnetometer - Vector3::new(40.0, 0.0, -25.0)).norm() < 1e-4);
+    }
+
+    /// Prints sensor reports of connected glasses in the format of the fixtures above.
+    /// Run with `cargo test capture_reports -- --ignored --nocapture` while holding
+    /// the glasses still in the pose to be captured.
+    #[cfg(not(target_os = "android"))]
+    #[test]
+    #[ignore = "needs connected glasses"]
+    fn capture_reports() {
+        let imu = ImuDevice::new().unwrap();
+        let mut captured = 0;
+        while captured < 5 {
+            let mut data = [0u8; 0x80];
+            let size = imu.device.read_timeout(&mut data, IMU_TIMEOUT).unwrap();
+            if size == 0 || data[0] != 1 || data[1] != 2 {
+                continue;
+            }
+            println!("// {:?}", ImuReport::parse(&data));
+            println!("[");
+            for line in data[..0x40].chunks(15) {
+                let bytes: Vec<_> = line.iter().map(|b| format!("{b:#04x},")).collect();
+                println!("    {}", bytes.join(" "));
+            }
+            println!("]");
+            captured += 1;
+        }
+    }
+
+    /// Looks for a CRC32 in sensor reports of connected glasses, like the one in command
+    /// packets, and prints which bytes it is stored at and which range it covers.
+    #[cfg(not(target_os = "android"))]
+    #[test]
+    #[ignore = "needs connected glasses"]
+    fn find_report_checksum() {
+        let imu = ImuDevice::new().unwrap();
+        let mut reports = Vec::new();
+        while reports.len() < 50 {
+            let mut data = [0u8; 0x80];
+            let size = imu.device.read_timeout(&mut data, IMU_TIMEOUT).unwrap();
+            if size == data.len() && data[0] == 1 && data[1] == 2 {
+                reports.push(data);
+            }
+        }
+        for offset in 0..=0x80 - 4 {
+            for start in 0..0x80 {
+                for end in start + 1..=0x80 {
+                    if start < offset + 4 && offset < end {
+                        continue;
+                    }
+                    if reports.iter().all(|report| {
+                        let stored =
+                            u32::from_le_bytes(report[offset..offset + 4].try_into().unwrap());
+                        stored != 0 && stored == crc32_adler(&report[start..end])
+                    }) {
+                        println!("CRC32 at {offset:#x} over {start:#x}..{end:#x}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_packet_checksum() {
+        let data = McuPacket {
+            cmd_id: 0x15,
+            data: b"1234".to_vec(),
+        }
+        .serialize()
+        .unwrap();
+        let packet = McuPacket::deserialize(&data).unwrap();
+        assert_eq!(
+            (packet.cmd_id, packet.data.as_slice()),
+            (0x15, &b"1234"[..])
+        );
+        // Corrupted payload, length and header
+        for (offset, value) in [(23, data[23] ^ 0x10), (6, 0xff), (0, 0)] {
+            let mut corrupted = data;
+            corrupted[offset] = value;
+            assert!(matches!(
+                McuPacket::deserialize(&corrupted),
+                Err(Error::ChecksumMismatch)
+            ));
+        }
+
+        let mut data = ImuPacket {
+            cmd_id: 0x19,
+            data: vec![1],
+        }
+        .serialize()
+        .unwrap();
+        assert_eq!(ImuPacket::deserialize(&data).unwrap().unwrap().data, [1]);
+        data[8] = 0;
+        assert!(matches!(
+            ImuPacket::deserialize(&data),
+            Err(Error::ChecksumMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_parse_report_invalid_mag_scale() {
+        let mut data = UPRIGHT;
+        // Zero divisor
+        data[0x2c..0x30].fill(0);
+        assert_eq!(parse(&data).magnetometer, None);
+    }
+
+    #[test]
+    fn test_report_validator() {
+        let still = |timestamp| ImuReport {
+            timestamp,
+            temperature: 30.0,
+            gyroscope: Vector3::zeros(),
+            accelerometer: Vector3::new(0.0, 9.81, 0.0),
+            magnetometer: Some(Vector3::new(0.0, -40.0, -25.0)),
+        };
+        let mut validator = ReportValidator::default();
+        assert!(validator.accept(&still(1000)));
+        assert!(validator.accept(&still(2000)));
+        // Single sample spikes
+        let spikes = [
+            ImuReport {
+                gyroscope: Vector3::new(0.0, 300.0, 0.0),
+                ..still(3000)
+            },
+            ImuReport {
+                accelerometer: Vector3::new(f32::INFINITY, 0.0, 0.0),
+                ..still(3000)
+            },
+            ImuReport {
+                magnetometer: Some(Vector3::new(0.0, f32::NAN, 0.0)),
+                ..still(3000)
+            },
+            ImuReport {
+                temperature: 800.0,
+                ..still(3000)
+            },
+            still(1500),
+            still(2000),
+            still(1 << 40),
+        ];
+        for spike in &spikes {
+            assert!(!validator.accept(spike), "{spike:?}");
+        }
+        assert!(validator.accept(&still(3000)));
+
+        // A real jump of the device clock is accepted after a while
+        let accepted = (0..20)
+            .position(|i| validator.accept(&still(100_000_000 + i * 1000)))
+            .unwrap();
+        assert_eq!(accepted, ReportValidator::MAX_TIMESTAMP_REJECTIONS as usize);
+        assert!(validator.accept(&still(100_000_000 + 20_000)));
+    }
+}
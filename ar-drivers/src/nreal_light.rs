@@ -24,8 +24,13 @@ use nalgebra::{
 use tinyjson::JsonValue;
 
 use crate::{
-    util::crc32_adler, ARGlasses, CameraDescriptor, DisplayMode, Error, GlassesEvent, Result, Side,
+    factory_calibration::FactoryCalibration,
+    util::{crc32_adler, display_mode_id, display_mode_info_by_id},
+    ARGlasses, CameraDescriptor, Capabilities, DisplayLayout, DisplayMode, DisplayModeInfo, Error,
+    GlassesEvent, Result, Side, TimestampSource,
 };
+#[cfg(not(target_os = "android"))]
+use crate::{GlassesDescriptor, GlassesModel, OpenOptions, Transport};
 
 /// The main structure representing a connected Nreal Light glasses
 pub struct NrealLight {
@@ -33,11 +38,36 @@ pub struct NrealLight {
     pending_packets: VecDeque<Packet>,
     last_heartbeat: std::time::Instant,
     ov580: Ov580,
+    rejected_packets: u64,
 }
 
 const COMMAND_TIMEOUT: i32 = 250;
 const OV_580_TIMEOUT: i32 = 250;
 
+/// Firmware display mode IDs, in order of preference
+const DISPLAY_MODES: &[(u8, DisplayModeInfo)] = &[
+    // "1&2D_1080"
+    (
+        b'1',
+        DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+    ),
+    // "3&3D_1080"
+    (
+        b'3',
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+    ),
+    // "2&3D_540"
+    (
+        b'2',
+        DisplayModeInfo::new(DisplayLayout::HalfSideBySide, 960, 1080, 60),
+    ),
+    // "4&3D_1080#72"
+    (
+        b'4',
+        DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 72),
+    ),
+];
+
 impl ARGlasses for NrealLight {
     fn serial(&mut self) -> Result<String> {
         let result = self.run_command(Packet {
@@ -58,43 +88,35 @@ impl ARGlasses for NrealLight {
     }
 
     fn get_display_mode(&mut self) -> Result<DisplayMode> {
-        let result = self.run_command(Packet {
-            category: b'3',
-            cmd_id: b'3',
-            ..Default::default()
-        })?;
-        match result.first() {
-            // "1&2D_1080"
-            Some(b'1') => Ok(DisplayMode::SameOnBoth),
-            // "2&3D_540"
-            Some(b'2') => Ok(DisplayMode::HalfSBS),
-            // "3&3D_1080"
-            Some(b'3') => Ok(DisplayMode::Stereo),
-            // "4&3D_1080#72"
-            Some(b'4') => Ok(DisplayMode::HighRefreshRate),
-            _ => Err(Error::Other("Unknown display mode")),
-        }
+        Ok(self.get_display_mode_info()?.display_mode())
     }
 
     fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
-        let display_mode_byte = match display_mode {
-            DisplayMode::SameOnBoth => b'1',
-            DisplayMode::HalfSBS => b'2',
-            DisplayMode::Stereo => b'3',
-            DisplayMode::HighRefreshRateSBS => b'4',
-            _ => return Err(Error::Other("Display mode not supported")),
-        };
+        self.set_display_mode_id(display_mode_id(DISPLAY_MODES, |mode| {
+            mode.display_mode() == display_mode
+        })?)
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        DISPLAY_MODES.iter().map(|(_, mode)| *mode).collect()
+    }
+
+    fn get_display_mode_info(&mut self) -> Result<DisplayModeInfo> {
         let result = self.run_command(Packet {
-            category: b'1',
+            category: b'3',
             cmd_id: b'3',
-            data: vec![display_mode_byte],
+            ..Default::default()
         })?;
+        let id = *result
+            .first()
+            .ok_or(Error::Other("Invalid display mode response"))?;
+        display_mode_info_by_id(DISPLAY_MODES, id)
+    }
 
-        if result.first() == Some(&display_mode_byte) {
-            Ok(())
-        } else {
-            Err(Error::Other("Display mode setting unsuccessful"))
-        }
+    fn set_display_mode_info(&mut self, display_mode: DisplayModeInfo) -> Result<()> {
+        self.set_display_mode_id(display_mode_id(DISPLAY_MODES, |mode| {
+            *mode == display_mode
+        })?)
     }
 
     fn display_fov(&self) -> f32 {
@@ -120,6 +142,19 @@ impl ARGlasses for NrealLight {
         "Nreal Light"
     }
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            proximity: true,
+            ambient_light: true,
+            vsync: true,
+            key_count: 2,
+            cameras: self.cameras().map_or(0, |cameras| cameras.len()),
+            stereo: true,
+            timestamps: TimestampSource::Device,
+            ..Default::default()
+        }
+    }
+
     fn cameras(&self) -> Result<Vec<crate::CameraDescriptor>> {
         let rgb = self.get_basic_camera_descriptor("rgb", "RGB_camera", "device_1")?;
         let slam_left =
@@ -136,6 +171,10 @@ impl ARGlasses for NrealLight {
         Ok(vec![rgb, slam_left, slam_right])
     }
 
+    fn rejected_packets(&self) -> u64 {
+        self.rejected_packets
+    }
+
     fn display_delay(&self) -> u64 {
         15500
     }
@@ -166,6 +205,8 @@ impl NrealLight {
         Self::new_common(
             HidApi::new_without_enumerate()?.wrap_sys_device(mcu_fd, -1)?,
             Ov580::new(ov580_fd)?,
+            true,
+            true,
         )
     }
 
@@ -176,14 +217,44 @@ impl NrealLight {
         Self::new_common(
             HidApi::new()?.open(Self::MCU_VID, Self::MCU_PID)?,
             Ov580::new()?,
+            true,
+            true,
+        )
+    }
+
+    /// Connect to the device found by [`crate::list_glasses`]
+    ///
+    /// Note that the OV580 (IMU) interface has no known link to the MCU one, so the
+    /// first one found is used. This only matters if multiple glasses are connected.
+    #[cfg(not(target_os = "android"))]
+    pub fn open(descriptor: &GlassesDescriptor, options: &OpenOptions) -> Result<Self> {
+        let hidapi = HidApi::new()?;
+        let path = std::ffi::CString::new(descriptor.bus_path.as_str())
+            .map_err(|_| Error::Other("Invalid device path"))?;
+        let mut ov580 = Ov580::new()?;
+        if let Some(read_timeout) = options.read_timeout {
+            ov580.read_timeout = read_timeout.as_millis() as i32;
+        }
+        Self::new_common(
+            hidapi.open_path(&path)?,
+            ov580,
+            options.ambient_light_events,
+            options.vsync_events,
         )
     }
-    fn new_common(device: HidDevice, ov580: Ov580) -> Result<Self> {
+
+    fn new_common(
+        device: HidDevice,
+        ov580: Ov580,
+        ambient_light_events: bool,
+        vsync_events: bool,
+    ) -> Result<Self> {
         let mut result = Self {
             device,
             pending_packets: Default::default(),
             last_heartbeat: std::time::Instant::now(),
             ov580,
+            rejected_packets: 0,
         };
         // Send a "Yes, I am a working SDK" command
         // This is needed for SBS 3D display to work.
@@ -193,17 +264,21 @@ impl NrealLight {
             data: vec![b'1'],
         })?;
         // Enable the Ambient Light event
-        result.run_command(Packet {
-            category: b'1',
-            cmd_id: b'L',
-            data: vec![b'1'],
-        })?;
+        if ambient_light_events {
+            result.run_command(Packet {
+                category: b'1',
+                cmd_id: b'L',
+                data: vec![b'1'],
+            })?;
+        }
         // Enable VSync event
-        result.run_command(Packet {
-            category: b'1',
-            cmd_id: b'N',
-            data: vec![b'1'],
-        })?;
+        if vsync_events {
+            result.run_command(Packet {
+                category: b'1',
+                cmd_id: b'N',
+                data: vec![b'1'],
+            })?;
+        }
         Ok(result)
     }
 
@@ -213,13 +288,35 @@ impl NrealLight {
         &self.ov580.config_json
     }
 
+    /// Returns the parsed calibration data stored on the Glasses
+    pub fn factory_calibration(&self) -> &FactoryCalibration {
+        &self.ov580.calibration
+    }
+
+    fn set_display_mode_id(&mut self, id: u8) -> Result<()> {
+        let result = self.run_command(Packet {
+            category: b'1',
+            cmd_id: b'3',
+            data: vec![id],
+        })?;
+
+        if result.first() == Some(&id) {
+            Ok(())
+        } else {
+            Err(Error::Other("Display mode setting unsuccessful"))
+        }
+    }
+
     fn read_mcu_packet(&mut self) -> Result<Option<GlassesEvent>> {
         let packet = if let Some(packet) = self.pending_packets.pop_front() {
             packet
-        } else if let Some(packet) = self.read_packet(0)? {
-            packet
         } else {
-            return Ok(None);
+            match self.read_packet(0) {
+                Ok(Some(packet)) => packet,
+                // Corrupted packets are simply dropped
+                Ok(None) | Err(Error::ChecksumMismatch) => return Ok(None),
+                Err(e) => return Err(e),
+            }
         };
         Ok(match packet {
             Packet {
@@ -258,7 +355,7 @@ impl NrealLight {
                 category: b'5',
                 cmd_id: b'S',
                 ..
-            } => Some(GlassesEvent::VSync),
+            } => Some(GlassesEvent::VSync { timestamp: None }),
             // NOTE: maybe we should retry right here instead of basically reporting timeout,
             //       but we will be called again soon enough.
             _ => None,
@@ -271,9 +368,11 @@ impl NrealLight {
         if packet_size == 0 {
             Ok(None)
         } else {
-            Ok(Some(
-                Packet::deserialize(&result).ok_or(Error::Other("Malformed packet received"))?,
-            ))
+            let packet = Packet::deserialize(&result);
+            if let Err(Error::ChecksumMismatch) = packet {
+                self.rejected_packets += 1;
+            }
+            packet.map(Some)
         }
     }
 
@@ -381,11 +480,31 @@ impl NrealLight {
     }
 }
 
+/// List connected Nreal Light glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn list(hidapi: &HidApi) -> Vec<GlassesDescriptor> {
+    hidapi
+        .device_list()
+        .filter(|d| d.vendor_id() == NrealLight::MCU_VID && d.product_id() == NrealLight::MCU_PID)
+        .map(|d| GlassesDescriptor {
+            model: GlassesModel::NrealLight,
+            vid: d.vendor_id(),
+            pid: d.product_id(),
+            // The glasses serial can only be queried with an MCU command
+            serial: None,
+            transport: Transport::Hid,
+            bus_path: d.path().to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
 struct Ov580 {
     device: HidDevice,
     config_json: JsonValue,
+    calibration: FactoryCalibration,
     gyro_bias: Vector3<f32>,
     accelerometer_bias: Vector3<f32>,
+    read_timeout: i32,
 }
 
 impl Ov580 {
@@ -402,8 +521,10 @@ impl Ov580 {
         let mut result = Self {
             device,
             config_json: JsonValue::Null,
+            calibration: Default::default(),
             gyro_bias: Default::default(),
             accelerometer_bias: Default::default(),
+            read_timeout: OV_580_TIMEOUT,
         };
         // Turn off IMU stream while reading config
         result.command(0x19, 0x0)?;
@@ -444,22 +565,12 @@ impl Ov580 {
     }
 
     fn parse_config(&mut self) -> Result<()> {
-        // XXX: This will panic if config is not in expected format.
-        //      should probably return Err() instead.
-        let cfg = &self.config_json["IMU"]["device_1"];
-        self.accelerometer_bias = Self::parse_vector(&cfg["accel_bias"]);
-        self.gyro_bias = Self::parse_vector(&cfg["gyro_bias"]);
+        self.calibration = FactoryCalibration::from_xreal_json(&self.config_json)?;
+        self.accelerometer_bias = self.calibration.accelerometer_bias.map(|c| c as f32);
+        self.gyro_bias = self.calibration.gyro_bias.map(|c| c as f32);
         Ok(())
     }
 
-    fn parse_vector(json: &JsonValue) -> Vector3<f32> {
-        Vector3::new(
-            *json[0].get::<f64>().unwrap() as f32,
-            *json[1].get::<f64>().unwrap() as f32,
-            *json[2].get::<f64>().unwrap() as f32,
-        )
-    }
-
     fn command(&self, cmd: u8, subcmd: u8) -> Result<Vec<u8>> {
         self.device.write(&[2, cmd, subcmd, 0, 0, 0, 0])?;
         for _ in 0..64 {
@@ -478,7 +589,9 @@ impl Ov580 {
     pub fn read_packet(&mut self) -> Result<GlassesEvent> {
         loop {
             let mut packet_data = [0u8; 0x80];
-            let data_size = self.device.read_timeout(&mut packet_data, OV_580_TIMEOUT)?;
+            let data_size = self
+                .device
+                .read_timeout(&mut packet_data, self.read_timeout)?;
             if data_size == 0 {
                 return Err(Error::PacketTimeout);
             }
@@ -543,27 +656,53 @@ impl Default for Packet {
 }
 
 impl Packet {
-    fn deserialize(data: &[u8]) -> Option<Packet> {
-        if data[0] != 2 {
-            return None;
-        }
-        let end = data.iter().position(|c| *c == 3)?;
+    /// Malformed packets are reported as [`Error::ChecksumMismatch`] too
+    fn deserialize(data: &[u8]) -> Result<Packet> {
+        Self::check_crc(data)?;
+        let malformed = || Error::ChecksumMismatch;
+        let end = data.iter().position(|c| *c == 3).ok_or_else(malformed)?;
         let inner = &data[1..end];
         let mut parts = inner.split(|c| *c == b':');
-        let _empty = parts.next()?;
-        let category = *parts.next()?.first()?;
-        let cmd_id = *parts.next()?.first()?;
-        let cmd_data = parts.next()?.into();
+        let _empty = parts.next().ok_or_else(malformed)?;
+        let category = *parts.next().and_then(|p| p.first()).ok_or_else(malformed)?;
+        let cmd_id = *parts.next().and_then(|p| p.first()).ok_or_else(malformed)?;
+        let cmd_data = parts.next().ok_or_else(malformed)?.into();
         // Next field is timestamp
         // Last field is CRC
-        // TODO: maybe check CRC?
-        Some(Packet {
+        Ok(Packet {
             category,
             cmd_id,
             data: cmd_data,
         })
     }
 
+    /// The CRC is calculated on everything before it, the same way as in [`Packet::serialize`]
+    fn check_crc(data: &[u8]) -> Result<()> {
+        if data.first() != Some(&2) {
+            return Err(Error::ChecksumMismatch);
+        }
+        // ...:<crc>:\x03
+        let crc_end = data
+            .iter()
+            .position(|c| *c == 3)
+            .and_then(|end| end.checked_sub(1))
+            .filter(|&i| data[i] == b':')
+            .ok_or(Error::ChecksumMismatch)?;
+        let crc_start = data[..crc_end]
+            .iter()
+            .rposition(|c| *c == b':')
+            .ok_or(Error::ChecksumMismatch)?
+            + 1;
+        let crc = std::str::from_utf8(&data[crc_start..crc_end])
+            .ok()
+            .and_then(|crc| u32::from_str_radix(crc.trim(), 16).ok())
+            .ok_or(Error::ChecksumMismatch)?;
+        if crc != crc32_adler(&data[..crc_start]) {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+
     fn serialize(&self) -> Option<[u8; 0x40]> {
         let mut writer = std::io::Cursor::new([0u8; 0x40]);
         writer
@@ -708,3 +847,34 @@ impl NrealLightSlamCamera {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_crc() {
+        let data = Packet {
+            category: b'3',
+            cmd_id: b'C',
+            data: b"x".to_vec(),
+        }
+        .serialize()
+        .unwrap();
+        let packet = Packet::deserialize(&data).unwrap();
+        assert_eq!(
+            (packet.category, packet.cmd_id, packet.data.as_slice()),
+            (b'3', b'C', &b"x"[..])
+        );
+        // Corrupted payload, CRC and framing
+        let crc_digit = if data[12] == b'0' { b'1' } else { b'0' };
+        for (offset, value) in [(6, b'y'), (12, crc_digit), (0, 0)] {
+            let mut corrupted = data;
+            corrupted[offset] = value;
+            assert!(matches!(
+                Packet::deserialize(&corrupted),
+                Err(Error::ChecksumMismatch)
+            ));
+        }
+    }
+}
@@ -0,0 +1,625 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Recording and replaying glasses event streams. See [`RecordingGlasses`] and [`ReplayGlasses`]
+//!
+//! The file format is compact and versioned. All numbers are little endian.
+//!
+//! * Header: magic (`ARGLREC\0`), version (`u16`), serial, name, display FOV,
+//!   display delay, display mode, IMU to display transformations, and the display
+//!   matrices (if the glasses had any).
+//! * Records until the end of the file: event type (`u8`), host receive time
+//!   (`u64`, usecs since the start of the recording), then the event data,
+//!   including the device timestamp where the event has one.
+//!
+//! The version is increased whenever event types are added, so that older readers
+//! reject newer files up front. Version 2 added the temperature, timestamped V-sync,
+//! brightness and volume events. Older versions can still be read.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nalgebra::{Isometry3, Matrix3, Quaternion, Translation3, UnitQuaternion, Vector3};
+
+use crate::{
+    util::forward_glasses, ARGlasses, DisplayMatrices, DisplayMode, Error, GlassesEvent, Result,
+    Side,
+};
+
+const MAGIC: &[u8; 8] = b"ARGLREC\0";
+const VERSION: u16 = 2;
+
+/// Glasses properties stored at the start of a recording
+#[derive(Debug, Clone)]
+pub struct RecordingHeader {
+    /// See [`ARGlasses::serial`]
+    pub serial: String,
+    /// See [`ARGlasses::name`]
+    pub name: String,
+    /// See [`ARGlasses::display_fov`]
+    pub display_fov: f32,
+    /// See [`ARGlasses::display_delay`]
+    pub display_delay: u64,
+    /// Display mode at the start of the recording, if it could be queried
+    pub display_mode: Option<DisplayMode>,
+    /// [`ARGlasses::imu_to_display_matrix`] for the left and right side, at zero IPD
+    pub imu_to_display: (Isometry3<f64>, Isometry3<f64>),
+    /// Change of the translation of [`ARGlasses::imu_to_display_matrix`] per meter of
+    /// IPD, for the left and right side
+    pub imu_to_display_per_ipd: (Vector3<f64>, Vector3<f64>),
+    /// See [`ARGlasses::display_matrices`]
+    pub display_matrices: Option<(DisplayMatrices, DisplayMatrices)>,
+}
+
+/// Wrapper that writes every event read from the wrapped glasses into a recording.
+/// Otherwise it works exactly like the wrapped glasses.
+pub struct RecordingGlasses<W: Write + Send> {
+    glasses: Box<dyn ARGlasses>,
+    writer: W,
+    started: Instant,
+}
+
+/// How fast [`ReplayGlasses`] plays back events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplaySpeed {
+    /// Events are returned at the same pace as they were recorded
+    RealTime,
+    /// Events are returned immediately
+    AsFastAsPossible,
+}
+
+/// Glasses that play back a recording made with [`RecordingGlasses`]
+///
+/// [`ARGlasses::read_event`] returns an [`Error::IoError`] of kind
+/// [`std::io::ErrorKind::UnexpectedEof`] at the end of the recording.
+pub struct ReplayGlasses<R: Read + Send> {
+    reader: R,
+    header: RecordingHeader,
+    name: &'static str,
+    display_mode: Option<DisplayMode>,
+    speed: ReplaySpeed,
+    started: Instant,
+}
+
+impl RecordingGlasses<BufWriter<File>> {
+    /// Start recording the events of `glasses` into a new file at `path`
+    pub fn create(glasses: Box<dyn ARGlasses>, path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(glasses, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Send> RecordingGlasses<W> {
+    /// Start recording the events of `glasses` into `writer`.
+    /// The header is written immediately.
+    pub fn new(mut glasses: Box<dyn ARGlasses>, mut writer: W) -> Result<Self> {
+        let header = RecordingHeader::from_glasses(glasses.as_mut())?;
+        header.write(&mut writer)?;
+        Ok(Self {
+            glasses,
+            writer,
+            started: Instant::now(),
+        })
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Stop recording, returning the wrapped glasses and the writer
+    pub fn into_inner(mut self) -> Result<(Box<dyn ARGlasses>, W)> {
+        self.writer.flush()?;
+        Ok((self.glasses, self.writer))
+    }
+}
+
+impl<W: Write + Send> ARGlasses for RecordingGlasses<W> {
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        let event = self.glasses.read_event()?;
+        let host_time = self.started.elapsed().as_micros() as u64;
+        write_record(&mut self.writer, host_time, &event)?;
+        Ok(event)
+    }
+
+    forward_glasses!(glasses);
+}
+
+impl ReplayGlasses<BufReader<File>> {
+    /// Open a recording file for playback
+    pub fn open(path: impl AsRef<Path>, speed: ReplaySpeed) -> Result<Self> {
+        Self::new(BufReader::new(File::open(path)?), speed)
+    }
+}
+
+impl<R: Read + Send> ReplayGlasses<R> {
+    /// Start playing back a recording. The header is read immediately.
+    pub fn new(mut reader: R, speed: ReplaySpeed) -> Result<Self> {
+        let header = RecordingHeader::read(&mut reader)?;
+        Ok(Self {
+            reader,
+            // name() has to return a static string. This is leaked once per replay,
+            // which is fine for the intended debugging use.
+            name: Box::leak(header.name.clone().into_boxed_str()),
+            display_mode: header.display_mode,
+            header,
+            speed,
+            started: Instant::now(),
+        })
+    }
+
+    /// The header of the recording
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// Read the next event along with its host receive time (relative to the
+    /// start of the recording), without any pacing.
+    pub fn read_record(&mut self) -> Result<(Duration, GlassesEvent)> {
+        read_record(&mut self.reader)
+    }
+}
+
+impl<R: Read + Send> ARGlasses for ReplayGlasses<R> {
+    fn serial(&mut self) -> Result<String> {
+        Ok(self.header.serial.clone())
+    }
+
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        let (host_time, event) = self.read_record()?;
+        if self.speed == ReplaySpeed::RealTime {
+            if let Some(wait) = host_time.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+        Ok(event)
+    }
+
+    fn get_display_mode(&mut self) -> Result<DisplayMode> {
+        self.display_mode.ok_or(Error::NotImplemented)
+    }
+
+    fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
+        self.display_mode = Some(display_mode);
+        Ok(())
+    }
+
+    fn display_fov(&self) -> f32 {
+        self.header.display_fov
+    }
+
+    fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        self.header.imu_to_display_matrix(side, ipd)
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn display_matrices(&self) -> Result<(DisplayMatrices, DisplayMatrices)> {
+        self.header
+            .display_matrices
+            .clone()
+            .ok_or(Error::NotImplemented)
+    }
+
+    fn display_delay(&self) -> u64 {
+        self.header.display_delay
+    }
+}
+
+impl RecordingHeader {
+    /// Collect the header data from connected glasses
+    pub fn from_glasses(glasses: &mut dyn ARGlasses) -> Result<Self> {
+        let imu_to_display = |side| {
+            let at_zero = glasses.imu_to_display_matrix(side, 0.0);
+            let at_one = glasses.imu_to_display_matrix(side, 1.0);
+            (
+                at_zero,
+                at_one.translation.vector - at_zero.translation.vector,
+            )
+        };
+        let (left, left_per_ipd) = imu_to_display(Side::Left);
+        let (right, right_per_ipd) = imu_to_display(Side::Right);
+        Ok(Self {
+            serial: glasses.serial()?,
+            name: glasses.name().into(),
+            display_fov: glasses.display_fov(),
+            display_delay: glasses.display_delay(),
+            display_mode: glasses.get_display_mode().ok(),
+            imu_to_display: (left, right),
+            imu_to_display_per_ipd: (left_per_ipd, right_per_ipd),
+            display_matrices: glasses.display_matrices().ok(),
+        })
+    }
+
+    /// Reconstruct [`ARGlasses::imu_to_display_matrix`] from the stored data
+    pub fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        let (mut isometry, per_ipd) = match side {
+            Side::Left => (self.imu_to_display.0, self.imu_to_display_per_ipd.0),
+            Side::Right => (self.imu_to_display.1, self.imu_to_display_per_ipd.1),
+        };
+        isometry.translation.vector += per_ipd * ipd as f64;
+        isometry
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u16::<LittleEndian>(VERSION)?;
+        write_string(writer, &self.serial)?;
+        write_string(writer, &self.name)?;
+        writer.write_f32::<LittleEndian>(self.display_fov)?;
+        writer.write_u64::<LittleEndian>(self.display_delay)?;
+        writer.write_u8(match self.display_mode {
+            None => 0,
+            Some(DisplayMode::SameOnBoth) => 1,
+            Some(DisplayMode::Stereo) => 2,
+            Some(DisplayMode::HalfSBS) => 3,
+            Some(DisplayMode::HighRefreshRate) => 4,
+            Some(DisplayMode::HighRefreshRateSBS) => 5,
+        })?;
+        write_isometry(writer, &self.imu_to_display.0)?;
+        write_vector(writer, &self.imu_to_display_per_ipd.0)?;
+        write_isometry(writer, &self.imu_to_display.1)?;
+        write_vector(writer, &self.imu_to_display_per_ipd.1)?;
+        match &self.display_matrices {
+            None => writer.write_u8(0)?,
+            Some((left, right)) => {
+                writer.write_u8(1)?;
+                write_display_matrices(writer, left)?;
+                write_display_matrices(writer, right)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::Other("Not an ar-drivers recording"));
+        }
+        if !(1..=VERSION).contains(&reader.read_u16::<LittleEndian>()?) {
+            return Err(Error::Other("Unsupported recording version"));
+        }
+        let serial = read_string(reader)?;
+        let name = read_string(reader)?;
+        let display_fov = reader.read_f32::<LittleEndian>()?;
+        let display_delay = reader.read_u64::<LittleEndian>()?;
+        let display_mode = match reader.read_u8()? {
+            0 => None,
+            1 => Some(DisplayMode::SameOnBoth),
+            2 => Some(DisplayMode::Stereo),
+            3 => Some(DisplayMode::HalfSBS),
+            4 => Some(DisplayMode::HighRefreshRate),
+            5 => Some(DisplayMode::HighRefreshRateSBS),
+            _ => return Err(Error::Other("Invalid display mode in recording")),
+        };
+        let left = read_isometry(reader)?;
+        let left_per_ipd = read_vector(reader)?;
+        let right = read_isometry(reader)?;
+        let right_per_ipd = read_vector(reader)?;
+        let display_matrices = match reader.read_u8()? {
+            0 => None,
+            _ => Some((
+                read_display_matrices(reader)?,
+                read_display_matrices(reader)?,
+            )),
+        };
+        Ok(Self {
+            serial,
+            name,
+            display_fov,
+            display_delay,
+            display_mode,
+            imu_to_display: (left, right),
+            imu_to_display_per_ipd: (left_per_ipd, right_per_ipd),
+            display_matrices,
+        })
+    }
+}
+
+fn write_record(writer: &mut impl Write, host_time: u64, event: &GlassesEvent) -> Result<()> {
+    let tag = match event {
+        GlassesEvent::AccGyro { .. } => 1,
+        GlassesEvent::Magnetometer { .. } => 2,
+        GlassesEvent::KeyPress(_) => 3,
+        GlassesEvent::ProximityNear => 4,
+        GlassesEvent::ProximityFar => 5,
+        GlassesEvent::AmbientLight(_) => 6,
+        GlassesEvent::VSync { timestamp: None } => 7,
+        GlassesEvent::Connected => 8,
+        GlassesEvent::Disconnected => 9,
+        GlassesEvent::Temperature { .. } => 10,
+        GlassesEvent::VSync { timestamp: Some(_) } => 11,
+        GlassesEvent::BrightnessChanged(_) => 12,
+        GlassesEvent::VolumeChanged(_) => 13,
+    };
+    writer.write_u8(tag)?;
+    writer.write_u64::<LittleEndian>(host_time)?;
+    match event {
+        GlassesEvent::AccGyro {
+            accelerometer,
+            gyroscope,
+            timestamp,
+        } => {
+            write_vector_f32(writer, accelerometer)?;
+            write_vector_f32(writer, gyroscope)?;
+            writer.write_u64::<LittleEndian>(*timestamp)?;
+        }
+        GlassesEvent::Magnetometer {
+            magnetometer,
+            timestamp,
+        } => {
+            write_vector_f32(writer, magnetometer)?;
+            writer.write_u64::<LittleEndian>(*timestamp)?;
+        }
+        GlassesEvent::KeyPress(key)
+        | GlassesEvent::BrightnessChanged(key)
+        | GlassesEvent::VolumeChanged(key) => writer.write_u8(*key)?,
+        GlassesEvent::VSync {
+            timestamp: Some(timestamp),
+        } => writer.write_u64::<LittleEndian>(*timestamp)?,
+        GlassesEvent::AmbientLight(value) => writer.write_u16::<LittleEndian>(*value)?,
+        GlassesEvent::Temperature {
+            temperature,
+            timestamp,
+        } => {
+            writer.write_f32::<LittleEndian>(*temperature)?;
+            writer.write_u64::<LittleEndian>(*timestamp)?;
+        }
+        GlassesEvent::ProximityNear
+        | GlassesEvent::ProximityFar
+        | GlassesEvent::VSync { timestamp: None }
+        | GlassesEvent::Connected
+        | GlassesEvent::Disconnected => (),
+    }
+    Ok(())
+}
+
+fn read_record(reader: &mut impl Read) -> Result<(Duration, GlassesEvent)> {
+    let tag = reader.read_u8()?;
+    let host_time = Duration::from_micros(reader.read_u64::<LittleEndian>()?);
+    let event = match tag {
+        1 => GlassesEvent::AccGyro {
+            accelerometer: read_vector_f32(reader)?,
+            gyroscope: read_vector_f32(reader)?,
+            timestamp: reader.read_u64::<LittleEndian>()?,
+        },
+        2 => GlassesEvent::Magnetometer {
+            magnetometer: read_vector_f32(reader)?,
+            timestamp: reader.read_u64::<LittleEndian>()?,
+        },
+        3 => GlassesEvent::KeyPress(reader.read_u8()?),
+        4 => GlassesEvent::ProximityNear,
+        5 => GlassesEvent::ProximityFar,
+        6 => GlassesEvent::AmbientLight(reader.read_u16::<LittleEndian>()?),
+        7 => GlassesEvent::VSync { timestamp: None },
+        8 => GlassesEvent::Connected,
+        9 => GlassesEvent::Disconnected,
+        10 => GlassesEvent::Temperature {
+            temperature: reader.read_f32::<LittleEndian>()?,
+            timestamp: reader.read_u64::<LittleEndian>()?,
+        },
+        11 => GlassesEvent::VSync {
+            timestamp: Some(reader.read_u64::<LittleEndian>()?),
+        },
+        12 => GlassesEvent::BrightnessChanged(reader.read_u8()?),
+        13 => GlassesEvent::VolumeChanged(reader.read_u8()?),
+        _ => return Err(Error::Other("Invalid event in recording")),
+    };
+    Ok((host_time, event))
+}
+
+fn write_string(writer: &mut impl Write, s: &str) -> Result<()> {
+    let len = u16::try_from(s.len()).map_err(|_| Error::Other("String too long to record"))?;
+    writer.write_u16::<LittleEndian>(len)?;
+    writer.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let mut data = vec![0u8; reader.read_u16::<LittleEndian>()? as usize];
+    reader.read_exact(&mut data)?;
+    String::from_utf8(data).map_err(|_| Error::Other("Invalid string in recording"))
+}
+
+fn write_vector_f32(writer: &mut impl Write, v: &Vector3<f32>) -> Result<()> {
+    for c in v.iter() {
+        writer.write_f32::<LittleEndian>(*c)?;
+    }
+    Ok(())
+}
+
+fn read_vector_f32(reader: &mut impl Read) -> Result<Vector3<f32>> {
+    Ok(Vector3::new(
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+        reader.read_f32::<LittleEndian>()?,
+    ))
+}
+
+fn write_vector(writer: &mut impl Write, v: &Vector3<f64>) -> Result<()> {
+    for c in v.iter() {
+        writer.write_f64::<LittleEndian>(*c)?;
+    }
+    Ok(())
+}
+
+fn read_vector(reader: &mut impl Read) -> Result<Vector3<f64>> {
+    Ok(Vector3::new(
+        reader.read_f64::<LittleEndian>()?,
+        reader.read_f64::<LittleEndian>()?,
+        reader.read_f64::<LittleEndian>()?,
+    ))
+}
+
+fn write_isometry(writer: &mut impl Write, isometry: &Isometry3<f64>) -> Result<()> {
+    write_vector(writer, &isometry.translation.vector)?;
+    for c in isometry.rotation.coords.iter() {
+        writer.write_f64::<LittleEndian>(*c)?;
+    }
+    Ok(())
+}
+
+fn read_isometry(reader: &mut impl Read) -> Result<Isometry3<f64>> {
+    let translation = read_vector(reader)?;
+    let mut coords = [0.0; 4];
+    for c in coords.iter_mut() {
+        *c = reader.read_f64::<LittleEndian>()?;
+    }
+    let [i, j, k, w] = coords;
+    Ok(Isometry3::from_parts(
+        Translation3::from(translation),
+        UnitQuaternion::from_quaternion(Quaternion::new(w, i, j, k)),
+    ))
+}
+
+fn write_display_matrices(writer: &mut impl Write, matrices: &DisplayMatrices) -> Result<()> {
+    for c in matrices.intrinsic_matrix.transpose().iter() {
+        writer.write_f64::<LittleEndian>(*c)?;
+    }
+    writer.write_u32::<LittleEndian>(matrices.resolution.0)?;
+    writer.write_u32::<LittleEndian>(matrices.resolution.1)?;
+    write_isometry(writer, &matrices.isometry)
+}
+
+fn read_display_matrices(reader: &mut impl Read) -> Result<DisplayMatrices> {
+    let mut values = [0.0; 9];
+    for c in values.iter_mut() {
+        *c = reader.read_f64::<LittleEndian>()?;
+    }
+    Ok(DisplayMatrices {
+        intrinsic_matrix: Matrix3::from_row_slice(&values),
+        resolution: (
+            reader.read_u32::<LittleEndian>()?,
+            reader.read_u32::<LittleEndian>()?,
+        ),
+        isometry: read_isometry(reader)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_round_trip() {
+        let header = RecordingHeader {
+            serial: "1234".into(),
+            name: "Test glasses".into(),
+            display_fov: 0.4,
+            display_delay: 7000,
+            display_mode: Some(DisplayMode::Stereo),
+            imu_to_display: (
+                Translation3::new(0.0, 0.01, 0.0)
+                    * UnitQuaternion::from_euler_angles(0.1, 0.0, 0.0),
+                Isometry3::identity(),
+            ),
+            imu_to_display_per_ipd: (Vector3::new(-0.5, 0.0, 0.0), Vector3::new(0.5, 0.0, 0.0)),
+            display_matrices: None,
+        };
+        let mut data = Vec::new();
+        header.write(&mut data).unwrap();
+        write_record(
+            &mut data,
+            1500,
+            &GlassesEvent::AccGyro {
+                accelerometer: Vector3::new(0.0, 9.81, 0.1),
+                gyroscope: Vector3::new(0.01, 0.02, 0.03),
+                timestamp: 123456,
+            },
+        )
+        .unwrap();
+        write_record(&mut data, 2500, &GlassesEvent::KeyPress(1)).unwrap();
+        write_record(
+            &mut data,
+            3000,
+            &GlassesEvent::VSync {
+                timestamp: Some(42),
+            },
+        )
+        .unwrap();
+
+        let mut replay =
+            ReplayGlasses::new(data.as_slice(), ReplaySpeed::AsFastAsPossible).unwrap();
+        assert_eq!(replay.serial().unwrap(), "1234");
+        assert_eq!(replay.name(), "Test glasses");
+        assert_eq!(replay.get_display_mode().unwrap(), DisplayMode::Stereo);
+        let right = replay.imu_to_display_matrix(Side::Right, 0.07);
+        assert!((right.translation.x - 0.035).abs() < 1e-9);
+
+        let (host_time, event) = replay.read_record().unwrap();
+        assert_eq!(host_time, Duration::from_micros(1500));
+        assert!(matches!(
+            event,
+            GlassesEvent::AccGyro {
+                timestamp: 123456,
+                ..
+            }
+        ));
+        assert!(matches!(replay.read_event(), Ok(GlassesEvent::KeyPress(1))));
+        assert!(matches!(
+            replay.read_event(),
+            Ok(GlassesEvent::VSync {
+                timestamp: Some(42)
+            })
+        ));
+        assert!(matches!(replay.read_event(), Err(Error::IoError(_))));
+    }
+
+    #[test]
+    fn test_long_string_rejected() {
+        let mut data = Vec::new();
+        assert!(write_string(&mut data, &"x".repeat(u16::MAX as usize)).is_ok());
+        assert!(matches!(
+            write_string(&mut data, &"x".repeat(u16::MAX as usize + 1)),
+            Err(Error::Other(_))
+        ));
+    }
+
+    #[cfg(feature = "simulated")]
+    #[test]
+    fn test_record_and_replay_glasses() {
+        use crate::simulated::{SimulatedGlasses, SimulationConfig};
+
+        let simulated = SimulatedGlasses::new(SimulationConfig {
+            realtime: false,
+            timestamp_jitter: 200.0,
+            ..Default::default()
+        });
+        let mut recording = RecordingGlasses::new(Box::new(simulated), Vec::new()).unwrap();
+        let recorded: Vec<_> = (0..3000)
+            .map(|_| format!("{:?}", recording.read_event().unwrap()))
+            .collect();
+        let (mut glasses, data) = recording.into_inner().unwrap();
+
+        let mut replay =
+            ReplayGlasses::new(data.as_slice(), ReplaySpeed::AsFastAsPossible).unwrap();
+        assert_eq!(replay.serial().unwrap(), glasses.serial().unwrap());
+        assert_eq!(replay.name(), glasses.name());
+        assert_eq!(replay.display_fov(), glasses.display_fov());
+        assert_eq!(replay.display_delay(), glasses.display_delay());
+        assert_eq!(
+            replay.get_display_mode().unwrap(),
+            glasses.get_display_mode().unwrap()
+        );
+        for side in [Side::Left, Side::Right] {
+            let expected = glasses.imu_to_display_matrix(side, 0.065);
+            let replayed = replay.imu_to_display_matrix(side, 0.065);
+            assert!((expected.to_homogeneous() - replayed.to_homogeneous()).norm() < 1e-9);
+        }
+        // Including the scripted proximity event after 1 second
+        let replayed: Vec<_> = (0..3000)
+            .map(|_| format!("{:?}", replay.read_event().unwrap()))
+            .collect();
+        assert_eq!(replayed, recorded);
+        assert!(recorded.iter().any(|event| event == "ProximityNear"));
+        assert!(matches!(replay.read_event(), Err(Error::IoError(_))));
+    }
+}
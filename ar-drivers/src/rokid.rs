@@ -11,7 +11,13 @@ use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
 use rusb::{request_type, DeviceHandle, GlobalContext};
 
 use crate::{
-    util::get_interface_for_endpoint, ARGlasses, DisplayMode, Error, GlassesEvent, Result, Side,
+    util::get_interface_for_endpoint, ARGlasses, Capabilities, DisplayLayout, DisplayMode,
+    DisplayModeInfo, Error, GlassesEvent, Result, Side, TimestampSource,
+};
+#[cfg(not(target_os = "android"))]
+use crate::{
+    util::{get_device_by_bus_path, usb_bus_path},
+    GlassesDescriptor, GlassesModel, OpenOptions, Transport,
 };
 
 /// The main structure representing a connected Rokid Air glasses
@@ -23,6 +29,11 @@ pub struct RokidAir {
     proxy_sensor_was_far: bool,
     pending_events: VecDeque<GlassesEvent>,
     model: RokidModel,
+    read_timeout: Duration,
+    vsync_events: bool,
+    last_vsync_timestamp: u64,
+    display_brightness: Option<u8>,
+    volume: Option<u8>,
 }
 
 enum RokidModel {
@@ -37,88 +48,12 @@ const TIMEOUT: Duration = Duration::from_millis(250);
 
 impl ARGlasses for RokidAir {
     fn serial(&mut self) -> Result<String> {
-        let mut result = [0u8; 0x40];
-        self.device_handle.read_control(
-            request_type(
-                rusb::Direction::In,
-                rusb::RequestType::Vendor,
-                rusb::Recipient::Device,
-            ),
-            0x81,
-            0x100,
-            0,
-            &mut result,
-            TIMEOUT,
-        )?;
-        Ok(
-            String::from_utf8(result.iter().copied().take_while(|c| *c != 0).collect())
-                .map_err(|_| "Invalid serial string")?,
-        )
+        read_serial(&self.device_handle)
     }
 
     fn read_event(&mut self) -> Result<GlassesEvent> {
         while self.pending_events.is_empty() {
-            let mut packet_data = [0u8; 0x40];
-            self.device_handle
-                .read_interrupt(INTERRUPT_IN_ENDPOINT, &mut packet_data, TIMEOUT)?;
-            match packet_data[0] {
-                2 => {
-                    let packet: &MiscPacket = bytemuck::cast_ref(&packet_data);
-                    self.handle_key_press(packet.keys_pressed);
-                    self.handle_proxy_sensor(packet.proxy_sensor);
-                }
-                4 => {
-                    let packet: &SensorPacket = bytemuck::cast_ref(&packet_data);
-                    let sensor_data =
-                        Vector3::from_data(nalgebra::ArrayStorage([packet.vector; 1]));
-                    match packet.sensor_type {
-                        1 => self.last_accelerometer = Some((sensor_data, packet.timestamp)),
-                        2 => self.last_gyroscope = Some((sensor_data, packet.timestamp)),
-                        // TODO: Magnetometer apparently gives an accuracy value too
-                        3 => self.pending_events.push_back(GlassesEvent::Magnetometer {
-                            magnetometer: sensor_data,
-                            timestamp: packet.timestamp,
-                        }),
-                        _ => (),
-                    }
-                    if let (Some((accelerometer, acc_ts)), Some((gyroscope, gyro_ts))) =
-                        (self.last_accelerometer, self.last_gyroscope)
-                    {
-                        if acc_ts == gyro_ts {
-                            self.last_gyroscope = None;
-                            self.last_accelerometer = None;
-                            self.pending_events.push_back(GlassesEvent::AccGyro {
-                                accelerometer,
-                                gyroscope,
-                                timestamp: acc_ts,
-                            });
-                        }
-                    }
-                }
-                17 => {
-                    let packet: &CombinedPacket = bytemuck::cast_ref(&packet_data);
-                    let timestamp = packet.timestamp / 1000;
-                    self.pending_events.push_back(GlassesEvent::AccGyro {
-                        accelerometer: Vector3::from_data(nalgebra::ArrayStorage(
-                            [packet.accelerometer; 1],
-                        )),
-                        gyroscope: Vector3::from_data(nalgebra::ArrayStorage(
-                            [packet.gyroscope; 1],
-                        )),
-                        timestamp,
-                    });
-                    self.pending_events.push_back(GlassesEvent::Magnetometer {
-                        magnetometer: Vector3::from_data(nalgebra::ArrayStorage(
-                            [packet.magnetometer; 1],
-                        )),
-                        timestamp,
-                    });
-                    // NOTE: was always zero on my Max
-                    self.handle_key_press(packet.keys_pressed);
-                    self.handle_proxy_sensor(packet.proxy_sensor);
-                }
-                _ => {}
-            }
+            self.read_packet()?;
         }
         Ok(self.pending_events.pop_front().unwrap())
     }
@@ -169,6 +104,42 @@ impl ARGlasses for RokidAir {
         Ok(())
     }
 
+    /// Only supported on the Rokid Max, which reports its brightness (as set with its
+    /// buttons) in every sensor packet. Setting it over USB is not supported.
+    /// The range of the reported value has not been verified on hardware yet, so
+    /// [`ARGlasses::brightness_range`] is not implemented.
+    fn get_brightness(&mut self) -> Result<u8> {
+        if let RokidModel::Air = self.model {
+            return Err(Error::NotImplemented);
+        }
+        // Sensor packets are sent continuously, this should not take long
+        for _ in 0..64 {
+            if let Some(brightness) = self.display_brightness {
+                return Ok(brightness);
+            }
+            self.read_packet()?;
+        }
+        Err(Error::PacketTimeout)
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        let mut result = vec![
+            DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+            DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+        ];
+        if let RokidModel::Max = self.model {
+            // Refresh rates are from the product specs
+            result.push(DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 120));
+            result.push(DisplayModeInfo::new(
+                DisplayLayout::SideBySide,
+                1920,
+                1080,
+                90,
+            ));
+        }
+        result
+    }
+
     fn display_fov(&self) -> f32 {
         match self.model {
             RokidModel::Air => {
@@ -203,6 +174,22 @@ impl ARGlasses for RokidAir {
         }
     }
 
+    fn capabilities(&self) -> Capabilities {
+        // Brightness, volume and V-sync are only sent in the combined sensor packets of the Max
+        let max = matches!(self.model, RokidModel::Max);
+        Capabilities {
+            magnetometer: true,
+            proximity: true,
+            vsync: max,
+            button_events: max,
+            key_count: 8,
+            brightness_read: max,
+            stereo: true,
+            timestamps: TimestampSource::Device,
+            ..Default::default()
+        }
+    }
+
     fn display_delay(&self) -> u64 {
         match self.model {
             RokidModel::Air => 15000,
@@ -263,6 +250,69 @@ struct CombinedPacket {
 unsafe impl bytemuck::Zeroable for CombinedPacket {}
 unsafe impl bytemuck::Pod for CombinedPacket {}
 
+/// Works without claiming the interface, so it can also be used for listing devices
+fn read_serial(device_handle: &DeviceHandle<GlobalContext>) -> Result<String> {
+    let mut result = [0u8; 0x40];
+    device_handle.read_control(
+        request_type(
+            rusb::Direction::In,
+            rusb::RequestType::Vendor,
+            rusb::Recipient::Device,
+        ),
+        0x81,
+        0x100,
+        0,
+        &mut result,
+        TIMEOUT,
+    )?;
+    Ok(
+        String::from_utf8(result.iter().copied().take_while(|c| *c != 0).collect())
+            .map_err(|_| "Invalid serial string")?,
+    )
+}
+
+fn read_model(device_handle: &DeviceHandle<GlobalContext>) -> Result<RokidModel> {
+    let product_string =
+        device_handle.read_product_string_ascii(&device_handle.device().device_descriptor()?)?;
+    Ok(if product_string.contains("Max") {
+        RokidModel::Max
+    } else {
+        RokidModel::Air
+    })
+}
+
+/// List connected Rokid glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn list() -> Vec<GlassesDescriptor> {
+    let Ok(devices) = rusb::DeviceList::new() else {
+        return Vec::new();
+    };
+    let mut result = Vec::new();
+    for device in devices.iter() {
+        let Ok(desc) = device.device_descriptor() else {
+            continue;
+        };
+        if desc.vendor_id() != RokidAir::VID || desc.product_id() != RokidAir::PID {
+            continue;
+        }
+        // Opening the device does not claim it, so this works even if it is in use.
+        let handle = device.open().ok();
+        let model = handle.as_ref().and_then(|h| read_model(h).ok());
+        result.push(GlassesDescriptor {
+            model: match model {
+                Some(RokidModel::Max) => GlassesModel::RokidMax,
+                _ => GlassesModel::RokidAir,
+            },
+            vid: RokidAir::VID,
+            pid: RokidAir::PID,
+            serial: handle.as_ref().and_then(|h| read_serial(h).ok()),
+            transport: Transport::Usb,
+            bus_path: usb_bus_path(&device),
+        });
+    }
+    result
+}
+
 impl RokidAir {
     /// Vendor ID of the Rokid Air (Yes, it is 1234. Yes that's probably not very legit)
     pub const VID: u16 = 0x04d2;
@@ -290,6 +340,17 @@ impl RokidAir {
         Self::new_common(get_device_vid_pid(Self::VID, Self::PID)?.open()?)
     }
 
+    /// Connect to the device found by [`crate::list_glasses`]
+    #[cfg(not(target_os = "android"))]
+    pub fn open(descriptor: &GlassesDescriptor, options: &OpenOptions) -> Result<Self> {
+        let mut result = Self::new_common(get_device_by_bus_path(&descriptor.bus_path)?.open()?)?;
+        if let Some(read_timeout) = options.read_timeout {
+            result.read_timeout = read_timeout;
+        }
+        result.vsync_events = options.vsync_events;
+        Ok(result)
+    }
+
     fn new_common(mut device_handle: DeviceHandle<GlobalContext>) -> Result<Self> {
         device_handle.set_auto_detach_kernel_driver(true)?;
 
@@ -298,24 +359,119 @@ impl RokidAir {
                 || Error::Other("Could not find endpoint, wrong USB structure (probably)"),
             )?,
         )?;
-        let product_string = device_handle
-            .read_product_string_ascii(&device_handle.device().device_descriptor()?)?;
+        let model = read_model(&device_handle)?;
         let result = Self {
             device_handle,
             last_accelerometer: None,
             last_gyroscope: None,
             previous_key_states: 0,
             proxy_sensor_was_far: false,
-            model: if product_string.contains("Max") {
-                RokidModel::Max
-            } else {
-                RokidModel::Air
-            },
+            model,
             pending_events: Default::default(),
+            read_timeout: TIMEOUT,
+            vsync_events: true,
+            last_vsync_timestamp: 0,
+            display_brightness: None,
+            volume: None,
         };
         Ok(result)
     }
 
+    /// Read and process a single packet, queueing the resulting events
+    fn read_packet(&mut self) -> Result<()> {
+        let mut packet_data = [0u8; 0x40];
+        self.device_handle.read_interrupt(
+            INTERRUPT_IN_ENDPOINT,
+            &mut packet_data,
+            self.read_timeout,
+        )?;
+        match packet_data[0] {
+            2 => {
+                let packet: &MiscPacket = bytemuck::cast_ref(&packet_data);
+                self.handle_key_press(packet.keys_pressed);
+                self.handle_proxy_sensor(packet.proxy_sensor);
+            }
+            4 => {
+                let packet: &SensorPacket = bytemuck::cast_ref(&packet_data);
+                let sensor_data = Vector3::from_data(nalgebra::ArrayStorage([packet.vector; 1]));
+                match packet.sensor_type {
+                    1 => self.last_accelerometer = Some((sensor_data, packet.timestamp)),
+                    2 => self.last_gyroscope = Some((sensor_data, packet.timestamp)),
+                    // TODO: Magnetometer apparently gives an accuracy value too
+                    3 => self.pending_events.push_back(GlassesEvent::Magnetometer {
+                        magnetometer: sensor_data,
+                        timestamp: packet.timestamp,
+                    }),
+                    _ => (),
+                }
+                if let (Some((accelerometer, acc_ts)), Some((gyroscope, gyro_ts))) =
+                    (self.last_accelerometer, self.last_gyroscope)
+                {
+                    if acc_ts == gyro_ts {
+                        self.last_gyroscope = None;
+                        self.last_accelerometer = None;
+                        self.pending_events.push_back(GlassesEvent::AccGyro {
+                            accelerometer,
+                            gyroscope,
+                            timestamp: acc_ts,
+                        });
+                    }
+                }
+            }
+            17 => {
+                let packet: &CombinedPacket = bytemuck::cast_ref(&packet_data);
+                let timestamp = packet.timestamp / 1000;
+                self.pending_events.push_back(GlassesEvent::AccGyro {
+                    accelerometer: Vector3::from_data(nalgebra::ArrayStorage(
+                        [packet.accelerometer; 1],
+                    )),
+                    gyroscope: Vector3::from_data(nalgebra::ArrayStorage([packet.gyroscope; 1])),
+                    timestamp,
+                });
+                self.pending_events.push_back(GlassesEvent::Magnetometer {
+                    magnetometer: Vector3::from_data(nalgebra::ArrayStorage(
+                        [packet.magnetometer; 1],
+                    )),
+                    timestamp,
+                });
+                // NOTE: was always zero on my Max
+                self.handle_key_press(packet.keys_pressed);
+                self.handle_proxy_sensor(packet.proxy_sensor);
+                self.handle_vsync(packet.vsync_timestamp);
+                let display_brightness = packet.display_brightness;
+                if self
+                    .display_brightness
+                    .is_some_and(|b| b != display_brightness)
+                {
+                    self.pending_events
+                        .push_back(GlassesEvent::BrightnessChanged(display_brightness));
+                }
+                self.display_brightness = Some(display_brightness);
+                let volume = packet.volume;
+                if self.volume.is_some_and(|v| v != volume) {
+                    self.pending_events
+                        .push_back(GlassesEvent::VolumeChanged(volume));
+                }
+                self.volume = Some(volume);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_vsync(&mut self, vsync_timestamp: u64) {
+        // Zero means no V-sync since startup (e.g. no video signal)
+        if vsync_timestamp != 0 && vsync_timestamp != self.last_vsync_timestamp {
+            self.last_vsync_timestamp = vsync_timestamp;
+            if self.vsync_events {
+                self.pending_events.push_back(GlassesEvent::VSync {
+                    // Same unit as the sensor timestamp
+                    timestamp: Some(vsync_timestamp / 1000),
+                });
+            }
+        }
+    }
+
     fn handle_key_press(&mut self, keys_pressed: u8) {
         let new_presses = keys_pressed & !self.previous_key_states;
         for bit in 0..8 {
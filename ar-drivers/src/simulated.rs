@@ -0,0 +1,680 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Simulated AR glasses, for development and testing without hardware. See [`SimulatedGlasses`]
+//!
+//! The simulator integrates a scripted head-motion [`Trajectory`] and synthesizes
+//! accelerometer, gyroscope and magnetometer readings from the resulting ground truth
+//! attitude, with configurable noise, bias, sample rate and timestamp jitter.
+//! Key presses and proximity events can be scripted too.
+//!
+//! When the `simulated` feature is enabled, [`crate::any_glasses`] returns the simulator
+//! if the `AR_DRIVERS_SIMULATED` environment variable is set, or if no real glasses
+//! were found at all. If real glasses were found but could not be opened, their error
+//! is returned instead.
+
+use std::{
+    collections::VecDeque,
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use nalgebra::{Isometry3, Translation3, UnitQuaternion, Vector3};
+
+use crate::{
+    ARGlasses, Capabilities, DisplayLayout, DisplayMode, DisplayModeInfo, Error, GlassesEvent,
+    Result, Side, TimestampSource,
+};
+
+/// Setting this environment variable (to anything) makes [`crate::any_glasses`]
+/// return a [`SimulatedGlasses`] instead of looking for real hardware.
+pub const SIMULATED_ENV_VAR: &str = "AR_DRIVERS_SIMULATED";
+
+const DISPLAY_MODES: &[DisplayModeInfo] = &[
+    DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 60),
+    DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 60),
+    DisplayModeInfo::new(DisplayLayout::HalfSideBySide, 960, 1080, 60),
+    DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 120),
+    DisplayModeInfo::new(DisplayLayout::Mirror, 1920, 1080, 90),
+    DisplayModeInfo::new(DisplayLayout::SideBySide, 1920, 1080, 90),
+];
+
+/// The main structure representing simulated glasses
+pub struct SimulatedGlasses {
+    config: SimulationConfig,
+    rng: Rng,
+    attitude: UnitQuaternion<f32>,
+    sample_index: u64,
+    time: u64,
+    last_magnetometer: Option<u64>,
+    next_scripted_event: usize,
+    display_mode: DisplayModeInfo,
+    brightness: u8,
+    pending_events: VecDeque<GlassesEvent>,
+    started: Instant,
+}
+
+/// Configuration of a [`SimulatedGlasses`] instance
+#[derive(Debug, Clone)]
+pub struct SimulationConfig {
+    /// Head motion to simulate
+    pub trajectory: Trajectory,
+    /// Ground truth attitude at the start: the rotation from the (RUB) device frame
+    /// to the (RUB) world frame. The trajectory is applied on top of this.
+    pub initial_attitude: UnitQuaternion<f32>,
+    /// Accelerometer and gyroscope sample rate, in Hz
+    pub sample_rate: f32,
+    /// Magnetometer sample rate, in Hz. `None` disables the magnetometer.
+    pub magnetometer_rate: Option<f32>,
+    /// Standard deviation of the gyroscope noise, in rad/sec
+    pub gyro_noise: f32,
+    /// Constant gyroscope bias, in rad/sec
+    pub gyro_bias: Vector3<f32>,
+    /// Standard deviation of the accelerometer noise, in m/s^2
+    pub accelerometer_noise: f32,
+    /// Constant accelerometer bias, in m/s^2
+    pub accelerometer_bias: Vector3<f32>,
+    /// Standard deviation of the magnetometer noise, in uT
+    pub magnetometer_noise: f32,
+    /// Earth's magnetic field in the (RUB) world frame, in uT.
+    /// The default points north (-Z) and down, roughly like in central Europe.
+    pub magnetic_field: Vector3<f32>,
+    /// Standard deviation of the timestamp jitter, in usecs. Large values
+    /// (compared to the sample period) produce non-monotonic timestamps.
+    pub timestamp_jitter: f32,
+    /// Key press and proximity events, sent at the specified time since start
+    pub scripted_events: Vec<(Duration, GlassesEvent)>,
+    /// Pace [`ARGlasses::read_event`] to the wall clock, like real hardware.
+    /// If false, events are generated as fast as possible.
+    pub realtime: bool,
+    /// Seed of the noise generator. The same seed produces the same event stream.
+    pub seed: u64,
+    /// Serial number reported by [`ARGlasses::serial`]
+    pub serial: String,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            trajectory: Trajectory::look_around(),
+            initial_attitude: UnitQuaternion::identity(),
+            sample_rate: 1000.0,
+            magnetometer_rate: Some(100.0),
+            gyro_noise: 0.003,
+            gyro_bias: Vector3::new(0.002, -0.001, 0.0015),
+            accelerometer_noise: 0.02,
+            accelerometer_bias: Vector3::zeros(),
+            magnetometer_noise: 0.3,
+            magnetic_field: Vector3::new(0.0, -44.0, -20.0),
+            timestamp_jitter: 0.0,
+            scripted_events: vec![
+                (Duration::from_secs(1), GlassesEvent::ProximityNear),
+                (Duration::from_secs(5), GlassesEvent::KeyPress(0)),
+            ],
+            realtime: true,
+            seed: 0x5eed,
+            serial: "SIMULATED0001".into(),
+        }
+    }
+}
+
+/// A single type of head motion, see [`MotionSegment`]
+#[derive(Debug, Clone)]
+pub enum Motion {
+    /// The head does not move
+    Still,
+    /// Constant angular velocity, in rad/sec, in the (RUB) device frame
+    Rotate(Vector3<f32>),
+    /// Sinusoidal rotation back and forth around an axis of the (RUB) device frame,
+    /// starting from the current attitude
+    Oscillate {
+        /// Rotation axis. Does not need to be normalized.
+        axis: Vector3<f32>,
+        /// Peak angular deviation, in radians
+        amplitude: f32,
+        /// Frequency of the oscillation, in Hz
+        frequency: f32,
+    },
+}
+
+/// A piece of a [`Trajectory`]
+#[derive(Debug, Clone)]
+pub struct MotionSegment {
+    /// How long the motion lasts
+    pub duration: Duration,
+    /// The motion itself
+    pub motion: Motion,
+}
+
+/// Scripted head motion: a sequence of motion segments
+#[derive(Debug, Clone, Default)]
+pub struct Trajectory {
+    /// The segments, in order
+    pub segments: Vec<MotionSegment>,
+    /// Start over after the last segment. Otherwise the head stays still after it.
+    pub looping: bool,
+}
+
+impl Trajectory {
+    /// Head that does not move at all
+    pub fn still() -> Self {
+        Self::default()
+    }
+
+    /// A looping sequence of pans, nods and tilts, with short pauses in between
+    pub fn look_around() -> Self {
+        let segment = |secs: f32, motion: Motion| MotionSegment {
+            duration: Duration::from_secs_f32(secs),
+            motion,
+        };
+        let turn = |x: f32, y: f32, z: f32| Motion::Rotate(Vector3::new(x, y, z));
+        Self {
+            segments: vec![
+                segment(2.0, Motion::Still),
+                // Look left, then right, then back to the middle
+                segment(1.0, turn(0.0, 0.5, 0.0)),
+                segment(2.0, turn(0.0, -0.5, 0.0)),
+                segment(1.0, turn(0.0, 0.5, 0.0)),
+                segment(1.0, Motion::Still),
+                // Nod
+                segment(
+                    2.0,
+                    Motion::Oscillate {
+                        axis: Vector3::x(),
+                        amplitude: 0.3,
+                        frequency: 1.0,
+                    },
+                ),
+                // Tilt head to the side and back
+                segment(0.5, turn(0.0, 0.0, 0.8)),
+                segment(0.5, turn(0.0, 0.0, -0.8)),
+            ],
+            looping: true,
+        }
+    }
+
+    /// Total length of the trajectory
+    pub fn duration(&self) -> Duration {
+        self.segments.iter().map(|s| s.duration).sum()
+    }
+
+    /// True angular velocity at `time` (since start), in rad/sec, in the (RUB) device frame
+    pub fn angular_velocity(&self, time: Duration) -> Vector3<f32> {
+        let total = self.duration();
+        if total.is_zero() {
+            return Vector3::zeros();
+        }
+        let mut time = time;
+        if time >= total {
+            if !self.looping {
+                return Vector3::zeros();
+            }
+            time = Duration::from_nanos((time.as_nanos() % total.as_nanos()) as u64);
+        }
+        for segment in &self.segments {
+            if time < segment.duration {
+                return segment.motion.angular_velocity(time);
+            }
+            time -= segment.duration;
+        }
+        Vector3::zeros()
+    }
+}
+
+impl Motion {
+    fn angular_velocity(&self, since_segment_start: Duration) -> Vector3<f32> {
+        match self {
+            Motion::Still => Vector3::zeros(),
+            Motion::Rotate(velocity) => *velocity,
+            Motion::Oscillate {
+                axis,
+                amplitude,
+                frequency,
+            } => {
+                let omega = 2.0 * std::f32::consts::PI * frequency;
+                axis.normalize()
+                    * (amplitude * omega * (omega * since_segment_start.as_secs_f32()).cos())
+            }
+        }
+    }
+}
+
+impl ARGlasses for SimulatedGlasses {
+    fn serial(&mut self) -> Result<String> {
+        Ok(self.config.serial.clone())
+    }
+
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        loop {
+            if let Some(event) = self.pending_events.pop_front() {
+                return Ok(event);
+            }
+            self.step();
+        }
+    }
+
+    fn get_display_mode(&mut self) -> Result<DisplayMode> {
+        Ok(self.display_mode.display_mode())
+    }
+
+    fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
+        self.display_mode = *DISPLAY_MODES
+            .iter()
+            .find(|mode| mode.display_mode() == display_mode)
+            .ok_or(Error::Other("Display mode not supported"))?;
+        Ok(())
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        DISPLAY_MODES.to_vec()
+    }
+
+    fn get_display_mode_info(&mut self) -> Result<DisplayModeInfo> {
+        Ok(self.display_mode)
+    }
+
+    fn set_display_mode_info(&mut self, display_mode: DisplayModeInfo) -> Result<()> {
+        if !DISPLAY_MODES.contains(&display_mode) {
+            return Err(Error::Other("Display mode not supported"));
+        }
+        self.display_mode = display_mode;
+        Ok(())
+    }
+
+    fn get_brightness(&mut self) -> Result<u8> {
+        Ok(self.brightness)
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        if brightness > 7 {
+            return Err(Error::Other("Brightness out of range"));
+        }
+        self.brightness = brightness;
+        Ok(())
+    }
+
+    fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        Ok(0..=7)
+    }
+
+    fn display_fov(&self) -> f32 {
+        24.0f32.to_radians()
+    }
+
+    fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        let side_multiplier = match side {
+            Side::Left => -0.5,
+            Side::Right => 0.5,
+        };
+        Translation3::new(ipd as f64 * side_multiplier, 0.0, 0.0).into()
+    }
+
+    fn name(&self) -> &'static str {
+        "Simulated glasses"
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // Only the scripted events are sent, in addition to the IMU data
+        Capabilities {
+            magnetometer: self.config.magnetometer_rate.is_some(),
+            proximity: self.config.scripted_events.iter().any(|(_, event)| {
+                matches!(
+                    event,
+                    GlassesEvent::ProximityNear | GlassesEvent::ProximityFar
+                )
+            }),
+            key_count: self
+                .config
+                .scripted_events
+                .iter()
+                .filter_map(|(_, event)| match event {
+                    GlassesEvent::KeyPress(key) => Some(key.saturating_add(1)),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0),
+            brightness_read: true,
+            brightness_write: true,
+            stereo: true,
+            timestamps: TimestampSource::Device,
+            ..Default::default()
+        }
+    }
+
+    fn display_delay(&self) -> u64 {
+        10000
+    }
+}
+
+impl SimulatedGlasses {
+    /// Create a new simulated device. The simulation starts immediately, i.e.
+    /// in realtime mode the first event is due right away.
+    pub fn new(config: SimulationConfig) -> Self {
+        Self {
+            rng: Rng::new(config.seed),
+            attitude: config.initial_attitude,
+            config,
+            sample_index: 0,
+            time: 0,
+            last_magnetometer: None,
+            next_scripted_event: 0,
+            display_mode: DISPLAY_MODES[0],
+            brightness: 4,
+            pending_events: Default::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// The configuration the simulator was created with
+    pub fn config(&self) -> &SimulationConfig {
+        &self.config
+    }
+
+    /// Ground truth attitude at the time of the last generated sample:
+    /// the rotation from the (RUB) device frame to the (RUB) world frame
+    pub fn true_attitude(&self) -> UnitQuaternion<f32> {
+        self.attitude
+    }
+
+    /// Simulation time of the last generated sample (without jitter), in usecs
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    fn step(&mut self) {
+        let previous_time = self.time;
+        self.sample_index += 1;
+        self.time = (self.sample_index as f64 * 1_000_000.0 / self.config.sample_rate as f64)
+            .round() as u64;
+
+        // Integrate using the velocity at the middle of the interval, and report that
+        // value, so that a perfect integrator of the gyro readings reproduces the
+        // ground truth exactly.
+        let mid_time = Duration::from_micros((previous_time + self.time) / 2);
+        let angular_velocity = self.config.trajectory.angular_velocity(mid_time);
+        let dt = (self.time - previous_time) as f32 / 1_000_000.0;
+        self.attitude *= UnitQuaternion::from_scaled_axis(angular_velocity * dt);
+
+        if self.config.realtime {
+            let due = Duration::from_micros(self.time);
+            if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+
+        while let Some((at, event)) = self.config.scripted_events.get(self.next_scripted_event) {
+            if at.as_micros() as u64 > self.time {
+                break;
+            }
+            self.pending_events.push_back(event.clone());
+            self.next_scripted_event += 1;
+        }
+
+        let timestamp = self.jittered_timestamp();
+        let to_device = self.attitude.inverse();
+        let gravity_reaction = Vector3::new(0.0, 9.81, 0.0);
+        let accelerometer = to_device * gravity_reaction
+            + self.config.accelerometer_bias
+            + self.noise_vector(self.config.accelerometer_noise);
+        let gyroscope =
+            angular_velocity + self.config.gyro_bias + self.noise_vector(self.config.gyro_noise);
+        self.pending_events.push_back(GlassesEvent::AccGyro {
+            accelerometer,
+            gyroscope,
+            timestamp,
+        });
+
+        if let Some(rate) = self.config.magnetometer_rate {
+            let period = (1_000_000.0 / rate) as u64;
+            if self
+                .last_magnetometer
+                .is_none_or(|last| self.time >= last + period)
+            {
+                self.last_magnetometer = Some(self.time);
+                let magnetometer = to_device * self.config.magnetic_field
+                    + self.noise_vector(self.config.magnetometer_noise);
+                self.pending_events.push_back(GlassesEvent::Magnetometer {
+                    magnetometer,
+                    timestamp,
+                });
+            }
+        }
+    }
+
+    fn jittered_timestamp(&mut self) -> u64 {
+        if self.config.timestamp_jitter <= 0.0 {
+            return self.time;
+        }
+        let jitter = (self.rng.gaussian() * self.config.timestamp_jitter).round() as i64;
+        self.time.saturating_add_signed(jitter)
+    }
+
+    fn noise_vector(&mut self, std_dev: f32) -> Vector3<f32> {
+        if std_dev <= 0.0 {
+            return Vector3::zeros();
+        }
+        Vector3::new(
+            self.rng.gaussian(),
+            self.rng.gaussian(),
+            self.rng.gaussian(),
+        ) * std_dev
+    }
+}
+
+/// Small deterministic noise source (xorshift64*), so that we don't need `rand`
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self {
+            // The state must never be zero
+            state: (seed ^ 0x9e37_79b9_7f4a_7c15).max(1),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in (0, 1]
+    fn uniform(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+    }
+
+    /// Standard normal distribution, using the Box-Muller transform
+    fn gaussian(&mut self) -> f32 {
+        let u1 = self.uniform();
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+}
+
+/// Descriptor of the default simulated glasses, see [`crate::list_glasses`]
+#[cfg(not(target_os = "android"))]
+pub(crate) fn descriptor() -> crate::GlassesDescriptor {
+    crate::GlassesDescriptor {
+        model: crate::GlassesModel::Simulated,
+        vid: 0,
+        pid: 0,
+        serial: Some(SimulationConfig::default().serial),
+        transport: crate::Transport::Simulated,
+        bus_path: "simulated".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> SimulationConfig {
+        SimulationConfig {
+            realtime: false,
+            ..Default::default()
+        }
+    }
+
+    fn noiseless(trajectory: Trajectory) -> SimulationConfig {
+        SimulationConfig {
+            trajectory,
+            gyro_noise: 0.0,
+            gyro_bias: Vector3::zeros(),
+            accelerometer_noise: 0.0,
+            magnetometer_noise: 0.0,
+            scripted_events: Vec::new(),
+            ..config()
+        }
+    }
+
+    /// Read events up to and including the AccGyro sample at `time`
+    fn read_until(glasses: &mut SimulatedGlasses, time: u64) -> Vec<GlassesEvent> {
+        let mut events = Vec::new();
+        while glasses.time() < time || !glasses.pending_events.is_empty() {
+            events.push(glasses.read_event().unwrap());
+        }
+        events
+    }
+
+    #[test]
+    fn test_same_seed_same_events() {
+        let config = SimulationConfig {
+            timestamp_jitter: 300.0,
+            ..config()
+        };
+        let run = |config: &SimulationConfig| {
+            let mut glasses = SimulatedGlasses::new(config.clone());
+            format!("{:?}", read_until(&mut glasses, 6_000_000))
+        };
+        assert_eq!(run(&config), run(&config));
+        let other_seed = SimulationConfig {
+            seed: config.seed + 1,
+            ..config.clone()
+        };
+        assert_ne!(run(&config), run(&other_seed));
+    }
+
+    #[test]
+    fn test_magnetometer_rate() {
+        let mut glasses = SimulatedGlasses::new(config());
+        let events = read_until(&mut glasses, 1_000_000);
+        let count = |f: fn(&GlassesEvent) -> bool| events.iter().filter(|e| f(e)).count();
+        assert_eq!(count(|e| matches!(e, GlassesEvent::AccGyro { .. })), 1000);
+        assert_eq!(
+            count(|e| matches!(e, GlassesEvent::Magnetometer { .. })),
+            100
+        );
+
+        let mut glasses = SimulatedGlasses::new(SimulationConfig {
+            magnetometer_rate: None,
+            ..config()
+        });
+        let events = read_until(&mut glasses, 1_000_000);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, GlassesEvent::Magnetometer { .. })));
+    }
+
+    #[test]
+    fn test_scripted_events() {
+        let mut glasses = SimulatedGlasses::new(SimulationConfig {
+            scripted_events: vec![
+                (Duration::from_micros(10_500), GlassesEvent::ProximityNear),
+                (Duration::from_millis(25), GlassesEvent::KeyPress(2)),
+            ],
+            ..config()
+        });
+        let events = read_until(&mut glasses, 100_000);
+        // Each scripted event comes right before the first sample at or after its time
+        let timestamp_after = |position: usize| match events[position + 1] {
+            GlassesEvent::AccGyro { timestamp, .. } => timestamp,
+            _ => panic!("{:?}", events[position + 1]),
+        };
+        let near = events
+            .iter()
+            .position(|e| matches!(e, GlassesEvent::ProximityNear))
+            .unwrap();
+        assert_eq!(timestamp_after(near), 11_000);
+        let key = events
+            .iter()
+            .position(|e| matches!(e, GlassesEvent::KeyPress(2)))
+            .unwrap();
+        assert_eq!(timestamp_after(key), 25_000);
+        assert_eq!(events.len(), 100 + 10 + 2);
+    }
+
+    #[test]
+    fn test_jittered_timestamps() {
+        let mut glasses = SimulatedGlasses::new(SimulationConfig {
+            timestamp_jitter: 600.0,
+            magnetometer_rate: None,
+            scripted_events: Vec::new(),
+            ..config()
+        });
+        let mut offsets = Vec::new();
+        let mut timestamps = Vec::new();
+        for _ in 0..10_000 {
+            if let GlassesEvent::AccGyro { timestamp, .. } = glasses.read_event().unwrap() {
+                offsets.push(timestamp as f64 - glasses.time() as f64);
+                timestamps.push(timestamp);
+            }
+        }
+        let mean = offsets.iter().sum::<f64>() / offsets.len() as f64;
+        let std_dev =
+            (offsets.iter().map(|o| (o - mean).powi(2)).sum::<f64>() / offsets.len() as f64).sqrt();
+        assert!(mean.abs() < 30.0, "{mean}");
+        assert!((std_dev - 600.0).abs() < 30.0, "{std_dev}");
+        // Not rounded to the sample period, and large enough to go backwards sometimes
+        assert!(timestamps.iter().any(|t| t % 1000 != 0));
+        assert!(timestamps.windows(2).any(|w| w[1] < w[0]));
+    }
+
+    #[test]
+    fn test_true_attitude_rotate() {
+        let velocity = Vector3::new(0.1, 0.5, -0.2);
+        let mut glasses = SimulatedGlasses::new(noiseless(Trajectory {
+            segments: vec![MotionSegment {
+                duration: Duration::from_secs(1),
+                motion: Motion::Rotate(velocity),
+            }],
+            looping: false,
+        }));
+        // Integrating the gyro readings gives the same attitude
+        let mut integrated = UnitQuaternion::identity();
+        for event in read_until(&mut glasses, 2_000_000) {
+            if let GlassesEvent::AccGyro { gyroscope, .. } = event {
+                integrated *= UnitQuaternion::from_scaled_axis(gyroscope * 0.001);
+            }
+        }
+        let expected = UnitQuaternion::from_scaled_axis(velocity);
+        assert!(glasses.true_attitude().angle_to(&expected) < 1e-4);
+        assert!(integrated.angle_to(&expected) < 1e-4);
+    }
+
+    #[test]
+    fn test_true_attitude_oscillate() {
+        let mut glasses = SimulatedGlasses::new(noiseless(Trajectory {
+            segments: vec![MotionSegment {
+                duration: Duration::from_secs(2),
+                motion: Motion::Oscillate {
+                    axis: Vector3::new(2.0, 0.0, 0.0),
+                    amplitude: 0.3,
+                    frequency: 1.0,
+                },
+            }],
+            looping: false,
+        }));
+        // The angle follows amplitude * sin(2 * PI * frequency * t)
+        read_until(&mut glasses, 250_000);
+        let expected = UnitQuaternion::from_scaled_axis(Vector3::new(0.3, 0.0, 0.0));
+        assert!(glasses.true_attitude().angle_to(&expected) < 1e-4);
+        read_until(&mut glasses, 1_750_000);
+        assert!(glasses.true_attitude().angle_to(&expected.inverse()) < 1e-4);
+        read_until(&mut glasses, 2_000_000);
+        assert!(glasses.true_attitude().angle() < 1e-4);
+    }
+}
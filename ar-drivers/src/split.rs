@@ -0,0 +1,224 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Concurrent access to glasses. See [`split_glasses`]
+
+use std::{
+    ops::RangeInclusive,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use nalgebra::Isometry3;
+
+use crate::{
+    ARGlasses, CameraDescriptor, Capabilities, DisplayMatrices, DisplayMode, DisplayModeInfo,
+    GlassesEvent, Result, Side,
+};
+
+/// Split opened glasses into an event reader and a cloneable controller, so that one
+/// thread can loop on [`GlassesReader::read_event`] while others change settings.
+///
+/// Calls through the two halves are serialized: a command waits for the read in
+/// progress (at most the read timeout of the driver), and is then executed before
+/// the next read starts. Command responses are therefore always returned to the
+/// caller of the command. Unrelated packets received while waiting for a response
+/// (e.g. key presses) are queued by the drivers, and are returned by the next
+/// [`GlassesReader::read_event`] call.
+pub fn split_glasses(glasses: Box<dyn ARGlasses>) -> (GlassesReader, GlassesController) {
+    let shared = Arc::new(Shared {
+        turn: Mutex::new(()),
+        glasses: Mutex::new(glasses),
+    });
+    (
+        GlassesReader {
+            shared: shared.clone(),
+        },
+        GlassesController { shared },
+    )
+}
+
+struct Shared {
+    // Taken before `glasses`, so that a waiting controller gets the glasses
+    // before the reader's next read, even though Mutex is not fair.
+    turn: Mutex<()>,
+    glasses: Mutex<Box<dyn ARGlasses>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Box<dyn ARGlasses>> {
+        let _turn = self.turn.lock().unwrap_or_else(|e| e.into_inner());
+        // A panic in a driver call does not make the glasses unusable
+        self.glasses.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Reader half of [`split_glasses`]
+pub struct GlassesReader {
+    shared: Arc<Shared>,
+}
+
+impl GlassesReader {
+    /// Get a single sensor event. Blocks. See [`ARGlasses::read_event`]
+    pub fn read_event(&mut self) -> Result<GlassesEvent> {
+        self.shared.lock().read_event()
+    }
+}
+
+/// Controller half of [`split_glasses`]. Can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct GlassesController {
+    shared: Arc<Shared>,
+}
+
+impl GlassesController {
+    /// See [`ARGlasses::serial`]
+    pub fn serial(&self) -> Result<String> {
+        self.shared.lock().serial()
+    }
+
+    /// See [`ARGlasses::get_display_mode`]
+    pub fn get_display_mode(&self) -> Result<DisplayMode> {
+        self.shared.lock().get_display_mode()
+    }
+
+    /// See [`ARGlasses::set_display_mode`]
+    pub fn set_display_mode(&self, display_mode: DisplayMode) -> Result<()> {
+        self.shared.lock().set_display_mode(display_mode)
+    }
+
+    /// See [`ARGlasses::supported_display_modes`]
+    pub fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        self.shared.lock().supported_display_modes()
+    }
+
+    /// See [`ARGlasses::get_display_mode_info`]
+    pub fn get_display_mode_info(&self) -> Result<DisplayModeInfo> {
+        self.shared.lock().get_display_mode_info()
+    }
+
+    /// See [`ARGlasses::set_display_mode_info`]
+    pub fn set_display_mode_info(&self, display_mode: DisplayModeInfo) -> Result<()> {
+        self.shared.lock().set_display_mode_info(display_mode)
+    }
+
+    /// See [`ARGlasses::get_brightness`]
+    pub fn get_brightness(&self) -> Result<u8> {
+        self.shared.lock().get_brightness()
+    }
+
+    /// See [`ARGlasses::set_brightness`]
+    pub fn set_brightness(&self, brightness: u8) -> Result<()> {
+        self.shared.lock().set_brightness(brightness)
+    }
+
+    /// See [`ARGlasses::brightness_range`]
+    pub fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        self.shared.lock().brightness_range()
+    }
+
+    /// See [`ARGlasses::display_fov`]
+    pub fn display_fov(&self) -> f32 {
+        self.shared.lock().display_fov()
+    }
+
+    /// See [`ARGlasses::imu_to_display_matrix`]
+    pub fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        self.shared.lock().imu_to_display_matrix(side, ipd)
+    }
+
+    /// See [`ARGlasses::name`]
+    pub fn name(&self) -> &'static str {
+        self.shared.lock().name()
+    }
+
+    /// See [`ARGlasses::cameras`]
+    pub fn cameras(&self) -> Result<Vec<CameraDescriptor>> {
+        self.shared.lock().cameras()
+    }
+
+    /// See [`ARGlasses::capabilities`]
+    pub fn capabilities(&self) -> Capabilities {
+        self.shared.lock().capabilities()
+    }
+
+    /// See [`ARGlasses::display_matrices`]
+    pub fn display_matrices(&self) -> Result<(DisplayMatrices, DisplayMatrices)> {
+        self.shared.lock().display_matrices()
+    }
+
+    /// See [`ARGlasses::display_delay`]
+    pub fn display_delay(&self) -> u64 {
+        self.shared.lock().display_delay()
+    }
+
+    /// See [`ARGlasses::rejected_packets`]
+    pub fn rejected_packets(&self) -> u64 {
+        self.shared.lock().rejected_packets()
+    }
+
+    /// Run an arbitrary function with exclusive access to the glasses
+    pub fn with_glasses<T>(&self, f: impl FnOnce(&mut dyn ARGlasses) -> T) -> T {
+        f(self.shared.lock().as_mut())
+    }
+}
+
+#[cfg(all(test, feature = "simulated"))]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc,
+        },
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::simulated::{SimulatedGlasses, SimulationConfig};
+
+    #[test]
+    fn test_controller_while_reading() {
+        // Paced like real hardware, so that the reader is blocked in read_event most of the time
+        let glasses = SimulatedGlasses::new(SimulationConfig {
+            realtime: true,
+            ..Default::default()
+        });
+        let (mut reader, controller) = split_glasses(Box::new(glasses));
+        let stop = Arc::new(AtomicBool::new(false));
+        let events = Arc::new(AtomicUsize::new(0));
+        let reader_thread = std::thread::spawn({
+            let stop = stop.clone();
+            let events = events.clone();
+            move || {
+                while !stop.load(Ordering::Relaxed) {
+                    reader.read_event().unwrap();
+                    events.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        let (done, finished) = mpsc::channel();
+        std::thread::spawn(move || {
+            for i in 0..100 {
+                let mode = if i % 2 == 0 {
+                    DisplayMode::Stereo
+                } else {
+                    DisplayMode::SameOnBoth
+                };
+                controller.set_display_mode(mode).unwrap();
+                assert_eq!(controller.get_display_mode().unwrap(), mode);
+            }
+            done.send(()).unwrap();
+        });
+        finished
+            .recv_timeout(Duration::from_secs(5))
+            .expect("controller calls did not finish");
+
+        let before = events.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(events.load(Ordering::Relaxed) > before);
+
+        stop.store(true, Ordering::Relaxed);
+        reader_thread.join().unwrap();
+    }
+}
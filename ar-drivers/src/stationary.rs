@@ -0,0 +1,350 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Detecting when the glasses are at rest, and re-estimating the gyroscope bias in
+//! those periods. See [`StationaryDetector`] and [`GyroBiasEstimator`]
+
+use std::time::Duration;
+
+use nalgebra::Vector3;
+
+use crate::GlassesEvent;
+
+/// Settings of [`StationaryDetector`]
+#[derive(Debug, Clone)]
+pub struct StationaryConfig {
+    /// Stationarity is decided on blocks of this length (in device time)
+    pub block_duration: Duration,
+    /// Maximum standard deviation of the gyroscope readings in a stationary block,
+    /// per axis, in rad/s
+    pub gyro_noise_limit: f32,
+    /// Maximum standard deviation of the accelerometer readings in a stationary block,
+    /// per axis, in m/s^2
+    pub accelerometer_noise_limit: f32,
+    /// Maximum plausible bias, in rad/s. Blocks with a larger average reading are
+    /// considered a slow, steady rotation instead of bias.
+    pub max_bias: f32,
+    /// Maximum difference between the norm of the average accelerometer reading and
+    /// 1G in a stationary block, in m/s^2
+    pub gravity_tolerance: f32,
+}
+
+impl Default for StationaryConfig {
+    fn default() -> Self {
+        Self {
+            block_duration: Duration::from_millis(500),
+            gyro_noise_limit: 0.01,
+            accelerometer_noise_limit: 0.1,
+            max_bias: 0.05,
+            gravity_tolerance: 0.5,
+        }
+    }
+}
+
+/// Transition reported by [`StationaryDetector::update`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionState {
+    /// The glasses are at rest (e.g. lying on a desk)
+    Stationary,
+    /// The glasses are being moved or worn
+    Moving,
+}
+
+/// Statistics of a completed block, returned by [`StationaryDetector::add_sample`]
+#[derive(Debug, Clone)]
+pub struct BlockSummary {
+    /// Timestamp of the first sample, in microseconds
+    pub start: u64,
+    /// Timestamp of the last sample, in microseconds
+    pub end: u64,
+    /// Average gyroscope reading, in rad/s
+    pub mean_gyroscope: Vector3<f64>,
+    /// Average accelerometer reading, in m/s^2
+    pub mean_accelerometer: Vector3<f64>,
+    /// Whether the glasses were at rest during the block
+    pub stationary: bool,
+}
+
+/// Decides whether the glasses are at rest, based on the variance of the gyroscope
+/// and accelerometer readings and the norm of the accelerometer reading, in blocks of
+/// [`StationaryConfig::block_duration`].
+#[derive(Debug, Clone)]
+pub struct StationaryDetector {
+    config: StationaryConfig,
+    block: Block,
+    state: Option<MotionState>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Block {
+    start: Option<u64>,
+    last: u64,
+    count: u32,
+    gyro_sum: Vector3<f64>,
+    gyro_square_sum: Vector3<f64>,
+    acc_sum: Vector3<f64>,
+    acc_square_sum: Vector3<f64>,
+}
+
+impl StationaryDetector {
+    const GRAVITY: f64 = 9.81;
+
+    /// Create a detector. The state is unknown until the first block is completed.
+    pub fn new(config: StationaryConfig) -> Self {
+        Self {
+            config,
+            block: Default::default(),
+            state: None,
+        }
+    }
+
+    /// The settings used by the detector
+    pub fn config(&self) -> &StationaryConfig {
+        &self.config
+    }
+
+    /// State decided on the last completed block
+    pub fn state(&self) -> Option<MotionState> {
+        self.state
+    }
+
+    /// Process an event. Returns the new state if it changed.
+    pub fn update(&mut self, event: &GlassesEvent) -> Option<MotionState> {
+        match event {
+            GlassesEvent::AccGyro {
+                accelerometer,
+                gyroscope,
+                timestamp,
+            } => {
+                let previous = self.state;
+                self.add_sample(accelerometer, gyroscope, *timestamp)?;
+                (self.state != previous).then_some(self.state).flatten()
+            }
+            GlassesEvent::Disconnected => {
+                self.reset();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Forget the current block and the state, e.g. because the timestamps restart
+    pub fn reset(&mut self) {
+        self.block = Default::default();
+        self.state = None;
+    }
+
+    /// Process a single sample. Returns the summary of the block if this sample
+    /// completed it.
+    pub fn add_sample(
+        &mut self,
+        accelerometer: &Vector3<f32>,
+        gyroscope: &Vector3<f32>,
+        timestamp: u64,
+    ) -> Option<BlockSummary> {
+        // Timestamps restarted, e.g. because of a reconnection
+        if timestamp < self.block.last {
+            self.block = Default::default();
+        }
+        let start = *self.block.start.get_or_insert(timestamp);
+        self.block.last = timestamp;
+        let gyroscope = gyroscope.map(|c| c as f64);
+        let accelerometer = accelerometer.map(|c| c as f64);
+        self.block.count += 1;
+        self.block.gyro_sum += gyroscope;
+        self.block.gyro_square_sum += gyroscope.component_mul(&gyroscope);
+        self.block.acc_sum += accelerometer;
+        self.block.acc_square_sum += accelerometer.component_mul(&accelerometer);
+
+        if timestamp - start < self.config.block_duration.as_micros() as u64 {
+            return None;
+        }
+        let block = std::mem::take(&mut self.block);
+        if block.count < 2 {
+            return None;
+        }
+        let n = block.count as f64;
+        let std_dev = |sum: &Vector3<f64>, square_sum: &Vector3<f64>| {
+            (square_sum / n - (sum / n).component_mul(&(sum / n)))
+                .map(|c| c.max(0.0).sqrt())
+                .max()
+        };
+        let mean_gyroscope = block.gyro_sum / n;
+        let mean_accelerometer = block.acc_sum / n;
+        let stationary = std_dev(&block.gyro_sum, &block.gyro_square_sum)
+            < self.config.gyro_noise_limit as f64
+            && std_dev(&block.acc_sum, &block.acc_square_sum)
+                < self.config.accelerometer_noise_limit as f64
+            && mean_gyroscope.amax() < self.config.max_bias as f64
+            && (mean_accelerometer.norm() - Self::GRAVITY).abs()
+                < self.config.gravity_tolerance as f64;
+        self.state = Some(if stationary {
+            MotionState::Stationary
+        } else {
+            MotionState::Moving
+        });
+        Some(BlockSummary {
+            start,
+            end: timestamp,
+            mean_gyroscope,
+            mean_accelerometer,
+            stationary,
+        })
+    }
+}
+
+impl Default for StationaryDetector {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+/// Settings of [`GyroBiasEstimator`]
+#[derive(Debug, Clone)]
+pub struct GyroBiasConfig {
+    /// Settings of the stationary detection
+    pub detector: StationaryConfig,
+    /// Weight of each new stationary block in the estimate (0..1). Lower values
+    /// give a less noisy, but slower adapting estimate.
+    pub learning_rate: f32,
+}
+
+impl Default for GyroBiasConfig {
+    fn default() -> Self {
+        Self {
+            detector: Default::default(),
+            learning_rate: 0.2,
+        }
+    }
+}
+
+/// Continuously re-estimates the gyroscope bias (on top of the factory calibration
+/// applied by the drivers), using the periods when the glasses are at rest.
+///
+/// Can be used with any [`crate::Fusion`] by passing the events through
+/// [`GyroBiasEstimator::compensate`] before the filter. For a temperature dependent
+/// model, see [`crate::gyro_bias::TemperatureBiasModel`].
+#[derive(Debug, Clone)]
+pub struct GyroBiasEstimator {
+    config: GyroBiasConfig,
+    detector: StationaryDetector,
+    bias: Option<Vector3<f64>>,
+}
+
+impl GyroBiasEstimator {
+    /// Create an estimator without an initial estimate
+    pub fn new(config: GyroBiasConfig) -> Self {
+        Self {
+            detector: StationaryDetector::new(config.detector.clone()),
+            config,
+            bias: None,
+        }
+    }
+
+    /// Start from a known bias (e.g. a saved one), in rad/s
+    pub fn with_initial_bias(mut self, bias: Vector3<f32>) -> Self {
+        self.bias = Some(bias.map(|c| c as f64));
+        self
+    }
+
+    /// Process an event. Returns the new motion state if it changed, see
+    /// [`StationaryDetector::update`].
+    pub fn update(&mut self, event: &GlassesEvent) -> Option<MotionState> {
+        let GlassesEvent::AccGyro {
+            accelerometer,
+            gyroscope,
+            timestamp,
+        } = event
+        else {
+            return self.detector.update(event);
+        };
+        let previous = self.detector.state();
+        let block = self
+            .detector
+            .add_sample(accelerometer, gyroscope, *timestamp)?;
+        if block.stationary {
+            let learning_rate = self.config.learning_rate as f64;
+            self.bias = Some(match self.bias {
+                Some(bias) => bias + (block.mean_gyroscope - bias) * learning_rate,
+                None => block.mean_gyroscope,
+            });
+        }
+        (self.detector.state() != previous)
+            .then(|| self.detector.state())
+            .flatten()
+    }
+
+    /// Process an event, then subtract the estimated bias from the gyroscope reading
+    /// if it is an [`GlassesEvent::AccGyro`] event. Returns the new motion state if
+    /// it changed.
+    pub fn compensate(&mut self, event: &mut GlassesEvent) -> Option<MotionState> {
+        let transition = self.update(event);
+        if let GlassesEvent::AccGyro { gyroscope, .. } = event {
+            if let Some(bias) = self.bias() {
+                *gyroscope -= bias;
+            }
+        }
+        transition
+    }
+
+    /// Current bias estimate, in rad/s
+    pub fn bias(&self) -> Option<Vector3<f32>> {
+        self.bias.map(|b| b.map(|c| c as f32))
+    }
+
+    /// Current motion state, see [`StationaryDetector::state`]
+    pub fn state(&self) -> Option<MotionState> {
+        self.detector.state()
+    }
+}
+
+impl Default for GyroBiasEstimator {
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// The `i`th 100Hz sample of upright glasses, with a biased and noisy gyroscope,
+    /// either still or rotating
+    pub(crate) fn imu_sample(i: u64, moving: bool, bias: Vector3<f32>) -> GlassesEvent {
+        let noise = ((i * 7919) % 13) as f32 / 13.0 - 0.5;
+        let rotation = if moving {
+            Vector3::new((i as f32 * 0.05).sin(), 0.3, 0.0)
+        } else {
+            Vector3::zeros()
+        };
+        GlassesEvent::AccGyro {
+            accelerometer: Vector3::new(0.0, 9.81, 0.0),
+            gyroscope: rotation + bias + Vector3::repeat(noise * 0.002),
+            timestamp: i * 10_000,
+        }
+    }
+
+    #[test]
+    fn test_bias_and_transitions() {
+        let bias = Vector3::new(0.003, -0.002, 0.001);
+        let mut estimator = GyroBiasEstimator::default();
+        let mut transitions = Vec::new();
+        // 5 seconds still, 5 seconds moving, 5 seconds still
+        for i in 0..1500u64 {
+            let mut event = imu_sample(i, (500..1000).contains(&i), bias);
+            if let Some(state) = estimator.compensate(&mut event) {
+                transitions.push(state);
+            }
+        }
+        assert_eq!(
+            transitions,
+            [
+                MotionState::Stationary,
+                MotionState::Moving,
+                MotionState::Stationary
+            ]
+        );
+        assert!((estimator.bias().unwrap() - bias).amax() < 2e-4);
+    }
+}
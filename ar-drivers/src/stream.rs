@@ -0,0 +1,220 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Async interface for glasses events. See [`GlassesStream`]
+//!
+//! Example usage (inside a tokio runtime):
+//! ```ignore
+//! let mut stream = GlassesStream::new(any_glasses()?, 64);
+//! while let Some(event) = stream.next().await {
+//!     match event? {
+//!         GlassesEvent::AccGyro { .. } => ...,
+//!         _ => {}
+//!     }
+//! }
+//! let glasses = stream.shutdown().await?;
+//! ```
+
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    thread::JoinHandle,
+};
+
+use futures_core::Stream;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{ARGlasses, Error, GlassesEvent, Result};
+
+/// A [`Stream`] of events from any glasses
+///
+/// Since all drivers use blocking IO, events are read on a dedicated thread, and
+/// passed through a bounded channel. If the consumer falls behind, the reader thread
+/// stops reading until there is room in the channel (the device itself may drop
+/// packets in the meantime).
+///
+/// [`Error::PacketTimeout`] and [`Error::ChecksumMismatch`] errors are passed along, and
+/// the stream continues after them.
+/// Any other error is considered fatal: it is the last item of the stream.
+///
+/// Dropping the stream stops the reader thread, and blocks until it finishes (at most
+/// the read timeout of the driver). Use [`GlassesStream::shutdown`] to wait for it
+/// asynchronously and get the glasses back.
+pub struct GlassesStream {
+    receiver: mpsc::Receiver<Result<GlassesEvent>>,
+    stop: Arc<AtomicBool>,
+    glasses: Option<oneshot::Receiver<Box<dyn ARGlasses>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GlassesStream {
+    /// Start reading events from `glasses`. At most `capacity` events are buffered.
+    /// Does not need to be called from inside a tokio runtime.
+    pub fn new(mut glasses: Box<dyn ARGlasses>, capacity: usize) -> Self {
+        let (event_sender, receiver) = mpsc::channel(capacity.max(1));
+        let (glasses_sender, glasses_receiver) = oneshot::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                let event = glasses.read_event();
+                let fatal = matches!(
+                    &event,
+                    Err(e) if !matches!(e, Error::PacketTimeout | Error::ChecksumMismatch)
+                );
+                if event_sender.blocking_send(event).is_err() || fatal {
+                    break;
+                }
+            }
+            // Nobody may be waiting for it, which is fine.
+            let _ = glasses_sender.send(glasses);
+        });
+        Self {
+            receiver,
+            stop,
+            glasses: Some(glasses_receiver),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop reading events, wait for the reader thread to finish, and return the glasses.
+    /// Events still in the buffer are discarded.
+    pub async fn shutdown(mut self) -> Result<Box<dyn ARGlasses>> {
+        self.stop();
+        // The thread is done once the glasses are sent back, no need to join it
+        self.thread = None;
+        self.glasses
+            .take()
+            .ok_or(Error::Other("Glasses stream already shut down"))?
+            .await
+            .map_err(|_| Error::Other("Glasses reader thread panicked"))
+    }
+}
+
+impl Stream for GlassesStream {
+    type Item = Result<GlassesEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl GlassesStream {
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        // Wakes up the reader thread if it is waiting for room in the channel
+        self.receiver.close();
+    }
+}
+
+impl Drop for GlassesStream {
+    fn drop(&mut self) {
+        self.stop();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "simulated"))]
+mod tests {
+    use std::{sync::atomic::AtomicUsize, time::Duration};
+
+    use super::*;
+    use crate::{
+        simulated::{SimulatedGlasses, SimulationConfig},
+        util::forward_glasses,
+    };
+
+    /// Counts the events read, and notes when the glasses are dropped
+    struct CountingGlasses {
+        glasses: SimulatedGlasses,
+        reads: Arc<AtomicUsize>,
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl ARGlasses for CountingGlasses {
+        fn read_event(&mut self) -> Result<GlassesEvent> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.glasses.read_event()
+        }
+
+        forward_glasses!(glasses);
+    }
+
+    impl Drop for CountingGlasses {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn stream(capacity: usize) -> (GlassesStream, Arc<AtomicUsize>, Arc<AtomicBool>) {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicBool::new(false));
+        let glasses = CountingGlasses {
+            glasses: SimulatedGlasses::new(SimulationConfig {
+                realtime: false,
+                ..Default::default()
+            }),
+            reads: reads.clone(),
+            dropped: dropped.clone(),
+        };
+        (
+            GlassesStream::new(Box::new(glasses), capacity),
+            reads,
+            dropped,
+        )
+    }
+
+    fn block_on<T>(future: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn test_yields_events() {
+        let (mut stream, _, _) = stream(16);
+        let events: Vec<_> = block_on(async {
+            let mut events = Vec::new();
+            while events.len() < 100 {
+                let event = std::future::poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await;
+                events.push(event.unwrap().unwrap());
+            }
+            events
+        });
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, GlassesEvent::AccGyro { .. })));
+        let glasses = block_on(stream.shutdown()).unwrap();
+        assert_eq!(glasses.name(), "Simulated glasses");
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let (stream, reads, _) = stream(8);
+        std::thread::sleep(Duration::from_millis(200));
+        // The channel is full, and the reader is blocked on the next event
+        assert!(reads.load(Ordering::Relaxed) <= 8 + 1);
+        drop(stream);
+    }
+
+    #[test]
+    fn test_drop_stops_reader() {
+        let (stream, reads, dropped) = stream(1);
+        while reads.load(Ordering::Relaxed) < 2 {
+            std::thread::yield_now();
+        }
+        drop(stream);
+        assert!(dropped.load(Ordering::Relaxed));
+        let reads_after_drop = reads.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(reads.load(Ordering::Relaxed), reads_after_drop);
+    }
+}
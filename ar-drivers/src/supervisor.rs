@@ -0,0 +1,484 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Automatic reconnection to glasses that were unplugged. See [`SupervisedGlasses`]
+
+use std::{
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use nalgebra::Isometry3;
+
+#[cfg(not(target_os = "android"))]
+use crate::GlassesSelector;
+use crate::{
+    recording::RecordingHeader, ARGlasses, CameraDescriptor, Capabilities, DisplayMatrices,
+    DisplayMode, DisplayModeInfo, Error, GlassesEvent, Result, Side,
+};
+
+/// Function used by [`SupervisedGlasses`] to connect to the glasses. On reconnection,
+/// it gets the serial number of the previously connected glasses.
+pub type Connector = Box<dyn FnMut(Option<&str>) -> Result<Box<dyn ARGlasses>> + Send>;
+
+/// Settings of [`SupervisedGlasses`]
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Delay between the disconnection and the first reconnection attempt
+    pub initial_delay: Duration,
+    /// The delay is doubled after every failed attempt, up to this value
+    pub max_delay: Duration,
+    /// Maximum time a single [`ARGlasses::read_event`] call waits while disconnected
+    pub poll_interval: Duration,
+    /// Number of consecutive [`Error::PacketTimeout`] errors after which the glasses
+    /// are considered disconnected. Zero disables this check.
+    pub timeout_limit: u32,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(5),
+            poll_interval: Duration::from_millis(250),
+            timeout_limit: 8,
+        }
+    }
+}
+
+/// Wrapper that survives the glasses being unplugged and plugged back in
+///
+/// Any error from the wrapped glasses' [`ARGlasses::read_event`] (other than
+/// [`Error::PacketTimeout`] and [`Error::ChecksumMismatch`]) is considered a disconnection, and is reported as a
+/// [`GlassesEvent::Disconnected`] event. After that, `read_event` tries to reconnect
+/// with exponential backoff (see [`ReconnectPolicy`]), and returns
+/// [`Error::PacketTimeout`] until it succeeds, so callers can keep looping. Only glasses
+/// with the same serial number are accepted. A successful reconnection is reported as
+/// a [`GlassesEvent::Connected`] event, after which normal events follow.
+///
+/// The display mode and brightness set through this wrapper are restored after
+/// reconnection. They can also be set while disconnected, in which case they are
+/// applied on reconnection.
+///
+/// Properties that do not need communication (name, FOV, display matrices, etc.)
+/// are queried once, at the first connection, and are available while disconnected.
+pub struct SupervisedGlasses {
+    connector: Connector,
+    policy: ReconnectPolicy,
+    glasses: Option<Box<dyn ARGlasses>>,
+    properties: RecordingHeader,
+    name: &'static str,
+    cameras: Vec<CameraDescriptor>,
+    display_mode: Option<RequestedDisplayMode>,
+    display_modes: Vec<DisplayModeInfo>,
+    capabilities: Capabilities,
+    brightness: Option<u8>,
+    brightness_range: Option<RangeInclusive<u8>>,
+    consecutive_timeouts: u32,
+    delay: Duration,
+    next_attempt: Instant,
+    /// Rejected packets of previous connections
+    rejected_packets: u64,
+}
+
+/// The last display mode set through [`SupervisedGlasses`], to be restored on reconnection
+#[derive(Clone, Copy)]
+enum RequestedDisplayMode {
+    Coarse(DisplayMode),
+    Exact(DisplayModeInfo),
+}
+
+impl SupervisedGlasses {
+    /// Connect to the glasses chosen by `selector`, and reconnect to the same glasses
+    /// (by serial number) after disconnection.
+    #[cfg(not(target_os = "android"))]
+    pub fn new(selector: GlassesSelector) -> Result<Self> {
+        Self::with_connector(
+            Box::new(move |serial| match serial {
+                Some(serial) => selector.clone().serial(serial).open(),
+                None => selector.open(),
+            }),
+            Default::default(),
+        )
+    }
+
+    /// Connect with a custom connector function. The first connection has to succeed.
+    pub fn with_connector(mut connector: Connector, policy: ReconnectPolicy) -> Result<Self> {
+        let mut glasses = connector(None)?;
+        let properties = RecordingHeader::from_glasses(glasses.as_mut())?;
+        Ok(Self {
+            name: glasses.name(),
+            cameras: glasses.cameras().unwrap_or_default(),
+            brightness_range: glasses.brightness_range().ok(),
+            display_modes: glasses.supported_display_modes(),
+            capabilities: glasses.capabilities(),
+            glasses: Some(glasses),
+            properties,
+            display_mode: None,
+            brightness: None,
+            consecutive_timeouts: 0,
+            delay: policy.initial_delay,
+            next_attempt: Instant::now(),
+            rejected_packets: 0,
+            connector,
+            policy,
+        })
+    }
+
+    /// Whether the glasses are currently connected
+    pub fn is_connected(&self) -> bool {
+        self.glasses.is_some()
+    }
+
+    fn disconnect(&mut self) -> GlassesEvent {
+        if let Some(glasses) = self.glasses.take() {
+            self.rejected_packets += glasses.rejected_packets();
+        }
+        self.consecutive_timeouts = 0;
+        self.delay = self.policy.initial_delay;
+        self.next_attempt = Instant::now() + self.delay;
+        GlassesEvent::Disconnected
+    }
+
+    fn reconnect(&mut self) -> Result<GlassesEvent> {
+        let now = Instant::now();
+        if now < self.next_attempt {
+            std::thread::sleep((self.next_attempt - now).min(self.policy.poll_interval));
+            if Instant::now() < self.next_attempt {
+                return Err(Error::PacketTimeout);
+            }
+        }
+        match self.try_connect() {
+            Ok(glasses) => {
+                self.glasses = Some(glasses);
+                Ok(GlassesEvent::Connected)
+            }
+            Err(_) => {
+                self.delay = (self.delay * 2).min(self.policy.max_delay);
+                self.next_attempt = Instant::now() + self.delay;
+                Err(Error::PacketTimeout)
+            }
+        }
+    }
+
+    fn try_connect(&mut self) -> Result<Box<dyn ARGlasses>> {
+        let mut glasses = (self.connector)(Some(&self.properties.serial))?;
+        if glasses.serial()? != self.properties.serial {
+            return Err(Error::NotFound);
+        }
+        match self.display_mode {
+            Some(RequestedDisplayMode::Coarse(display_mode)) => {
+                glasses.set_display_mode(display_mode)?
+            }
+            Some(RequestedDisplayMode::Exact(display_mode)) => {
+                glasses.set_display_mode_info(display_mode)?
+            }
+            None => (),
+        }
+        if let Some(brightness) = self.brightness {
+            glasses.set_brightness(brightness)?;
+        }
+        Ok(glasses)
+    }
+}
+
+impl ARGlasses for SupervisedGlasses {
+    fn serial(&mut self) -> Result<String> {
+        Ok(self.properties.serial.clone())
+    }
+
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        let Some(glasses) = &mut self.glasses else {
+            return self.reconnect();
+        };
+        match glasses.read_event() {
+            Ok(event) => {
+                self.consecutive_timeouts = 0;
+                Ok(event)
+            }
+            Err(Error::ChecksumMismatch) => Err(Error::ChecksumMismatch),
+            Err(Error::PacketTimeout) => {
+                self.consecutive_timeouts = self.consecutive_timeouts.saturating_add(1);
+                if self.consecutive_timeouts == self.policy.timeout_limit {
+                    Ok(self.disconnect())
+                } else {
+                    Err(Error::PacketTimeout)
+                }
+            }
+            Err(_) => Ok(self.disconnect()),
+        }
+    }
+
+    fn get_display_mode(&mut self) -> Result<DisplayMode> {
+        match &mut self.glasses {
+            Some(glasses) => glasses.get_display_mode(),
+            None => match self.display_mode {
+                Some(RequestedDisplayMode::Coarse(display_mode)) => Ok(display_mode),
+                Some(RequestedDisplayMode::Exact(display_mode)) => Ok(display_mode.display_mode()),
+                None => Err(Error::NotFound),
+            },
+        }
+    }
+
+    fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
+        if let Some(glasses) = &mut self.glasses {
+            glasses.set_display_mode(display_mode)?;
+        }
+        self.display_mode = Some(RequestedDisplayMode::Coarse(display_mode));
+        Ok(())
+    }
+
+    fn supported_display_modes(&self) -> Vec<DisplayModeInfo> {
+        self.display_modes.clone()
+    }
+
+    fn get_display_mode_info(&mut self) -> Result<DisplayModeInfo> {
+        match &mut self.glasses {
+            Some(glasses) => glasses.get_display_mode_info(),
+            None => match self.display_mode {
+                Some(RequestedDisplayMode::Exact(display_mode)) => Ok(display_mode),
+                _ => Err(Error::NotFound),
+            },
+        }
+    }
+
+    fn set_display_mode_info(&mut self, display_mode: DisplayModeInfo) -> Result<()> {
+        if let Some(glasses) = &mut self.glasses {
+            glasses.set_display_mode_info(display_mode)?;
+        } else if !self.display_modes.contains(&display_mode) {
+            return Err(Error::Other("Display mode not supported"));
+        }
+        self.display_mode = Some(RequestedDisplayMode::Exact(display_mode));
+        Ok(())
+    }
+
+    fn get_brightness(&mut self) -> Result<u8> {
+        match &mut self.glasses {
+            Some(glasses) => glasses.get_brightness(),
+            None => self.brightness.ok_or(Error::NotFound),
+        }
+    }
+
+    fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+        if let Some(glasses) = &mut self.glasses {
+            glasses.set_brightness(brightness)?;
+        } else if !self.brightness_range()?.contains(&brightness) {
+            return Err(Error::Other("Brightness out of range"));
+        }
+        self.brightness = Some(brightness);
+        Ok(())
+    }
+
+    fn brightness_range(&self) -> Result<RangeInclusive<u8>> {
+        self.brightness_range.clone().ok_or(Error::NotImplemented)
+    }
+
+    fn display_fov(&self) -> f32 {
+        self.properties.display_fov
+    }
+
+    fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        self.properties.imu_to_display_matrix(side, ipd)
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn cameras(&self) -> Result<Vec<CameraDescriptor>> {
+        Ok(self.cameras.clone())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+
+    fn display_matrices(&self) -> Result<(DisplayMatrices, DisplayMatrices)> {
+        self.properties
+            .display_matrices
+            .clone()
+            .ok_or(Error::NotImplemented)
+    }
+
+    fn display_delay(&self) -> u64 {
+        self.properties.display_delay
+    }
+
+    fn rejected_packets(&self) -> u64 {
+        self.rejected_packets + self.glasses.as_ref().map_or(0, |g| g.rejected_packets())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    /// Fake transport: a shared "cable" that the test can unplug and plug back in
+    #[derive(Clone)]
+    struct Cable {
+        plugged: Arc<AtomicBool>,
+        serial: Arc<Mutex<String>>,
+        display_mode: Arc<Mutex<DisplayMode>>,
+    }
+
+    struct FakeGlasses {
+        cable: Cable,
+        timestamp: u64,
+    }
+
+    impl Cable {
+        fn new() -> Self {
+            Self {
+                plugged: Arc::new(AtomicBool::new(true)),
+                serial: Arc::new(Mutex::new("FAKE1".into())),
+                display_mode: Arc::new(Mutex::new(DisplayMode::SameOnBoth)),
+            }
+        }
+
+        fn set_plugged(&self, plugged: bool) {
+            self.plugged.store(plugged, Ordering::SeqCst);
+            if !plugged {
+                // Glasses forget their settings when unplugged
+                *self.display_mode.lock().unwrap() = DisplayMode::SameOnBoth;
+            }
+        }
+
+        fn connector(&self) -> Connector {
+            let cable = self.clone();
+            Box::new(move |_| {
+                if cable.plugged.load(Ordering::SeqCst) {
+                    Ok(Box::new(FakeGlasses {
+                        cable: cable.clone(),
+                        timestamp: 0,
+                    }))
+                } else {
+                    Err(Error::NotFound)
+                }
+            })
+        }
+    }
+
+    impl ARGlasses for FakeGlasses {
+        fn serial(&mut self) -> Result<String> {
+            Ok(self.cable.serial.lock().unwrap().clone())
+        }
+
+        fn read_event(&mut self) -> Result<GlassesEvent> {
+            if !self.cable.plugged.load(Ordering::SeqCst) {
+                return Err(Error::Other("Device disconnected"));
+            }
+            self.timestamp += 1000;
+            Ok(GlassesEvent::AccGyro {
+                accelerometer: Vector3::new(0.0, 9.81, 0.0),
+                gyroscope: Vector3::zeros(),
+                timestamp: self.timestamp,
+            })
+        }
+
+        fn get_display_mode(&mut self) -> Result<DisplayMode> {
+            Ok(*self.cable.display_mode.lock().unwrap())
+        }
+
+        fn set_display_mode(&mut self, display_mode: DisplayMode) -> Result<()> {
+            *self.cable.display_mode.lock().unwrap() = display_mode;
+            Ok(())
+        }
+
+        fn display_fov(&self) -> f32 {
+            0.4
+        }
+
+        fn imu_to_display_matrix(&self, _side: Side, _ipd: f32) -> Isometry3<f64> {
+            Isometry3::identity()
+        }
+
+        fn name(&self) -> &'static str {
+            "Fake glasses"
+        }
+
+        fn display_delay(&self) -> u64 {
+            0
+        }
+    }
+
+    fn fast_policy() -> ReconnectPolicy {
+        ReconnectPolicy {
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::from_millis(1),
+            poll_interval: Duration::from_millis(1),
+            timeout_limit: 8,
+        }
+    }
+
+    /// Read until something other than a timeout comes
+    fn next_event(glasses: &mut SupervisedGlasses) -> GlassesEvent {
+        for _ in 0..100 {
+            match glasses.read_event() {
+                Err(Error::PacketTimeout) => continue,
+                event => return event.unwrap(),
+            }
+        }
+        panic!("No event received");
+    }
+
+    #[test]
+    fn test_reconnect_restores_display_mode() {
+        let cable = Cable::new();
+        let mut glasses =
+            SupervisedGlasses::with_connector(cable.connector(), fast_policy()).unwrap();
+        glasses.set_display_mode(DisplayMode::Stereo).unwrap();
+        assert!(matches!(
+            glasses.read_event(),
+            Ok(GlassesEvent::AccGyro { .. })
+        ));
+
+        cable.set_plugged(false);
+        assert!(matches!(
+            glasses.read_event(),
+            Ok(GlassesEvent::Disconnected)
+        ));
+        assert!(!glasses.is_connected());
+        assert!(matches!(glasses.read_event(), Err(Error::PacketTimeout)));
+        assert_eq!(glasses.name(), "Fake glasses");
+        assert_eq!(glasses.serial().unwrap(), "FAKE1");
+
+        cable.set_plugged(true);
+        assert!(matches!(next_event(&mut glasses), GlassesEvent::Connected));
+        assert!(glasses.is_connected());
+        assert_eq!(*cable.display_mode.lock().unwrap(), DisplayMode::Stereo);
+        assert!(matches!(
+            next_event(&mut glasses),
+            GlassesEvent::AccGyro { .. }
+        ));
+    }
+
+    #[test]
+    fn test_different_serial_is_rejected() {
+        let cable = Cable::new();
+        let mut glasses =
+            SupervisedGlasses::with_connector(cable.connector(), fast_policy()).unwrap();
+        cable.set_plugged(false);
+        assert!(matches!(
+            glasses.read_event(),
+            Ok(GlassesEvent::Disconnected)
+        ));
+
+        *cable.serial.lock().unwrap() = "FAKE2".into();
+        cable.set_plugged(true);
+        for _ in 0..20 {
+            assert!(matches!(glasses.read_event(), Err(Error::PacketTimeout)));
+        }
+        assert!(!glasses.is_connected());
+
+        *cable.serial.lock().unwrap() = "FAKE1".into();
+        assert!(matches!(next_event(&mut glasses), GlassesEvent::Connected));
+    }
+}
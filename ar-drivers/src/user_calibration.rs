@@ -0,0 +1,355 @@
+// Copyright (C) 2023, Alex Badics
+// This file is part of ar-drivers-rs
+// Licensed under the MIT license. See LICENSE file in the project root for details.
+
+//! Corrections and preferences of the user, stored per device. See [`UserCalibration`]
+//! and [`CalibratedGlasses`]
+//!
+//! The drivers only apply the factory calibration. Everything the user calibrates on
+//! top of that (e.g. with [`crate::stationary::GyroBiasEstimator`] or
+//! [`crate::mag_calibration::MagCalibrator`]) can be saved here, keyed by the serial
+//! number of the glasses, and is applied automatically by [`crate::open_glasses`] and
+//! [`crate::GlassesSelector`] (see [`crate::OpenOptions::user_calibration`]).
+
+use std::path::{Path, PathBuf};
+
+use nalgebra::{Isometry3, Vector3};
+
+use crate::{
+    config_file::{device_file, ConfigFile},
+    mag_calibration::MagCalibration,
+    util::forward_glasses,
+    ARGlasses, DisplayLayout, DisplayModeInfo, Error, GlassesEvent, Result, Side,
+};
+
+/// Accelerometer correction: `(raw - offset) * scale`, per axis
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccelerometerCalibration {
+    /// Subtracted from the readings, in m/s^2
+    pub offset: Vector3<f32>,
+    /// Multiplier of each axis, applied after the offset
+    pub scale: Vector3<f32>,
+}
+
+impl AccelerometerCalibration {
+    /// Correct a reading
+    pub fn apply(&self, accelerometer: &Vector3<f32>) -> Vector3<f32> {
+        (accelerometer - self.offset).component_mul(&self.scale)
+    }
+}
+
+impl Default for AccelerometerCalibration {
+    fn default() -> Self {
+        Self {
+            offset: Vector3::zeros(),
+            scale: Vector3::repeat(1.0),
+        }
+    }
+}
+
+/// Calibration and preferences of the user for a specific device. Everything is
+/// optional: missing values leave the readings and the settings of the glasses as
+/// they are. All vectors are in the (RUB) frame of [`GlassesEvent`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserCalibration {
+    /// Gyroscope bias remaining after the factory calibration, in rad/sec.
+    /// Subtracted from the readings.
+    pub gyro_bias: Option<Vector3<f32>>,
+    /// Accelerometer offset and scale, on top of the factory calibration
+    pub accelerometer: Option<AccelerometerCalibration>,
+    /// Magnetometer hard and soft iron correction
+    pub magnetometer: Option<MagCalibration>,
+    /// Display mode to set when the glasses are opened
+    pub display_mode: Option<DisplayModeInfo>,
+    /// Interpupillary distance of the user, in meters. [`CalibratedGlasses`] uses it
+    /// instead of the `ipd` argument of [`ARGlasses::imu_to_display_matrix`].
+    pub ipd: Option<f32>,
+}
+
+impl UserCalibration {
+    const INVALID_FILE: &str = "Invalid user calibration file";
+
+    /// The default location of the calibration file of the glasses with this serial
+    /// number, in the same directory as [`MagCalibration::default_path`]
+    pub fn default_path(serial: &str) -> Option<PathBuf> {
+        device_file("calibration", serial)
+    }
+
+    /// Load the calibration of the glasses with this serial number from the default
+    /// location. If there is no magnetometer calibration in it, the one saved by
+    /// [`MagCalibration::save`] to [`MagCalibration::default_path`] is used.
+    /// `None` if there is no calibration at all.
+    pub fn load_for(serial: &str) -> Result<Option<Self>> {
+        let mut calibration = match Self::default_path(serial) {
+            Some(path) if path.exists() => Some(Self::load(&path)?),
+            _ => None,
+        };
+        if calibration
+            .as_ref()
+            .is_none_or(|c| c.magnetometer.is_none())
+        {
+            if let Some(path) = MagCalibration::default_path(serial).filter(|p| p.exists()) {
+                calibration
+                    .get_or_insert_with(Default::default)
+                    .magnetometer = Some(MagCalibration::load(&path)?);
+            }
+        }
+        Ok(calibration)
+    }
+
+    /// Save to the default location of the glasses with this serial number
+    pub fn save_for(&self, serial: &str) -> Result<()> {
+        let path = Self::default_path(serial)
+            .ok_or(Error::Other("Could not determine the config directory"))?;
+        self.save(&path)
+    }
+
+    /// Save in the same text format as [`MagCalibration::save`],
+    /// creating the directory if needed
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = ConfigFile::new();
+        if let Some(gyro_bias) = &self.gyro_bias {
+            file.set_values("gyro_bias", gyro_bias.as_slice());
+        }
+        if let Some(accelerometer) = &self.accelerometer {
+            file.set_values("accelerometer_offset", accelerometer.offset.as_slice());
+            file.set_values("accelerometer_scale", accelerometer.scale.as_slice());
+        }
+        if let Some(magnetometer) = &self.magnetometer {
+            magnetometer.write_to(&mut file, "mag_");
+        }
+        if let Some(mode) = &self.display_mode {
+            file.set(
+                "display_mode",
+                format!(
+                    "{:?} {} {} {}",
+                    mode.layout, mode.resolution.0, mode.resolution.1, mode.refresh_rate
+                ),
+            );
+        }
+        if let Some(ipd) = self.ipd {
+            file.set("ipd", ipd);
+        }
+        file.save(path)
+    }
+
+    /// Load a calibration saved with [`UserCalibration::save`]
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = ConfigFile::load(path)?;
+        let vector = |key: &str| -> Result<Option<Vector3<f32>>> {
+            Ok(file
+                .values(key, 3, Self::INVALID_FILE)?
+                .map(|v| Vector3::from_column_slice(&v)))
+        };
+        let accelerometer = match (
+            vector("accelerometer_offset")?,
+            vector("accelerometer_scale")?,
+        ) {
+            (None, None) => None,
+            (offset, scale) => Some(AccelerometerCalibration {
+                offset: offset.unwrap_or_else(Vector3::zeros),
+                scale: scale.unwrap_or_else(|| Vector3::repeat(1.0)),
+            }),
+        };
+        Ok(Self {
+            gyro_bias: vector("gyro_bias")?,
+            accelerometer,
+            magnetometer: MagCalibration::read_from(&file, "mag_")?,
+            display_mode: file
+                .get("display_mode")
+                .map(|value| {
+                    Self::parse_display_mode(value).ok_or(Error::Other(Self::INVALID_FILE))
+                })
+                .transpose()?,
+            ipd: file.values("ipd", 1, Self::INVALID_FILE)?.map(|v| v[0]),
+        })
+    }
+
+    fn parse_display_mode(value: &str) -> Option<DisplayModeInfo> {
+        let mut parts = value.split_whitespace();
+        let layout = match parts.next()? {
+            "Mirror" => DisplayLayout::Mirror,
+            "SideBySide" => DisplayLayout::SideBySide,
+            "HalfSideBySide" => DisplayLayout::HalfSideBySide,
+            _ => return None,
+        };
+        let mut number = || parts.next()?.parse().ok();
+        Some(DisplayModeInfo::new(
+            layout,
+            number()?,
+            number()?,
+            number()?,
+        ))
+    }
+
+    /// Correct the readings of the event, if it has any
+    pub fn compensate(&self, event: &mut GlassesEvent) {
+        match event {
+            GlassesEvent::AccGyro {
+                accelerometer,
+                gyroscope,
+                ..
+            } => {
+                if let Some(calibration) = &self.accelerometer {
+                    *accelerometer = calibration.apply(accelerometer);
+                }
+                if let Some(bias) = &self.gyro_bias {
+                    *gyroscope -= bias;
+                }
+            }
+            GlassesEvent::Magnetometer { magnetometer, .. } => {
+                if let Some(calibration) = &self.magnetometer {
+                    *magnetometer = calibration.apply(magnetometer);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Glasses wrapper that applies a [`UserCalibration`] to the events and the display
+/// matrices
+pub struct CalibratedGlasses {
+    glasses: Box<dyn ARGlasses>,
+    calibration: UserCalibration,
+}
+
+impl CalibratedGlasses {
+    /// Wrap already opened glasses. The preferred display mode is set right away;
+    /// failing to do so (e.g. because the glasses do not support it) is not an error.
+    pub fn new(mut glasses: Box<dyn ARGlasses>, calibration: UserCalibration) -> Self {
+        if let Some(mode) = calibration.display_mode {
+            let _ = glasses.set_display_mode_info(mode);
+        }
+        Self {
+            glasses,
+            calibration,
+        }
+    }
+
+    /// The calibration applied to the events
+    pub fn calibration(&self) -> &UserCalibration {
+        &self.calibration
+    }
+
+    /// Replace the calibration applied to the events. It is not saved.
+    pub fn set_calibration(&mut self, calibration: UserCalibration) {
+        self.calibration = calibration;
+    }
+
+    /// Get the wrapped glasses back
+    pub fn into_inner(self) -> Box<dyn ARGlasses> {
+        self.glasses
+    }
+}
+
+/// Wrap the glasses in a [`CalibratedGlasses`] if there is a saved calibration for
+/// them. Unreadable calibration files are ignored, so that they do not prevent
+/// using the glasses.
+#[cfg(not(target_os = "android"))]
+pub(crate) fn apply_saved(
+    mut glasses: Box<dyn ARGlasses>,
+    serial: Option<&str>,
+) -> Box<dyn ARGlasses> {
+    let serial = match serial {
+        Some(serial) => serial.to_string(),
+        None => match glasses.serial() {
+            Ok(serial) => serial,
+            Err(_) => return glasses,
+        },
+    };
+    match UserCalibration::load_for(&serial) {
+        Ok(Some(calibration)) => Box::new(CalibratedGlasses::new(glasses, calibration)),
+        _ => glasses,
+    }
+}
+
+impl ARGlasses for CalibratedGlasses {
+    fn read_event(&mut self) -> Result<GlassesEvent> {
+        let mut event = self.glasses.read_event()?;
+        self.calibration.compensate(&mut event);
+        Ok(event)
+    }
+
+    /// Uses the saved [`UserCalibration::ipd`] instead of `ipd`, if there is one
+    fn imu_to_display_matrix(&self, side: Side, ipd: f32) -> Isometry3<f64> {
+        self.glasses
+            .imu_to_display_matrix(side, self.calibration.ipd.unwrap_or(ipd))
+    }
+
+    forward_glasses!(
+        glasses: serial,
+        get_display_mode,
+        set_display_mode,
+        supported_display_modes,
+        get_display_mode_info,
+        set_display_mode_info,
+        get_brightness,
+        set_brightness,
+        brightness_range,
+        display_fov,
+        name,
+        cameras,
+        display_matrices,
+        display_delay,
+        rejected_packets,
+        capabilities
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Matrix3;
+
+    use super::*;
+
+    #[test]
+    fn test_save_load_compensate() {
+        let calibration = UserCalibration {
+            gyro_bias: Some(Vector3::new(0.001, -0.002, 0.0005)),
+            accelerometer: Some(AccelerometerCalibration {
+                offset: Vector3::new(0.1, 0.0, -0.05),
+                scale: Vector3::new(1.01, 0.99, 1.0),
+            }),
+            magnetometer: Some(MagCalibration {
+                hard_iron: Vector3::new(10.0, -5.0, 3.0),
+                soft_iron: Matrix3::new(1.1, 0.1, 0.0, 0.1, 0.9, 0.0, 0.0, 0.0, 1.0),
+                field_strength: 45.0,
+                residual: 0.01,
+                coverage: 1.0,
+            }),
+            display_mode: Some(DisplayModeInfo::new(
+                DisplayLayout::SideBySide,
+                1920,
+                1080,
+                90,
+            )),
+            ipd: Some(0.063),
+        };
+        let path = std::env::temp_dir().join("ar_drivers_test_user_calibration.txt");
+        calibration.save(&path).unwrap();
+        let loaded = UserCalibration::load(&path).unwrap();
+        UserCalibration::default().save(&path).unwrap();
+        let empty = UserCalibration::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, calibration);
+        assert_eq!(empty, UserCalibration::default());
+
+        let mut event = GlassesEvent::AccGyro {
+            accelerometer: Vector3::new(0.1, 9.81, -0.05),
+            gyroscope: Vector3::new(0.001, -0.002, 0.0005),
+            timestamp: 0,
+        };
+        calibration.compensate(&mut event);
+        let GlassesEvent::AccGyro {
+            accelerometer,
+            gyroscope,
+            ..
+        } = event
+        else {
+            unreachable!()
+        };
+        assert_eq!(gyroscope, Vector3::zeros());
+        assert!((accelerometer - Vector3::new(0.0, 9.81 * 0.99, 0.0)).norm() < 1e-5);
+    }
+}
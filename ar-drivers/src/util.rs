@@ -7,7 +7,7 @@
 use rusb::{Device, DeviceHandle, DeviceList, GlobalContext};
 
 #[allow(unused_imports)]
-use crate::{Error, Result};
+use crate::{DisplayModeInfo, Error, Result};
 
 #[cfg(feature = "rusb")]
 #[cfg(not(target_os = "android"))]
@@ -22,6 +22,25 @@ pub fn get_device_vid_pid(vid: u16, pid: u16) -> Result<Device<GlobalContext>> {
     Err(Error::NotFound)
 }
 
+/// Location of a USB device in the form `bus:address`, used in
+/// [`crate::GlassesDescriptor::bus_path`]
+#[cfg(feature = "rusb")]
+#[cfg(not(target_os = "android"))]
+pub fn usb_bus_path(device: &Device<GlobalContext>) -> String {
+    format!("{:03}:{:03}", device.bus_number(), device.address())
+}
+
+#[cfg(feature = "rusb")]
+#[cfg(not(target_os = "android"))]
+pub fn get_device_by_bus_path(bus_path: &str) -> Result<Device<GlobalContext>> {
+    for device in DeviceList::new()?.iter() {
+        if usb_bus_path(&device) == bus_path {
+            return Ok(device);
+        }
+    }
+    Err(Error::NotFound)
+}
+
 #[cfg(feature = "rusb")]
 pub fn get_interface_for_endpoint(
     device: &Device<GlobalContext>,
@@ -91,3 +110,154 @@ pub(crate) fn crc32_adler(buf: &[u8]) -> u32 {
 
     r ^ 0xffffffffu32
 }
+
+/// Look up the [`DisplayModeInfo`] of a firmware display mode ID
+#[cfg(feature = "nreal")]
+pub(crate) fn display_mode_info_by_id(
+    modes: &[(u8, DisplayModeInfo)],
+    id: u8,
+) -> Result<DisplayModeInfo> {
+    modes
+        .iter()
+        .find(|(mode_id, _)| *mode_id == id)
+        .map(|(_, mode)| *mode)
+        .ok_or(Error::Other("Unknown display mode"))
+}
+
+/// Look up the firmware ID of the first mode matching `predicate`. `modes` should only
+/// contain the supported modes, in order of preference.
+#[cfg(feature = "nreal")]
+pub(crate) fn display_mode_id(
+    modes: &[(u8, DisplayModeInfo)],
+    predicate: impl Fn(&DisplayModeInfo) -> bool,
+) -> Result<u8> {
+    modes
+        .iter()
+        .find(|(_, mode)| predicate(mode))
+        .map(|(id, _)| *id)
+        .ok_or(Error::Other("Display mode not supported"))
+}
+
+/// Implement [`crate::ARGlasses`] methods of a wrapper by calling the same method of
+/// its `$field`. Without a method list, everything except `read_event` is forwarded.
+macro_rules! forward_glasses {
+    ($field:ident) => {
+        $crate::util::forward_glasses!(
+            $field: serial,
+            get_display_mode,
+            set_display_mode,
+            supported_display_modes,
+            get_display_mode_info,
+            set_display_mode_info,
+            get_brightness,
+            set_brightness,
+            brightness_range,
+            display_fov,
+            imu_to_display_matrix,
+            name,
+            cameras,
+            display_matrices,
+            display_delay,
+            rejected_packets,
+            capabilities
+        );
+    };
+    ($field:ident: $($method:ident),+) => {
+        $($crate::util::forward_glasses!(@$field $method);)+
+    };
+    (@$field:ident serial) => {
+        fn serial(&mut self) -> $crate::Result<String> {
+            self.$field.serial()
+        }
+    };
+    (@$field:ident get_display_mode) => {
+        fn get_display_mode(&mut self) -> $crate::Result<$crate::DisplayMode> {
+            self.$field.get_display_mode()
+        }
+    };
+    (@$field:ident set_display_mode) => {
+        fn set_display_mode(&mut self, display_mode: $crate::DisplayMode) -> $crate::Result<()> {
+            self.$field.set_display_mode(display_mode)
+        }
+    };
+    (@$field:ident supported_display_modes) => {
+        fn supported_display_modes(&self) -> Vec<$crate::DisplayModeInfo> {
+            self.$field.supported_display_modes()
+        }
+    };
+    (@$field:ident get_display_mode_info) => {
+        fn get_display_mode_info(&mut self) -> $crate::Result<$crate::DisplayModeInfo> {
+            self.$field.get_display_mode_info()
+        }
+    };
+    (@$field:ident set_display_mode_info) => {
+        fn set_display_mode_info(
+            &mut self,
+            display_mode: $crate::DisplayModeInfo,
+        ) -> $crate::Result<()> {
+            self.$field.set_display_mode_info(display_mode)
+        }
+    };
+    (@$field:ident get_brightness) => {
+        fn get_brightness(&mut self) -> $crate::Result<u8> {
+            self.$field.get_brightness()
+        }
+    };
+    (@$field:ident set_brightness) => {
+        fn set_brightness(&mut self, brightness: u8) -> $crate::Result<()> {
+            self.$field.set_brightness(brightness)
+        }
+    };
+    (@$field:ident brightness_range) => {
+        fn brightness_range(&self) -> $crate::Result<std::ops::RangeInclusive<u8>> {
+            self.$field.brightness_range()
+        }
+    };
+    (@$field:ident display_fov) => {
+        fn display_fov(&self) -> f32 {
+            self.$field.display_fov()
+        }
+    };
+    (@$field:ident imu_to_display_matrix) => {
+        fn imu_to_display_matrix(
+            &self,
+            side: $crate::Side,
+            ipd: f32,
+        ) -> nalgebra::Isometry3<f64> {
+            self.$field.imu_to_display_matrix(side, ipd)
+        }
+    };
+    (@$field:ident name) => {
+        fn name(&self) -> &'static str {
+            self.$field.name()
+        }
+    };
+    (@$field:ident cameras) => {
+        fn cameras(&self) -> $crate::Result<Vec<$crate::CameraDescriptor>> {
+            self.$field.cameras()
+        }
+    };
+    (@$field:ident display_matrices) => {
+        fn display_matrices(
+            &self,
+        ) -> $crate::Result<($crate::DisplayMatrices, $crate::DisplayMatrices)> {
+            self.$field.display_matrices()
+        }
+    };
+    (@$field:ident display_delay) => {
+        fn display_delay(&self) -> u64 {
+            self.$field.display_delay()
+        }
+    };
+    (@$field:ident rejected_packets) => {
+        fn rejected_packets(&self) -> u64 {
+            self.$field.rejected_packets()
+        }
+    };
+    (@$field:ident capabilities) => {
+        fn capabilities(&self) -> $crate::Capabilities {
+            self.$field.capabilities()
+        }
+    };
+}
+pub(crate) use forward_glasses;
@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 
-use ar_drivers::{any_glasses, GlassesEvent};
+use ar_drivers::{supervisor::SupervisedGlasses, ARGlasses, Error, GlassesEvent, GlassesSelector};
 
 use bevy::prelude::*;
 use dcmimu::DCMIMU;
@@ -33,13 +33,19 @@ impl Plugin for HmdPlugin {
 /// 2. Continuously reads accelerometer and gyroscope data from the glasses
 /// 3. Updates the shared DCMIMU state with new motion data
 /// 4. Calculates time delta between measurements for accurate integration
+/// 5. Keeps running through the glasses being unplugged and plugged back in
 ///
 /// The motion data is used to update camera orientation in the main rendering thread.
 fn start_tracking(imu_store: Res<ImuStore>) {
     let shared_dcmimu_clone = Arc::clone(&imu_store.dcmimu);
 
     std::thread::spawn(move || {
-        let mut glasses = any_glasses().unwrap();
+        let mut glasses = loop {
+            match SupervisedGlasses::new(GlassesSelector::new()) {
+                Ok(glasses) => break glasses,
+                Err(_) => std::thread::sleep(std::time::Duration::from_secs(1)),
+            }
+        };
         // println!("Got glasses, serial={}", glasses.serial().unwrap());
         let mut last_timestamp: Option<u64> = None;
 
@@ -48,11 +54,23 @@ fn start_tracking(imu_store: Res<ImuStore>) {
         // let mut loop_counter = 0;
 
         loop {
+            let event = match glasses.read_event() {
+                Ok(event) => event,
+                // Also returned while waiting for the glasses to be plugged back in
+                Err(Error::PacketTimeout) => continue,
+                // Corrupted packet, already dropped by the driver
+                Err(Error::ChecksumMismatch) => continue,
+                Err(e) => panic!("Glasses error: {e}"),
+            };
+            if let GlassesEvent::Disconnected = event {
+                // Device timestamps restart after reconnection
+                last_timestamp = None;
+            }
             if let GlassesEvent::AccGyro {
                 accelerometer,
                 gyroscope,
                 timestamp,
-            } = glasses.read_event().unwrap()
+            } = event
             {
                 if let Some(last_timestamp) = last_timestamp {
                     let dt = (timestamp - last_timestamp) as f32 / 1_000_000.0; // in seconds